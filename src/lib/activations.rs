@@ -1,16 +1,806 @@
-use std::f64::consts::E;
+use super::{initializers::Initializer, tensor::Tensor};
+use std::f64::consts::{E, PI, SQRT_2};
 
-#[derive(Clone)]
+//Elementwise nonlinearity. Layers keep both the input they passed to forward and the output
+//they got back, so backward can use whichever one its derivative is cheaper in.
+pub trait Activation {
+    fn forward(&self, input: &Tensor) -> Tensor;
 
-pub struct Activation<'a> {
-    // lifetime of struct is 'a
-    pub function: &'a dyn Fn(f64) -> f64,
-    pub derivative: &'a dyn Fn(f64) -> f64,
-    pub name: &'a str,
+    //Gradient with respect to the input, given the gradient with respect to the output.
+    //Activations with parameters also store their parameter gradients here.
+    fn backward(&mut self, input: &Tensor, output: &Tensor, grad_output: &Tensor) -> Tensor;
+
+    //Learnable values such as PReLU slopes, trained alongside the owning layer's weights
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        Vec::new()
+    }
+
+    //Gradients computed by the last backward call, in the same order as parameters()
+    fn gradients(&self) -> Vec<&Tensor> {
+        Vec::new()
+    }
+
+    //Weight initializer suited to a layer followed by this activation
+    fn default_initializer(&self) -> Initializer {
+        Initializer::XavierUniform
+    }
+
+    fn name(&self) -> &str;
 }
+
 //Sigmoid Function
-pub const SIGMOID: Activation = Activation {
-    function: &|x| 1.0 / (1.0 + E.powf(-x)), //function of sigmoid
-    derivative: &|x| x * (1.0 - x),          //derivative of sigmoid
-    name: "Sigmoid",                         //Sigmoid
-};
+pub struct Sigmoid;
+pub const SIGMOID: Sigmoid = Sigmoid;
+
+impl Activation for Sigmoid {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, sigmoid) //function of sigmoid
+    }
+
+    fn backward(&mut self, _input: &Tensor, output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(output, grad_output, |y| y * (1.0 - y)) //derivative of sigmoid, from its output
+    }
+
+    fn name(&self) -> &str {
+        "Sigmoid"
+    }
+}
+
+pub struct Tanh;
+pub const TANH: Tanh = Tanh;
+
+impl Activation for Tanh {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, f64::tanh)
+    }
+
+    fn backward(&mut self, _input: &Tensor, output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(output, grad_output, |y| 1.0 - y * y)
+    }
+
+    fn name(&self) -> &str {
+        "Tanh"
+    }
+}
+
+pub struct Identity;
+pub const IDENTITY: Identity = Identity;
+
+impl Activation for Identity {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        input.clone()
+    }
+
+    fn backward(&mut self, _input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        grad_output.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Identity"
+    }
+}
+
+//--------------------------------------------------------------Rectifiers---------------------------------------------------------------------
+
+pub struct ReLU;
+pub const RELU: ReLU = ReLU;
+
+impl Activation for ReLU {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x.max(0.0))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| if x > 0.0 { 1.0 } else { 0.0 })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "ReLU"
+    }
+}
+
+//Small slope for negative inputs so units never stop learning entirely
+pub struct LeakyReLU {
+    pub slope: f64,
+}
+pub const LEAKY_RELU: LeakyReLU = LeakyReLU { slope: 0.01 };
+
+impl Activation for LeakyReLU {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| if x > 0.0 { x } else { self.slope * x })
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(
+            input,
+            grad_output,
+            |x| if x > 0.0 { 1.0 } else { self.slope },
+        )
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "LeakyReLU"
+    }
+}
+
+pub struct Elu {
+    pub alpha: f64,
+}
+pub const ELU: Elu = Elu { alpha: 1.0 };
+
+impl Activation for Elu {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| if x > 0.0 { x } else { self.alpha * x.exp_m1() })
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            if x > 0.0 {
+                1.0
+            } else {
+                self.alpha * x.exp()
+            }
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "ELU"
+    }
+}
+
+//Self normalising ELU, pair with LeCun normal initialisation
+pub struct Selu;
+pub const SELU: Selu = Selu;
+
+const SELU_ALPHA: f64 = 1.673_263_242_354_377_3;
+const SELU_SCALE: f64 = 1.050_700_987_355_480_5;
+
+impl Activation for Selu {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| {
+            SELU_SCALE * if x > 0.0 { x } else { SELU_ALPHA * x.exp_m1() }
+        })
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            SELU_SCALE * if x > 0.0 { 1.0 } else { SELU_ALPHA * x.exp() }
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::LeCunNormal
+    }
+
+    fn name(&self) -> &str {
+        "SELU"
+    }
+}
+
+//--------------------------------------------------------------Smooth Gates---------------------------------------------------------------------
+
+//x * P(X <= x) for a standard normal X
+pub struct Gelu;
+pub const GELU: Gelu = Gelu;
+
+impl Activation for Gelu {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x * normal_cdf(x))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            normal_cdf(x) + x * (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "GELU"
+    }
+}
+
+//The tanh approximation of GELU used by BERT and GPT-2
+pub struct GeluTanh;
+pub const GELU_TANH: GeluTanh = GeluTanh;
+
+impl Activation for GeluTanh {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| 0.5 * x * (1.0 + gelu_tanh_inner(x).tanh()))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            let t = gelu_tanh_inner(x).tanh();
+            let inner_derivative = (2.0 / PI).sqrt() * (1.0 + 3.0 * 0.044715 * x * x);
+            0.5 * (1.0 + t) + 0.5 * x * (1.0 - t * t) * inner_derivative
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "GELUTanh"
+    }
+}
+
+pub struct SiLU;
+pub const SILU: SiLU = SiLU;
+//Swish with beta = 1 is SiLU
+pub const SWISH: SiLU = SiLU;
+
+impl Activation for SiLU {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x * sigmoid(x))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            let s = sigmoid(x);
+            s * (1.0 + x * (1.0 - s))
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "SiLU"
+    }
+}
+
+pub struct Mish;
+pub const MISH: Mish = Mish;
+
+impl Activation for Mish {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x * softplus(x).tanh())
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            let t = softplus(x).tanh();
+            t + x * (1.0 - t * t) * sigmoid(x)
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "Mish"
+    }
+}
+
+pub struct Softplus;
+pub const SOFTPLUS: Softplus = Softplus;
+
+impl Activation for Softplus {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, softplus)
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, sigmoid)
+    }
+
+    fn name(&self) -> &str {
+        "Softplus"
+    }
+}
+
+pub struct Softsign;
+pub const SOFTSIGN: Softsign = Softsign;
+
+impl Activation for Softsign {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x / (1.0 + x.abs()))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| 1.0 / (1.0 + x.abs()).powi(2))
+    }
+
+    fn name(&self) -> &str {
+        "Softsign"
+    }
+}
+
+//--------------------------------------------------------------Piecewise Linear---------------------------------------------------------------------
+
+//relu6(x + 3) / 6
+pub struct HardSigmoid;
+pub const HARD_SIGMOID: HardSigmoid = HardSigmoid;
+
+impl Activation for HardSigmoid {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| (x / 6.0 + 0.5).clamp(0.0, 1.0))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            if x > -3.0 && x < 3.0 {
+                1.0 / 6.0
+            } else {
+                0.0
+            }
+        })
+    }
+
+    fn name(&self) -> &str {
+        "HardSigmoid"
+    }
+}
+
+//x * relu6(x + 3) / 6
+pub struct HardSwish;
+pub const HARD_SWISH: HardSwish = HardSwish;
+
+impl Activation for HardSwish {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        elementwise(input, |x| x * (x / 6.0 + 0.5).clamp(0.0, 1.0))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        chain(input, grad_output, |x| {
+            if x <= -3.0 {
+                0.0
+            } else if x >= 3.0 {
+                1.0
+            } else {
+                (2.0 * x + 3.0) / 6.0
+            }
+        })
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "HardSwish"
+    }
+}
+
+//--------------------------------------------------------------Learnable---------------------------------------------------------------------
+
+//ReLU with a learned negative slope per channel along the axis, starting at 0.25.
+//A single channel shares one slope across the whole tensor.
+pub struct PReLU {
+    pub slopes: Tensor,
+    axis: usize,
+    slope_gradient: Tensor,
+}
+
+impl PReLU {
+    //Channels are along axis 0, the features of a TensorLayer
+    pub fn new(channels: usize) -> PReLU {
+        PReLU {
+            slopes: Tensor::from(vec![channels], vec![0.25; channels]),
+            axis: 0,
+            slope_gradient: Tensor::new(vec![channels]),
+        }
+    }
+
+    //Axis 1 for the channels of batch-first [N, C, ...] tensors
+    pub fn with_axis(mut self, axis: usize) -> PReLU {
+        self.axis = axis;
+        self
+    }
+
+    //Slope used for every element of a tensor shaped like input, in order
+    fn channels<'a>(&'a self, input: &Tensor) -> impl Iterator<Item = usize> + 'a {
+        let channels = self.slopes.data.len();
+        let inner: usize = if channels == 1 {
+            1
+        } else {
+            assert!(
+                input.shape.get(self.axis) == Some(&channels),
+                "PReLU with {} slopes got a tensor shaped {:?} along axis {}",
+                channels,
+                input.shape,
+                self.axis
+            );
+            input.shape[self.axis + 1..].iter().product()
+        };
+        (0..input.data.len()).map(move |index| (index / inner) % channels)
+    }
+}
+
+impl Activation for PReLU {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let data = input
+            .data
+            .iter()
+            .zip(self.channels(input))
+            .map(|(&x, c)| if x > 0.0 { x } else { self.slopes.data[c] * x })
+            .collect();
+        Tensor::from(input.shape.clone(), data)
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        let channels: Vec<usize> = self.channels(input).collect();
+        let mut slope_gradient = Tensor::new(self.slopes.shape.clone());
+        let mut grad = grad_output.clone();
+
+        for ((g, &x), c) in grad.data.iter_mut().zip(&input.data).zip(channels) {
+            if x <= 0.0 {
+                slope_gradient.data[c] += *g * x;
+                *g *= self.slopes.data[c];
+            }
+        }
+
+        self.slope_gradient = slope_gradient;
+        grad
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.slopes]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.slope_gradient]
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "PReLU"
+    }
+}
+
+//x * sigmoid(beta * x) with beta learned, SiLU when beta is 1
+pub struct Swish {
+    pub beta: Tensor,
+    beta_gradient: Tensor,
+}
+
+impl Swish {
+    pub fn new(beta: f64) -> Swish {
+        Swish {
+            beta: Tensor::from(vec![1], vec![beta]),
+            beta_gradient: Tensor::new(vec![1]),
+        }
+    }
+}
+
+impl Activation for Swish {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let beta = self.beta.data[0];
+        elementwise(input, |x| x * sigmoid(beta * x))
+    }
+
+    fn backward(&mut self, input: &Tensor, _output: &Tensor, grad_output: &Tensor) -> Tensor {
+        let beta = self.beta.data[0];
+        let mut beta_gradient = 0.0;
+        let grad = Tensor::from(
+            grad_output.shape.clone(),
+            input
+                .data
+                .iter()
+                .zip(&grad_output.data)
+                .map(|(&x, g)| {
+                    let s = sigmoid(beta * x);
+                    let slope = s * (1.0 - s);
+                    beta_gradient += g * x * x * slope;
+                    g * (s + beta * x * slope)
+                })
+                .collect(),
+        );
+        self.beta_gradient.data[0] = beta_gradient;
+        grad
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.beta_gradient]
+    }
+
+    fn default_initializer(&self) -> Initializer {
+        Initializer::HeNormal
+    }
+
+    fn name(&self) -> &str {
+        "Swish"
+    }
+}
+
+//--------------------------------------------------------------Vector Activations---------------------------------------------------------------------
+
+//Normalises every slice along the axis into a probability distribution. Axis 0 is the class
+//axis of the [classes, batch] columns a TensorLayer produces.
+pub struct Softmax {
+    pub axis: usize,
+}
+pub const SOFTMAX: Softmax = Softmax { axis: 0 };
+
+impl Softmax {
+    pub fn new(axis: usize) -> Softmax {
+        Softmax { axis }
+    }
+}
+
+impl Activation for Softmax {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let mut output = input.clone();
+        for_each_slice(&mut output, self.axis, |slice| {
+            let max = slice
+                .iter()
+                .map(|&(_, x)| x)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let total: f64 = slice.iter().map(|&(_, x)| (x - max).exp()).sum();
+            slice
+                .iter()
+                .map(|&(_, x)| (x - max).exp() / total)
+                .collect()
+        });
+        output
+    }
+
+    //Jacobian-vector product y * (g - sum(g * y)) over each slice
+    fn backward(&mut self, _input: &Tensor, output: &Tensor, grad_output: &Tensor) -> Tensor {
+        let mut grad = grad_output.clone();
+        for_each_slice(&mut grad, self.axis, |slice| {
+            let y = |index: usize| output.data[index];
+            let dot: f64 = slice.iter().map(|&(index, g)| g * y(index)).sum();
+            slice
+                .iter()
+                .map(|&(index, g)| y(index) * (g - dot))
+                .collect()
+        });
+        grad
+    }
+
+    fn name(&self) -> &str {
+        "Softmax"
+    }
+}
+
+//log(softmax(x)) computed directly, pair it with a negative log likelihood loss
+pub struct LogSoftmax {
+    pub axis: usize,
+}
+pub const LOG_SOFTMAX: LogSoftmax = LogSoftmax { axis: 0 };
+
+impl LogSoftmax {
+    pub fn new(axis: usize) -> LogSoftmax {
+        LogSoftmax { axis }
+    }
+}
+
+impl Activation for LogSoftmax {
+    fn forward(&self, input: &Tensor) -> Tensor {
+        let mut output = input.clone();
+        for_each_slice(&mut output, self.axis, |slice| {
+            let max = slice
+                .iter()
+                .map(|&(_, x)| x)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let log_total = slice
+                .iter()
+                .map(|&(_, x)| (x - max).exp())
+                .sum::<f64>()
+                .ln();
+            slice.iter().map(|&(_, x)| x - max - log_total).collect()
+        });
+        output
+    }
+
+    //g - softmax(x) * sum(g) over each slice, with softmax(x) = exp(output)
+    fn backward(&mut self, _input: &Tensor, output: &Tensor, grad_output: &Tensor) -> Tensor {
+        let mut grad = grad_output.clone();
+        for_each_slice(&mut grad, self.axis, |slice| {
+            let total: f64 = slice.iter().map(|&(_, g)| g).sum();
+            slice
+                .iter()
+                .map(|&(index, g)| g - output.data[index].exp() * total)
+                .collect()
+        });
+        grad
+    }
+
+    fn name(&self) -> &str {
+        "LogSoftmax"
+    }
+}
+
+//--------------------------------------------------------------Helpers---------------------------------------------------------------------
+
+fn elementwise(input: &Tensor, function: impl Fn(f64) -> f64) -> Tensor {
+    Tensor::from(
+        input.shape.clone(),
+        input.data.iter().map(|&x| function(x)).collect(),
+    )
+}
+
+//grad_output scaled by the local derivative, evaluated at the cached input or output
+fn chain(cached: &Tensor, grad_output: &Tensor, derivative: impl Fn(f64) -> f64) -> Tensor {
+    Tensor::from(
+        grad_output.shape.clone(),
+        cached
+            .data
+            .iter()
+            .zip(&grad_output.data)
+            .map(|(&x, g)| g * derivative(x))
+            .collect(),
+    )
+}
+
+//Rewrites every 1-D slice of the tensor along the axis. The function gets the slice as
+//(flat index, value) pairs and returns its new values in the same order.
+fn for_each_slice(
+    tensor: &mut Tensor,
+    axis: usize,
+    mut function: impl FnMut(&[(usize, f64)]) -> Vec<f64>,
+) {
+    assert!(
+        axis < tensor.shape.len(),
+        "Axis {} is out of range for a tensor shaped {:?}",
+        axis,
+        tensor.shape
+    );
+    let length = tensor.shape[axis];
+    let inner: usize = tensor.shape[axis + 1..].iter().product();
+    let outer: usize = tensor.shape[..axis].iter().product();
+
+    let mut slice = Vec::with_capacity(length);
+    for o in 0..outer {
+        for i in 0..inner {
+            slice.clear();
+            slice.extend((0..length).map(|k| {
+                let index = (o * length + k) * inner + i;
+                (index, tensor.data[index])
+            }));
+            for (&(index, _), value) in slice.iter().zip(function(&slice)) {
+                tensor.data[index] = value;
+            }
+        }
+    }
+}
+
+//Split on the sign so neither branch overflows
+pub(crate) fn sigmoid(x: f64) -> f64 {
+    if x >= 0.0 {
+        1.0 / (1.0 + E.powf(-x))
+    } else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+//ln(1 + e^x) without overflow for large x
+fn softplus(x: f64) -> f64 {
+    x.max(0.0) + (-x.abs()).exp().ln_1p()
+}
+
+fn gelu_tanh_inner(x: f64) -> f64 {
+    (2.0 / PI).sqrt() * (x + 0.044715 * x * x * x)
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / SQRT_2))
+}
+
+//Taylor series near zero and the continued fraction of erfc further out,
+//both accurate to about 1e-15
+fn erf(x: f64) -> f64 {
+    let a = x.abs();
+    let value = if a < 2.5 {
+        let mut term = a;
+        let mut sum = a;
+        let mut n = 0.0;
+        while term.abs() > 1e-17 * sum.abs() {
+            n += 1.0;
+            term *= -a * a / n;
+            sum += term / (2.0 * n + 1.0);
+        }
+        2.0 / PI.sqrt() * sum
+    } else {
+        let mut fraction = a;
+        for n in (1..=60).rev() {
+            fraction = a + (n as f64 / 2.0) / fraction;
+        }
+        1.0 - (-a * a).exp() / (PI.sqrt() * fraction)
+    };
+    value.copysign(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{
+        gradient_check::{activation_check, gradient_check},
+        layer::{ActivationLayer, Layer},
+    };
+
+    const TOLERANCE: f64 = 1e-6;
+
+    //Spread over [-6, 6] but clear of the kinks at 0 and ±3
+    fn points() -> Vec<f64> {
+        (0..33).map(|i| -6.0 + 0.37 * i as f64 + 0.0123).collect()
+    }
+
+    #[test]
+    fn elementwise_derivatives() {
+        let mut activations: Vec<Box<dyn Activation>> = vec![
+            Box::new(SIGMOID),
+            Box::new(TANH),
+            Box::new(IDENTITY),
+            Box::new(RELU),
+            Box::new(LEAKY_RELU),
+            Box::new(LeakyReLU { slope: 0.2 }),
+            Box::new(ELU),
+            Box::new(Elu { alpha: 0.5 }),
+            Box::new(SELU),
+            Box::new(GELU),
+            Box::new(GELU_TANH),
+            Box::new(SILU),
+            Box::new(MISH),
+            Box::new(SOFTPLUS),
+            Box::new(SOFTSIGN),
+            Box::new(HARD_SIGMOID),
+            Box::new(HARD_SWISH),
+            Box::new(PReLU::new(1)),
+            Box::new(Swish::new(1.5)),
+        ];
+        for activation in activations.iter_mut() {
+            let error = activation_check(activation.as_mut(), &points(), 1e-6);
+            assert!(
+                error < TOLERANCE,
+                "{} relative error {}",
+                activation.name(),
+                error
+            );
+        }
+    }
+
+    fn assert_layer_gradients<A: Activation>(activation: A, input_shape: Vec<usize>) {
+        let mut layer = ActivationLayer::new(activation);
+        let error = gradient_check(&mut layer, &Tensor::random(input_shape), 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            layer.name(),
+            error
+        );
+    }
+
+    #[test]
+    fn learnable_parameter_gradients() {
+        assert_layer_gradients(PReLU::new(3).with_axis(1), vec![2, 3, 4]);
+        assert_layer_gradients(PReLU::new(3), vec![3, 5]);
+        assert_layer_gradients(Swish::new(0.7), vec![4, 5]);
+    }
+
+    #[test]
+    fn softmax_gradients() {
+        assert_layer_gradients(SOFTMAX, vec![4, 3]);
+        assert_layer_gradients(Softmax::new(1), vec![2, 5]);
+        assert_layer_gradients(LOG_SOFTMAX, vec![4, 3]);
+        assert_layer_gradients(LogSoftmax::new(1), vec![2, 5]);
+    }
+}
@@ -0,0 +1,473 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::{project, project_backward, Tensor},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+//Multi-head scaled dot-product attention over batch-first [N, L, E] sequences.
+//Queries, keys and values are projected to E features, split into `heads` heads of E / heads
+//features, attended separately and concatenated before the output projection.
+//As a Layer it is self-attention; attend() takes separate query, key and value inputs.
+pub struct MultiHeadAttention {
+    //Projections are [E, E] with [E] biases
+    pub w_q: Tensor,
+    pub b_q: Tensor,
+    pub w_k: Tensor,
+    pub b_k: Tensor,
+    pub w_v: Tensor,
+    pub b_v: Tensor,
+    pub w_o: Tensor,
+    pub b_o: Tensor,
+    embed_dim: usize,
+    heads: usize,
+    causal: bool,
+    padding_mask: Option<Tensor>,
+    dropout: f64,
+    rng: StdRng,
+    training: bool,
+    //Gradients in the same order as parameters()
+    gradients: Vec<Tensor>,
+    cache: Option<AttentionCache>,
+}
+
+struct AttentionCache {
+    //[N, L] query rows and [N, S] key rows
+    query_len: usize,
+    key_len: usize,
+    batch: usize,
+    x_q: Vec<f64>,
+    x_k: Vec<f64>,
+    x_v: Vec<f64>,
+    q: Vec<f64>,
+    k: Vec<f64>,
+    v: Vec<f64>,
+    //Softmax output before dropout, [N, heads, L, S]
+    weights: Tensor,
+    //Inverted dropout scales, same shape as weights, empty when no dropout was applied
+    dropout_mask: Vec<f64>,
+    //Concatenated heads, [N, L, E]
+    context: Vec<f64>,
+}
+
+impl MultiHeadAttention {
+    pub fn new(embed_dim: usize, heads: usize) -> MultiHeadAttention {
+        assert!(
+            heads > 0 && embed_dim.is_multiple_of(heads),
+            "Embedding size {} must split evenly into {} heads",
+            embed_dim,
+            heads
+        );
+        let bound = 1.0 / (embed_dim as f64).sqrt();
+        let projection = || Tensor::random(vec![embed_dim, embed_dim]).multiply_scalar(bound);
+        let bias = || Tensor::new(vec![embed_dim]);
+
+        let mut attention = MultiHeadAttention {
+            w_q: projection(),
+            b_q: bias(),
+            w_k: projection(),
+            b_k: bias(),
+            w_v: projection(),
+            b_v: bias(),
+            w_o: projection(),
+            b_o: bias(),
+            embed_dim,
+            heads,
+            causal: false,
+            padding_mask: None,
+            dropout: 0.0,
+            rng: StdRng::from_entropy(),
+            training: true,
+            gradients: Vec::new(),
+            cache: None,
+        };
+        attention.gradients = attention
+            .parameters()
+            .iter()
+            .map(|p| Tensor::new(p.shape.clone()))
+            .collect();
+        attention
+    }
+
+    //Query position i only attends to key positions j <= i
+    pub fn with_causal_mask(mut self) -> MultiHeadAttention {
+        self.causal = true;
+        self
+    }
+
+    //Dropout on the attention weights, only in training mode
+    pub fn with_dropout(mut self, p: f64) -> MultiHeadAttention {
+        assert!(
+            (0.0..1.0).contains(&p),
+            "Dropout probability must be in [0, 1), got {}",
+            p
+        );
+        self.dropout = p;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> MultiHeadAttention {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    //[N, S] mask over the keys of the next forward calls, non-zero entries are padding
+    //and receive no attention
+    pub fn set_padding_mask(&mut self, mask: Option<Tensor>) {
+        self.padding_mask = mask;
+    }
+
+    //Softmax attention weights of the last forward call, [N, heads, L, S], before dropout
+    pub fn attention_weights(&self) -> Option<&Tensor> {
+        self.cache.as_ref().map(|cache| &cache.weights)
+    }
+
+    //Attention of query [N, L, E] over key and value [N, S, E], returns [N, L, E]
+    pub fn attend(&mut self, query: &Tensor, key: &Tensor, value: &Tensor) -> Tensor {
+        let e = self.embed_dim;
+        assert!(
+            query.shape.len() == 3 && query.shape[2] == e,
+            "Query must be [N, L, {}], got {:?}",
+            e,
+            query.shape
+        );
+        assert!(
+            key.shape.len() == 3 && key.shape == value.shape && key.shape[2] == e,
+            "Key and value must both be [N, S, {}], got {:?} and {:?}",
+            e,
+            key.shape,
+            value.shape
+        );
+        assert!(
+            key.shape[0] == query.shape[0],
+            "Query and key batch sizes differ"
+        );
+        let (batch, query_len, key_len) = (query.shape[0], query.shape[1], key.shape[1]);
+        if let Some(mask) = &self.padding_mask {
+            assert!(
+                mask.shape == vec![batch, key_len],
+                "Padding mask must be [{}, {}], got {:?}",
+                batch,
+                key_len,
+                mask.shape
+            );
+        }
+
+        let q = project(&query.data, &self.w_q, &self.b_q);
+        let k = project(&key.data, &self.w_k, &self.b_k);
+        let v = project(&value.data, &self.w_v, &self.b_v);
+
+        let d = e / self.heads;
+        let scale = 1.0 / (d as f64).sqrt();
+        let mut weights = Tensor::new(vec![batch, self.heads, query_len, key_len]);
+        let apply_dropout = self.training && self.dropout > 0.0;
+        let mut dropout_mask = Vec::new();
+        let mut context = vec![0.0; batch * query_len * e];
+
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let block = (n * self.heads + h) * query_len * key_len;
+                for i in 0..query_len {
+                    let row = &mut weights.data[block + i * key_len..block + (i + 1) * key_len];
+                    let q_row = &q[(n * query_len + i) * e + h * d..][..d];
+                    for (j, score) in row.iter_mut().enumerate() {
+                        *score = if self.is_masked(n, i, j) {
+                            f64::NEG_INFINITY
+                        } else {
+                            let k_row = &k[(n * key_len + j) * e + h * d..][..d];
+                            q_row.iter().zip(k_row).map(|(a, b)| a * b).sum::<f64>() * scale
+                        };
+                    }
+                    softmax_in_place(row);
+                }
+            }
+        }
+
+        if apply_dropout {
+            let keep = 1.0 / (1.0 - self.dropout);
+            dropout_mask = (0..weights.data.len())
+                .map(|_| {
+                    if self.rng.gen::<f64>() < self.dropout {
+                        0.0
+                    } else {
+                        keep
+                    }
+                })
+                .collect();
+        }
+
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let block = (n * self.heads + h) * query_len * key_len;
+                for i in 0..query_len {
+                    let out = &mut context[(n * query_len + i) * e + h * d..][..d];
+                    for j in 0..key_len {
+                        let mut a = weights.data[block + i * key_len + j];
+                        if apply_dropout {
+                            a *= dropout_mask[block + i * key_len + j];
+                        }
+                        let v_row = &v[(n * key_len + j) * e + h * d..][..d];
+                        for (o, x) in out.iter_mut().zip(v_row) {
+                            *o += a * x;
+                        }
+                    }
+                }
+            }
+        }
+
+        let output = project(&context, &self.w_o, &self.b_o);
+        self.cache = Some(AttentionCache {
+            query_len,
+            key_len,
+            batch,
+            x_q: query.data.clone(),
+            x_k: key.data.clone(),
+            x_v: value.data.clone(),
+            q,
+            k,
+            v,
+            weights,
+            dropout_mask,
+            context,
+        });
+        Tensor::from(vec![batch, query_len, e], output)
+    }
+
+    //Gradients w.r.t. the query, key and value of the last attend call
+    pub fn attend_backward(&mut self, grad_output: &Tensor) -> (Tensor, Tensor, Tensor) {
+        let cache = self
+            .cache
+            .as_ref()
+            .expect("attend_backward called before attend");
+        let e = self.embed_dim;
+        let d = e / self.heads;
+        let scale = 1.0 / (d as f64).sqrt();
+        let (batch, query_len, key_len) = (cache.batch, cache.query_len, cache.key_len);
+        let mut gradients: Vec<Tensor> = self
+            .gradients
+            .iter()
+            .map(|g| Tensor::new(g.shape.clone()))
+            .collect();
+
+        let d_context = project_backward(
+            &grad_output.data,
+            &cache.context,
+            &self.w_o,
+            &mut gradients[6..8],
+        );
+
+        let mut d_q = vec![0.0; cache.q.len()];
+        let mut d_k = vec![0.0; cache.k.len()];
+        let mut d_v = vec![0.0; cache.v.len()];
+        let mut d_weights = vec![0.0; key_len];
+
+        for n in 0..batch {
+            for h in 0..self.heads {
+                let block = (n * self.heads + h) * query_len * key_len;
+                for i in 0..query_len {
+                    let d_out = &d_context[(n * query_len + i) * e + h * d..][..d];
+                    let a_row = &cache.weights.data[block + i * key_len..][..key_len];
+
+                    //Through the weighted sum of values, then the dropout mask
+                    for (j, d_a) in d_weights.iter_mut().enumerate() {
+                        let drop = if cache.dropout_mask.is_empty() {
+                            1.0
+                        } else {
+                            cache.dropout_mask[block + i * key_len + j]
+                        };
+                        let v_row = &cache.v[(n * key_len + j) * e + h * d..][..d];
+                        *d_a = drop * d_out.iter().zip(v_row).map(|(a, b)| a * b).sum::<f64>();
+
+                        let d_v_row = &mut d_v[(n * key_len + j) * e + h * d..][..d];
+                        for (dv, g) in d_v_row.iter_mut().zip(d_out) {
+                            *dv += a_row[j] * drop * g;
+                        }
+                    }
+
+                    //Through the softmax
+                    let dot: f64 = d_weights.iter().zip(a_row).map(|(g, a)| g * a).sum();
+                    let q_row = &cache.q[(n * query_len + i) * e + h * d..][..d];
+                    for j in 0..key_len {
+                        let d_score = a_row[j] * (d_weights[j] - dot) * scale;
+                        if d_score == 0.0 {
+                            continue;
+                        }
+                        let k_row = &cache.k[(n * key_len + j) * e + h * d..][..d];
+                        let d_q_row = &mut d_q[(n * query_len + i) * e + h * d..][..d];
+                        for (dq, x) in d_q_row.iter_mut().zip(k_row) {
+                            *dq += d_score * x;
+                        }
+                        let d_k_row = &mut d_k[(n * key_len + j) * e + h * d..][..d];
+                        for (dk, x) in d_k_row.iter_mut().zip(q_row) {
+                            *dk += d_score * x;
+                        }
+                    }
+                }
+            }
+        }
+
+        let grad_query = project_backward(&d_q, &cache.x_q, &self.w_q, &mut gradients[0..2]);
+        let grad_key = project_backward(&d_k, &cache.x_k, &self.w_k, &mut gradients[2..4]);
+        let grad_value = project_backward(&d_v, &cache.x_v, &self.w_v, &mut gradients[4..6]);
+
+        self.gradients = gradients;
+        (
+            Tensor::from(vec![batch, query_len, e], grad_query),
+            Tensor::from(vec![batch, key_len, e], grad_key),
+            Tensor::from(vec![batch, key_len, e], grad_value),
+        )
+    }
+
+    fn is_masked(&self, n: usize, i: usize, j: usize) -> bool {
+        let padded = self
+            .padding_mask
+            .as_ref()
+            .is_some_and(|mask| mask.data[n * mask.shape[1] + j] != 0.0);
+        padded || (self.causal && j > i)
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.attend(input, input, input)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let (mut grad_query, grad_key, grad_value) = self.attend_backward(grad_output);
+        grad_query.add(&grad_key);
+        grad_query.add(&grad_value);
+        grad_query
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![
+            &mut self.w_q,
+            &mut self.b_q,
+            &mut self.w_k,
+            &mut self.b_k,
+            &mut self.w_v,
+            &mut self.b_v,
+            &mut self.w_o,
+            &mut self.b_o,
+        ]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.gradients.iter().collect()
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    //Samples are [L, E]
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.len() != 2 || input_shape[1] != self.embed_dim {
+            let expected = format!("[*, {}]", self.embed_dim);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    //Four [L, E] x [E, E] projections plus the score and weighted sum products
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let (len, e) = (input_shape[0], self.embed_dim);
+        8 * len * e * e + 4 * len * len * e
+    }
+
+    fn name(&self) -> &str {
+        "MultiHeadAttention"
+    }
+}
+
+//Softmax that subtracts the row maximum first. Fully masked rows (all -inf) become zeros.
+fn softmax_in_place(row: &mut [f64]) {
+    let max = row.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        row.fill(0.0);
+        return;
+    }
+    let mut sum = 0.0;
+    for x in row.iter_mut() {
+        *x = (*x - max).exp();
+        sum += *x;
+    }
+    for x in row.iter_mut() {
+        *x /= sum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::gradient_check;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(attention: &mut MultiHeadAttention, input_shape: Vec<usize>) {
+        let error = gradient_check(attention, &Tensor::random(input_shape), 1e-6);
+        assert!(error < TOLERANCE, "relative error {}", error);
+    }
+
+    //Second sample has its last two keys padded
+    fn padding_mask() -> Tensor {
+        Tensor::from(vec![2, 4], vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0])
+    }
+
+    #[test]
+    fn attention_gradients() {
+        assert_gradients(&mut MultiHeadAttention::new(4, 2), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn causal_attention_gradients() {
+        assert_gradients(
+            &mut MultiHeadAttention::new(4, 2).with_causal_mask(),
+            vec![2, 3, 4],
+        );
+    }
+
+    #[test]
+    fn padding_masked_attention_gradients() {
+        let mut attention = MultiHeadAttention::new(4, 2);
+        attention.set_padding_mask(Some(padding_mask()));
+        assert_gradients(&mut attention, vec![2, 4, 4]);
+    }
+
+    #[test]
+    fn weights_sum_to_one_over_unmasked_keys() {
+        let mut attention = MultiHeadAttention::new(6, 3).with_causal_mask();
+        attention.set_padding_mask(Some(padding_mask()));
+        attention.forward(&Tensor::random(vec![2, 4, 6]));
+
+        let weights = attention.attention_weights().unwrap();
+        assert_eq!(weights.shape, vec![2, 3, 4, 4]);
+        for (r, row) in weights.data.chunks(4).enumerate() {
+            let (n, i) = (r / 12, r % 4);
+            let unmasked: Vec<usize> = (0..4).filter(|&j| j <= i && (n == 0 || j < 2)).collect();
+            for (j, &w) in row.iter().enumerate() {
+                if !unmasked.contains(&j) {
+                    assert_eq!(w, 0.0, "masked key {} of row {} got weight {}", j, r, w);
+                }
+            }
+            let sum: f64 = unmasked.iter().map(|&j| row[j]).sum();
+            assert!((sum - 1.0).abs() < 1e-12, "row {} sums to {}", r, sum);
+        }
+    }
+
+    #[test]
+    fn fully_masked_query_row_is_zero() {
+        //With a causal mask the first query only sees the first key, padding it masks the whole row
+        let mut attention = MultiHeadAttention::new(4, 2).with_causal_mask();
+        attention.set_padding_mask(Some(Tensor::from(vec![1, 3], vec![1.0, 0.0, 0.0])));
+        let output = attention.forward(&Tensor::random(vec![1, 3, 4]));
+
+        let weights = attention.attention_weights().unwrap();
+        for head in 0..2 {
+            assert!(weights.data[head * 9..head * 9 + 3]
+                .iter()
+                .all(|&w| w == 0.0));
+        }
+        assert!(output.data[..4].iter().all(|&o| o == 0.0));
+        assert!(output.data[4..].iter().any(|&o| o != 0.0));
+    }
+}
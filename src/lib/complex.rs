@@ -0,0 +1,259 @@
+use super::tensor::Tensor;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+    pub const ONE: Complex = Complex { re: 1.0, im: 0.0 };
+
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    //e^(i * theta) scaled by r
+    pub fn from_polar(r: f64, theta: f64) -> Complex {
+        Complex {
+            re: r * theta.cos(),
+            im: r * theta.sin(),
+        }
+    }
+
+    pub fn conj(self) -> Complex {
+        Complex {
+            re: self.re,
+            im: -self.im,
+        }
+    }
+
+    //Magnitude |z|
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    //|z|^2, cheaper than norm when only comparing magnitudes
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    //Phase angle in radians
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn exp(self) -> Complex {
+        Complex::from_polar(self.re.exp(), self.im)
+    }
+
+    pub fn scale(self, factor: f64) -> Complex {
+        Complex {
+            re: self.re * factor,
+            im: self.im * factor,
+        }
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(re: f64) -> Complex {
+        Complex { re, im: 0.0 }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re + other.re,
+            im: self.im + other.im,
+        }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re - other.re,
+            im: self.im - other.im,
+        }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let denom = other.norm_sqr();
+        assert!(denom != 0.0, "Attempt to divide by a zero complex number");
+        Complex {
+            re: (self.re * other.re + self.im * other.im) / denom,
+            im: (self.im * other.re - self.re * other.im) / denom,
+        }
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex {
+            re: -self.re,
+            im: -self.im,
+        }
+    }
+}
+
+//--------------------------------------------------------------Complex Tensors---------------------------------------------------------------------
+
+impl Tensor<Complex> {
+    //A Tensor of complex zeros
+    pub fn new_complex(shape: Vec<usize>) -> Tensor<Complex> {
+        let count = shape.iter().product();
+        Tensor {
+            shape,
+            data: vec![Complex::ZERO; count],
+        }
+    }
+
+    //Promotes a real Tensor to a complex one with zero imaginary parts
+    pub fn from_real(real: &Tensor) -> Tensor<Complex> {
+        Tensor {
+            shape: real.shape.clone(),
+            data: real.data.iter().map(|&re| Complex::from(re)).collect(),
+        }
+    }
+
+    pub fn from_parts(real: &Tensor, imag: &Tensor) -> Tensor<Complex> {
+        assert!(
+            real.shape == imag.shape,
+            "Shape mismatch for complex parts: {:?} and {:?}",
+            real.shape,
+            imag.shape
+        );
+
+        Tensor {
+            shape: real.shape.clone(),
+            data: real
+                .data
+                .iter()
+                .zip(imag.data.iter())
+                .map(|(&re, &im)| Complex::new(re, im))
+                .collect(),
+        }
+    }
+
+    pub fn real(&self) -> Tensor {
+        Tensor::from(self.shape.clone(), self.data.iter().map(|z| z.re).collect())
+    }
+
+    pub fn imag(&self) -> Tensor {
+        Tensor::from(self.shape.clone(), self.data.iter().map(|z| z.im).collect())
+    }
+
+    //Elementwise magnitude
+    pub fn abs(&self) -> Tensor {
+        Tensor::from(
+            self.shape.clone(),
+            self.data.iter().map(|z| z.norm()).collect(),
+        )
+    }
+
+    //Elementwise phase angle
+    pub fn angle(&self) -> Tensor {
+        Tensor::from(
+            self.shape.clone(),
+            self.data.iter().map(|z| z.arg()).collect(),
+        )
+    }
+
+    pub fn conj(&self) -> Tensor<Complex> {
+        Tensor {
+            shape: self.shape.clone(),
+            data: self.data.iter().map(|z| z.conj()).collect(),
+        }
+    }
+
+    pub fn map_complex(&self, function: &dyn Fn(Complex) -> Complex) -> Tensor<Complex> {
+        Tensor {
+            shape: self.shape.clone(),
+            data: self.data.iter().map(|&z| function(z)).collect(),
+        }
+    }
+
+    //Elementwise product, used for pointwise multiplication in the frequency domain
+    pub fn multiply_elementwise(&self, other: &Tensor<Complex>) -> Tensor<Complex> {
+        assert!(
+            self.shape == other.shape,
+            "Shape mismatch for elementwise multiplication: {:?} and {:?}",
+            self.shape,
+            other.shape
+        );
+
+        Tensor {
+            shape: self.shape.clone(),
+            data: self
+                .data
+                .iter()
+                .zip(other.data.iter())
+                .map(|(&a, &b)| a * b)
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Complex, b: Complex) {
+        assert!((a - b).norm() < 1e-12, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = Complex::new(3.0, -2.0);
+        let b = Complex::new(-1.5, 0.5);
+        assert_close(a * b, Complex::new(-3.5, 4.5));
+        assert_close(a / b * b, a);
+        assert_close(a * a.conj(), Complex::from(a.norm_sqr()));
+        assert_close(-a + a, Complex::ZERO);
+    }
+
+    #[test]
+    fn polar_form() {
+        let z = Complex::from_polar(2.0, 0.75);
+        assert!((z.norm() - 2.0).abs() < 1e-12);
+        assert!((z.arg() - 0.75).abs() < 1e-12);
+        assert_close(Complex::new(0.0, std::f64::consts::PI).exp(), -Complex::ONE);
+    }
+
+    #[test]
+    fn tensor_parts_round_trip() {
+        let real = Tensor::random(vec![2, 3]);
+        let imag = Tensor::random(vec![2, 3]);
+        let z = Tensor::from_parts(&real, &imag);
+        assert_eq!(z.real().data, real.data);
+        assert_eq!(z.imag().data, imag.data);
+        assert_eq!(
+            z.conj().imag().data,
+            imag.data.iter().map(|x| -x).collect::<Vec<_>>()
+        );
+
+        let product = z.multiply_elementwise(&z.conj());
+        for (p, magnitude) in product.data.iter().zip(z.abs().data) {
+            assert_close(*p, Complex::from(magnitude * magnitude));
+        }
+    }
+}
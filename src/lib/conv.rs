@@ -0,0 +1,1055 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::{conv_output_size, Tensor},
+};
+
+//D-dimensional convolution over [N, C, *spatial] tensors with D spatial axes,
+//computed as im2col + one matrix multiply per group.
+//Weights are [out_channels, in_channels / groups, kernel_size, ... (D times)].
+pub struct Conv<const D: usize> {
+    pub weights: Tensor,
+    pub biases: Option<Tensor>,
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+    groups: usize,
+    input_shape: Vec<usize>,
+    columns: Tensor,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+//[N, C, L] sequences
+pub type Conv1d = Conv<1>;
+//[N, C, H, W] images
+pub type Conv2d = Conv<2>;
+//[N, C, D, H, W] volumes
+pub type Conv3d = Conv<3>;
+
+impl<const D: usize> Conv<D> {
+    //Stride 1, no padding, no dilation, a single group and a bias
+    pub fn new(in_channels: usize, out_channels: usize, kernel_size: usize) -> Conv<D> {
+        assert!(kernel_size > 0, "Kernel size must be positive");
+        let weights = Tensor::random(Conv::<D>::weight_shape(
+            out_channels,
+            in_channels,
+            kernel_size,
+        ));
+        Conv {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            bias_gradient: Tensor::new(vec![out_channels]),
+            weights,
+            biases: Some(Tensor::random(vec![out_channels])),
+            in_channels,
+            out_channels,
+            kernel_size,
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            groups: 1,
+            input_shape: Vec::new(),
+            columns: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> Conv<D> {
+        assert!(stride > 0, "Stride must be positive");
+        self.stride = stride;
+        self
+    }
+
+    //Zero padding added to both sides of each spatial axis
+    pub fn with_padding(mut self, padding: usize) -> Conv<D> {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> Conv<D> {
+        assert!(dilation > 0, "Dilation must be positive");
+        self.dilation = dilation;
+        self
+    }
+
+    //Splits the channels into independent groups, each group only sees its own in_channels / groups inputs
+    pub fn with_groups(mut self, groups: usize) -> Conv<D> {
+        assert!(
+            groups > 0
+                && self.in_channels.is_multiple_of(groups)
+                && self.out_channels.is_multiple_of(groups),
+            "in_channels {} and out_channels {} must both be divisible by groups {}",
+            self.in_channels,
+            self.out_channels,
+            groups
+        );
+        self.groups = groups;
+        self.weights = Tensor::random(Conv::<D>::weight_shape(
+            self.out_channels,
+            self.in_channels / groups,
+            self.kernel_size,
+        ));
+        self.weight_gradient = Tensor::new(self.weights.shape.clone());
+        self
+    }
+
+    pub fn without_bias(mut self) -> Conv<D> {
+        self.biases = None;
+        self
+    }
+
+    fn weight_shape(out_channels: usize, in_channels: usize, kernel_size: usize) -> Vec<usize> {
+        [vec![out_channels, in_channels], vec![kernel_size; D]].concat()
+    }
+
+    fn spatial_output(&self, in_spatial: &[usize]) -> Vec<usize> {
+        in_spatial
+            .iter()
+            .map(|&size| {
+                conv_output_size(
+                    size,
+                    self.kernel_size,
+                    self.stride,
+                    self.padding,
+                    self.dilation,
+                )
+            })
+            .collect()
+    }
+
+    //kernel, stride, padding and dilation spelled out for every spatial axis
+    fn unfold_arguments(&self) -> [[usize; D]; 4] {
+        [
+            [self.kernel_size; D],
+            [self.stride; D],
+            [self.padding; D],
+            [self.dilation; D],
+        ]
+    }
+
+    //Weights of one group as a [out_channels / groups, in_channels / groups * k * k] matrix
+    fn group_weights(&self, group: usize) -> Tensor {
+        let rows = self.out_channels / self.groups;
+        let cols = self.weights.data.len() / self.out_channels;
+        Tensor::from(
+            vec![rows, cols],
+            self.weights.data[group * rows * cols..(group + 1) * rows * cols].to_vec(),
+        )
+    }
+}
+
+impl<const D: usize> Layer for Conv<D> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            input.shape.len() == D + 2 && input.shape[1] == self.in_channels,
+            "{} expects [N, {}] followed by {} spatial axes, got {:?}",
+            self.name(),
+            self.in_channels,
+            D,
+            input.shape
+        );
+        let n = input.shape[0];
+        let out_spatial = self.spatial_output(&input.shape[2..]);
+        let [kernel, stride, padding, dilation] = self.unfold_arguments();
+
+        self.input_shape = input.shape.clone();
+        self.columns = input.im2col(&kernel, &stride, &padding, &dilation);
+
+        //[out_channels, N * spatial], one GEMM per group
+        let rows_per_group = self.columns.shape[0] / self.groups;
+        let channels_per_group = self.out_channels / self.groups;
+        let spatial: usize = out_spatial.iter().product();
+        let mut res = Tensor::new([vec![n, self.out_channels], out_spatial].concat());
+
+        for g in 0..self.groups {
+            let cols = self
+                .columns
+                .rows(g * rows_per_group, (g + 1) * rows_per_group);
+            let out = self.group_weights(g).multiply(&cols);
+
+            //Scatter [oc, N * spatial] into [N, oc, *spatial]
+            for o in 0..channels_per_group {
+                let oc = g * channels_per_group + o;
+                let bias = self.biases.as_ref().map_or(0.0, |b| b.data[oc]);
+                for b in 0..n {
+                    let src = &out.data[o * n * spatial + b * spatial..][..spatial];
+                    let dst = &mut res.data[(b * self.out_channels + oc) * spatial..][..spatial];
+                    for (d, &s) in dst.iter_mut().zip(src.iter()) {
+                        *d = s + bias;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let n = self.input_shape[0];
+        let spatial: usize = grad_output.shape[2..].iter().product();
+        let [kernel, stride, padding, dilation] = self.unfold_arguments();
+
+        //Rearrange [N, oc, *spatial] into the [oc, N * spatial] layout of the forward GEMM
+        let grad = to_channel_rows(grad_output);
+
+        if self.biases.is_some() {
+            for oc in 0..self.out_channels {
+                self.bias_gradient.data[oc] = grad.data[oc * n * spatial..(oc + 1) * n * spatial]
+                    .iter()
+                    .sum();
+            }
+        }
+
+        let rows_per_group = self.columns.shape[0] / self.groups;
+        let channels_per_group = self.out_channels / self.groups;
+        let mut column_gradient = Tensor::new(self.columns.shape.clone());
+
+        for g in 0..self.groups {
+            let grad_g = grad.rows(g * channels_per_group, (g + 1) * channels_per_group);
+            let cols = self
+                .columns
+                .rows(g * rows_per_group, (g + 1) * rows_per_group);
+
+            let weight_gradient = grad_g.multiply(&cols.transpose());
+            let size = weight_gradient.data.len();
+            self.weight_gradient.data[g * size..(g + 1) * size]
+                .copy_from_slice(&weight_gradient.data);
+
+            let col_grad = self.group_weights(g).transpose().multiply(&grad_g);
+            let size = col_grad.data.len();
+            column_gradient.data[g * size..(g + 1) * size].copy_from_slice(&col_grad.data);
+        }
+
+        column_gradient.col2im(&self.input_shape, &kernel, &stride, &padding, &dilation)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        match &mut self.biases {
+            Some(biases) => vec![&mut self.weights, biases],
+            None => vec![&mut self.weights],
+        }
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        match self.biases {
+            Some(_) => vec![&self.weight_gradient, &self.bias_gradient],
+            None => vec![&self.weight_gradient],
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let min_size = min_input_size(self.kernel_size, self.padding, self.dilation);
+        if input_shape.len() != D + 1
+            || input_shape[0] != self.in_channels
+            || input_shape[1..].iter().any(|&size| size < min_size)
+        {
+            let expected = expected_sample(self.in_channels, D, min_size);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok([
+            vec![self.out_channels],
+            self.spatial_output(&input_shape[1..]),
+        ]
+        .concat())
+    }
+
+    //One multiply-add per kernel tap per output element
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let taps = self.in_channels / self.groups * self.kernel_size.pow(D as u32);
+        let outputs: usize = self.out_channels
+            * self
+                .spatial_output(&input_shape[1..])
+                .iter()
+                .product::<usize>();
+        2 * taps * outputs
+    }
+
+    fn name(&self) -> &str {
+        match D {
+            1 => "Conv1d",
+            2 => "Conv2d",
+            3 => "Conv3d",
+            _ => "Conv",
+        }
+    }
+}
+
+//--------------------------------------------------------------Transposed Convolution---------------------------------------------------------------------
+
+//The adjoint of Conv: every input pixel scatters a weighted copy of the kernel into the output,
+//growing the spatial size to (in - 1) * stride - 2 * padding + dilation * (k - 1) + output_padding + 1.
+//Forward is computed as a GEMM followed by col2im, backward as im2col followed by a GEMM.
+//Weights are [in_channels, out_channels / groups, kernel_size, ... (D times)].
+pub struct ConvTranspose<const D: usize> {
+    pub weights: Tensor,
+    pub biases: Option<Tensor>,
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    output_padding: usize,
+    dilation: usize,
+    groups: usize,
+    //Input in the [in_channels, N * spatial] GEMM layout
+    input: Tensor,
+    input_shape: Vec<usize>,
+    output_shape: Vec<usize>,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+pub type ConvTranspose1d = ConvTranspose<1>;
+pub type ConvTranspose2d = ConvTranspose<2>;
+pub type ConvTranspose3d = ConvTranspose<3>;
+
+impl<const D: usize> ConvTranspose<D> {
+    //Stride 1, no padding, no dilation, a single group and a bias
+    pub fn new(in_channels: usize, out_channels: usize, kernel_size: usize) -> ConvTranspose<D> {
+        assert!(kernel_size > 0, "Kernel size must be positive");
+        let weights = Tensor::random(ConvTranspose::<D>::weight_shape(
+            in_channels,
+            out_channels,
+            kernel_size,
+        ));
+        ConvTranspose {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            bias_gradient: Tensor::new(vec![out_channels]),
+            weights,
+            biases: Some(Tensor::random(vec![out_channels])),
+            in_channels,
+            out_channels,
+            kernel_size,
+            stride: 1,
+            padding: 0,
+            output_padding: 0,
+            dilation: 1,
+            groups: 1,
+            input: Tensor::new(Vec::new()),
+            input_shape: Vec::new(),
+            output_shape: Vec::new(),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> ConvTranspose<D> {
+        assert!(stride > 0, "Stride must be positive");
+        self.stride = stride;
+        self
+    }
+
+    //Removes padding rows/columns from both sides of the output, mirroring Conv's padding
+    pub fn with_padding(mut self, padding: usize) -> ConvTranspose<D> {
+        self.padding = padding;
+        self
+    }
+
+    //Extra size added to one side of each output axis. With stride > 1 several output
+    //sizes map to the same input size under Conv, this picks which one to produce.
+    pub fn with_output_padding(mut self, output_padding: usize) -> ConvTranspose<D> {
+        self.output_padding = output_padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> ConvTranspose<D> {
+        assert!(dilation > 0, "Dilation must be positive");
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn with_groups(mut self, groups: usize) -> ConvTranspose<D> {
+        assert!(
+            groups > 0
+                && self.in_channels.is_multiple_of(groups)
+                && self.out_channels.is_multiple_of(groups),
+            "in_channels {} and out_channels {} must both be divisible by groups {}",
+            self.in_channels,
+            self.out_channels,
+            groups
+        );
+        self.groups = groups;
+        self.weights = Tensor::random(ConvTranspose::<D>::weight_shape(
+            self.in_channels,
+            self.out_channels / groups,
+            self.kernel_size,
+        ));
+        self.weight_gradient = Tensor::new(self.weights.shape.clone());
+        self
+    }
+
+    pub fn without_bias(mut self) -> ConvTranspose<D> {
+        self.biases = None;
+        self
+    }
+
+    fn weight_shape(in_channels: usize, out_channels: usize, kernel_size: usize) -> Vec<usize> {
+        [vec![in_channels, out_channels], vec![kernel_size; D]].concat()
+    }
+
+    fn spatial_output(&self, in_spatial: &[usize]) -> Vec<usize> {
+        assert!(
+            self.output_padding < self.stride,
+            "output_padding {} must be smaller than stride {}",
+            self.output_padding,
+            self.stride
+        );
+        in_spatial
+            .iter()
+            .map(|&size| {
+                let full = (size - 1) * self.stride
+                    + self.dilation * (self.kernel_size - 1)
+                    + self.output_padding
+                    + 1;
+                assert!(
+                    full > 2 * self.padding,
+                    "Padding {} removes the whole output",
+                    self.padding
+                );
+                full - 2 * self.padding
+            })
+            .collect()
+    }
+
+    fn unfold_arguments(&self) -> [[usize; D]; 4] {
+        [
+            [self.kernel_size; D],
+            [self.stride; D],
+            [self.padding; D],
+            [self.dilation; D],
+        ]
+    }
+
+    //Weights of one group as a [in_channels / groups, out_channels / groups * k^D] matrix
+    fn group_weights(&self, group: usize) -> Tensor {
+        let rows = self.in_channels / self.groups;
+        let cols = self.weights.data.len() / self.in_channels;
+        Tensor::from(
+            vec![rows, cols],
+            self.weights.data[group * rows * cols..(group + 1) * rows * cols].to_vec(),
+        )
+    }
+}
+
+impl<const D: usize> Layer for ConvTranspose<D> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            input.shape.len() == D + 2 && input.shape[1] == self.in_channels,
+            "{} expects [N, {}] followed by {} spatial axes, got {:?}",
+            self.name(),
+            self.in_channels,
+            D,
+            input.shape
+        );
+        let n = input.shape[0];
+        let out_spatial = self.spatial_output(&input.shape[2..]);
+        let [kernel, stride, padding, dilation] = self.unfold_arguments();
+
+        self.input = to_channel_rows(input);
+        self.input_shape = input.shape.clone();
+        self.output_shape = [vec![n, self.out_channels], out_spatial].concat();
+
+        //Columns of the output, [out_channels * k^D, N * in_spatial]
+        let in_per_group = self.in_channels / self.groups;
+        let taps: usize = kernel.iter().product();
+        let mut columns = Tensor::new(vec![self.out_channels * taps, self.input.shape[1]]);
+        for g in 0..self.groups {
+            let x = self.input.rows(g * in_per_group, (g + 1) * in_per_group);
+            let cols = self.group_weights(g).transpose().multiply(&x);
+            let size = cols.data.len();
+            columns.data[g * size..(g + 1) * size].copy_from_slice(&cols.data);
+        }
+
+        let mut res = columns.col2im(&self.output_shape, &kernel, &stride, &padding, &dilation);
+
+        if let Some(biases) = &self.biases {
+            let spatial = res.data.len() / (n * self.out_channels);
+            for (i, chunk) in res.data.chunks_mut(spatial).enumerate() {
+                let bias = biases.data[i % self.out_channels];
+                for value in chunk {
+                    *value += bias;
+                }
+            }
+        }
+
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let [kernel, stride, padding, dilation] = self.unfold_arguments();
+
+        if self.biases.is_some() {
+            let grad = to_channel_rows(grad_output);
+            let cols = grad.shape[1];
+            for oc in 0..self.out_channels {
+                self.bias_gradient.data[oc] = grad.data[oc * cols..(oc + 1) * cols].iter().sum();
+            }
+        }
+
+        //Unfolding the output gradient lines it up with the input pixels that produced it
+        let columns = grad_output.im2col(&kernel, &stride, &padding, &dilation);
+        let rows_per_group = columns.shape[0] / self.groups;
+        let in_per_group = self.in_channels / self.groups;
+        let mut grad_input = Tensor::new(self.input.shape.clone());
+
+        for g in 0..self.groups {
+            let cols = columns.rows(g * rows_per_group, (g + 1) * rows_per_group);
+            let x = self.input.rows(g * in_per_group, (g + 1) * in_per_group);
+
+            let weight_gradient = x.multiply(&cols.transpose());
+            let size = weight_gradient.data.len();
+            self.weight_gradient.data[g * size..(g + 1) * size]
+                .copy_from_slice(&weight_gradient.data);
+
+            let input_gradient = self.group_weights(g).multiply(&cols);
+            let size = input_gradient.data.len();
+            grad_input.data[g * size..(g + 1) * size].copy_from_slice(&input_gradient.data);
+        }
+
+        from_channel_rows(&grad_input, &self.input_shape)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        match &mut self.biases {
+            Some(biases) => vec![&mut self.weights, biases],
+            None => vec![&mut self.weights],
+        }
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        match self.biases {
+            Some(_) => vec![&self.weight_gradient, &self.bias_gradient],
+            None => vec![&self.weight_gradient],
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        //The output must outgrow the padding cropped from both sides
+        let span = self.dilation * (self.kernel_size - 1) + self.output_padding + 1;
+        let mut min_size = 1;
+        while (min_size - 1) * self.stride + span <= 2 * self.padding {
+            min_size += 1;
+        }
+        if input_shape.len() != D + 1
+            || input_shape[0] != self.in_channels
+            || input_shape[1..].iter().any(|&size| size < min_size)
+        {
+            let expected = expected_sample(self.in_channels, D, min_size);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok([
+            vec![self.out_channels],
+            self.spatial_output(&input_shape[1..]),
+        ]
+        .concat())
+    }
+
+    //Every input element is scattered through a kernel of each output channel in its group
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let inputs: usize = input_shape.iter().product();
+        2 * inputs * self.out_channels / self.groups * self.kernel_size.pow(D as u32)
+    }
+
+    fn name(&self) -> &str {
+        match D {
+            1 => "ConvTranspose1d",
+            2 => "ConvTranspose2d",
+            3 => "ConvTranspose3d",
+            _ => "ConvTranspose",
+        }
+    }
+}
+
+//--------------------------------------------------------------Depthwise Convolution---------------------------------------------------------------------
+
+//Conv2d with groups == in_channels, each input channel is convolved with its own
+//`multiplier` filters. Computed with direct loops instead of im2col: every filter only
+//touches one channel, so unfolding all channels into a column matrix would be wasted work.
+//Weights are [in_channels * multiplier, 1, kernel_size, kernel_size].
+pub struct DepthwiseConv2d {
+    pub weights: Tensor,
+    pub biases: Option<Tensor>,
+    in_channels: usize,
+    multiplier: usize,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+    input: Tensor,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+impl DepthwiseConv2d {
+    //One filter per channel, stride 1, no padding, no dilation and a bias
+    pub fn new(in_channels: usize, kernel_size: usize) -> DepthwiseConv2d {
+        DepthwiseConv2d::with_multiplier(in_channels, 1, kernel_size)
+    }
+
+    pub fn with_multiplier(
+        in_channels: usize,
+        multiplier: usize,
+        kernel_size: usize,
+    ) -> DepthwiseConv2d {
+        assert!(kernel_size > 0, "Kernel size must be positive");
+        assert!(multiplier > 0, "Channel multiplier must be positive");
+        let out_channels = in_channels * multiplier;
+        let weights = Tensor::random(vec![out_channels, 1, kernel_size, kernel_size]);
+        DepthwiseConv2d {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            bias_gradient: Tensor::new(vec![out_channels]),
+            weights,
+            biases: Some(Tensor::random(vec![out_channels])),
+            in_channels,
+            multiplier,
+            kernel_size,
+            stride: 1,
+            padding: 0,
+            dilation: 1,
+            input: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> DepthwiseConv2d {
+        assert!(stride > 0, "Stride must be positive");
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> DepthwiseConv2d {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> DepthwiseConv2d {
+        assert!(dilation > 0, "Dilation must be positive");
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn without_bias(mut self) -> DepthwiseConv2d {
+        self.biases = None;
+        self
+    }
+
+    fn out_channels(&self) -> usize {
+        self.in_channels * self.multiplier
+    }
+
+    fn spatial_output(&self, size: usize) -> usize {
+        conv_output_size(
+            size,
+            self.kernel_size,
+            self.stride,
+            self.padding,
+            self.dilation,
+        )
+    }
+
+    //Input coordinate of kernel tap k at output position o, None inside the padding
+    fn source(&self, o: usize, k: usize, size: usize) -> Option<usize> {
+        let i = (o * self.stride + k * self.dilation) as isize - self.padding as isize;
+        if i < 0 || i >= size as isize {
+            None
+        } else {
+            Some(i as usize)
+        }
+    }
+}
+
+impl Layer for DepthwiseConv2d {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            input.shape.len() == 4 && input.shape[1] == self.in_channels,
+            "DepthwiseConv2d expects [N, {}, H, W], got {:?}",
+            self.in_channels,
+            input.shape
+        );
+        let (n, h, w) = (input.shape[0], input.shape[2], input.shape[3]);
+        let (out_h, out_w) = (self.spatial_output(h), self.spatial_output(w));
+        let (out_channels, k) = (self.out_channels(), self.kernel_size);
+
+        self.input = input.clone();
+        let mut res = Tensor::new(vec![n, out_channels, out_h, out_w]);
+
+        for b in 0..n {
+            for oc in 0..out_channels {
+                let ch = oc / self.multiplier;
+                let plane = &input.data[(b * self.in_channels + ch) * h * w..][..h * w];
+                let kernel = &self.weights.data[oc * k * k..][..k * k];
+                let bias = self.biases.as_ref().map_or(0.0, |b| b.data[oc]);
+                let out = &mut res.data[(b * out_channels + oc) * out_h * out_w..][..out_h * out_w];
+
+                for oi in 0..out_h {
+                    for oj in 0..out_w {
+                        let mut sum = bias;
+                        for ki in 0..k {
+                            let Some(i) = self.source(oi, ki, h) else {
+                                continue;
+                            };
+                            for kj in 0..k {
+                                if let Some(j) = self.source(oj, kj, w) {
+                                    sum += kernel[ki * k + kj] * plane[i * w + j];
+                                }
+                            }
+                        }
+                        out[oi * out_w + oj] = sum;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let (n, h, w) = (
+            self.input.shape[0],
+            self.input.shape[2],
+            self.input.shape[3],
+        );
+        let (out_h, out_w) = (grad_output.shape[2], grad_output.shape[3]);
+        let (out_channels, k) = (self.out_channels(), self.kernel_size);
+
+        let mut grad_input = Tensor::new(self.input.shape.clone());
+        self.weight_gradient = Tensor::new(self.weights.shape.clone());
+        self.bias_gradient = Tensor::new(vec![out_channels]);
+
+        for b in 0..n {
+            for oc in 0..out_channels {
+                let ch = oc / self.multiplier;
+                let plane_offset = (b * self.in_channels + ch) * h * w;
+                let grad =
+                    &grad_output.data[(b * out_channels + oc) * out_h * out_w..][..out_h * out_w];
+                self.bias_gradient.data[oc] += grad.iter().sum::<f64>();
+
+                for oi in 0..out_h {
+                    for oj in 0..out_w {
+                        let g = grad[oi * out_w + oj];
+                        for ki in 0..k {
+                            let Some(i) = self.source(oi, ki, h) else {
+                                continue;
+                            };
+                            for kj in 0..k {
+                                if let Some(j) = self.source(oj, kj, w) {
+                                    let index = plane_offset + i * w + j;
+                                    self.weight_gradient.data[oc * k * k + ki * k + kj] +=
+                                        g * self.input.data[index];
+                                    grad_input.data[index] +=
+                                        g * self.weights.data[oc * k * k + ki * k + kj];
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        grad_input
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        match &mut self.biases {
+            Some(biases) => vec![&mut self.weights, biases],
+            None => vec![&mut self.weights],
+        }
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        match self.biases {
+            Some(_) => vec![&self.weight_gradient, &self.bias_gradient],
+            None => vec![&self.weight_gradient],
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let min_size = min_input_size(self.kernel_size, self.padding, self.dilation);
+        if input_shape.len() != 3
+            || input_shape[0] != self.in_channels
+            || input_shape[1..].iter().any(|&size| size < min_size)
+        {
+            let expected = expected_sample(self.in_channels, 2, min_size);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(vec![
+            self.out_channels(),
+            self.spatial_output(input_shape[1]),
+            self.spatial_output(input_shape[2]),
+        ])
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let outputs = self.out_channels()
+            * self.spatial_output(input_shape[1])
+            * self.spatial_output(input_shape[2]);
+        2 * outputs * self.kernel_size * self.kernel_size
+    }
+
+    fn name(&self) -> &str {
+        "DepthwiseConv2d"
+    }
+}
+
+//--------------------------------------------------------------Separable Convolution---------------------------------------------------------------------
+
+//MobileNet style block: a depthwise convolution filters each channel spatially, then a
+//1x1 pointwise convolution mixes channels. Costs roughly 1 / out_channels + 1 / k^2 of a
+//full Conv2d with the same shape.
+pub struct SeparableConv2d {
+    pub depthwise: DepthwiseConv2d,
+    pub pointwise: Conv2d,
+}
+
+impl SeparableConv2d {
+    pub fn new(in_channels: usize, out_channels: usize, kernel_size: usize) -> SeparableConv2d {
+        SeparableConv2d {
+            depthwise: DepthwiseConv2d::new(in_channels, kernel_size).without_bias(),
+            pointwise: Conv2d::new(in_channels, out_channels, 1),
+        }
+    }
+
+    pub fn with_stride(mut self, stride: usize) -> SeparableConv2d {
+        self.depthwise = self.depthwise.with_stride(stride);
+        self
+    }
+
+    pub fn with_padding(mut self, padding: usize) -> SeparableConv2d {
+        self.depthwise = self.depthwise.with_padding(padding);
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: usize) -> SeparableConv2d {
+        self.depthwise = self.depthwise.with_dilation(dilation);
+        self
+    }
+
+    pub fn without_bias(mut self) -> SeparableConv2d {
+        self.pointwise = self.pointwise.without_bias();
+        self
+    }
+}
+
+impl Layer for SeparableConv2d {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let filtered = self.depthwise.forward(input);
+        self.pointwise.forward(&filtered)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let grad = self.pointwise.backward(grad_output);
+        self.depthwise.backward(&grad)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        let mut parameters = self.depthwise.parameters();
+        parameters.extend(self.pointwise.parameters());
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        let mut gradients = self.depthwise.gradients();
+        gradients.extend(self.pointwise.gradients());
+        gradients
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        self.pointwise
+            .output_shape(&self.depthwise.output_shape(input_shape)?)
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let depthwise_shape = self.depthwise.output_shape(input_shape).unwrap_or_default();
+        self.depthwise.flops(input_shape) + self.pointwise.flops(&depthwise_shape)
+    }
+
+    fn name(&self) -> &str {
+        "SeparableConv2d"
+    }
+}
+
+//--------------------------------------------------------------Helpers---------------------------------------------------------------------
+
+//Smallest spatial size a (dilated, padded) kernel fits in
+fn min_input_size(kernel_size: usize, padding: usize, dilation: usize) -> usize {
+    let span = dilation * (kernel_size - 1) + 1;
+    span.saturating_sub(2 * padding).max(1)
+}
+
+//Sample shape description like "[3, >= 5, >= 5]"
+fn expected_sample(channels: usize, spatial_axes: usize, min_size: usize) -> String {
+    let spatial = vec![format!(">= {}", min_size); spatial_axes];
+    format!("[{}, {}]", channels, spatial.join(", "))
+}
+
+//[N, C, *spatial] -> [C, N * spatial], the layout the convolution GEMMs work in
+fn to_channel_rows(input: &Tensor) -> Tensor {
+    let (n, c) = (input.shape[0], input.shape[1]);
+    let spatial = input.data.len() / (n * c);
+    let mut res = Tensor::new(vec![c, n * spatial]);
+    for b in 0..n {
+        for ch in 0..c {
+            let src = &input.data[(b * c + ch) * spatial..][..spatial];
+            res.data[ch * n * spatial + b * spatial..][..spatial].copy_from_slice(src);
+        }
+    }
+    res
+}
+
+//Inverse of to_channel_rows, `shape` is the [N, C, *spatial] shape to restore
+fn from_channel_rows(rows: &Tensor, shape: &[usize]) -> Tensor {
+    let (n, c) = (shape[0], shape[1]);
+    let spatial = rows.data.len() / (n * c);
+    let mut res = Tensor::new(shape.to_vec());
+    for b in 0..n {
+        for ch in 0..c {
+            let src = &rows.data[ch * n * spatial + b * spatial..][..spatial];
+            res.data[(b * c + ch) * spatial..][..spatial].copy_from_slice(src);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::gradient_check;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(layer: &mut dyn Layer, input_shape: Vec<usize>) {
+        let error = gradient_check(layer, &Tensor::random(input_shape), 1e-6);
+        assert!(error < TOLERANCE, "gradient error {:e}", error);
+    }
+
+    #[test]
+    fn conv2d_gradients() {
+        assert_gradients(&mut Conv2d::new(2, 3, 3), vec![2, 2, 5, 5]);
+    }
+
+    #[test]
+    fn conv2d_gradients_with_stride_and_padding() {
+        let mut conv = Conv2d::new(2, 3, 3).with_stride(2).with_padding(1);
+        assert_gradients(&mut conv, vec![2, 2, 6, 5]);
+    }
+
+    #[test]
+    fn conv2d_gradients_with_dilation() {
+        let mut conv = Conv2d::new(2, 2, 2).with_dilation(2).with_padding(1);
+        assert_gradients(&mut conv, vec![1, 2, 6, 6]);
+    }
+
+    #[test]
+    fn conv2d_gradients_with_groups_and_no_bias() {
+        let mut conv = Conv2d::new(4, 6, 3).with_groups(2).without_bias();
+        assert!(conv.parameters().len() == 1);
+        assert_gradients(&mut conv, vec![2, 4, 4, 4]);
+    }
+
+    #[test]
+    fn conv1d_gradients() {
+        let mut conv = Conv1d::new(2, 3, 3)
+            .with_stride(2)
+            .with_padding(2)
+            .with_dilation(2);
+        assert_gradients(&mut conv, vec![2, 2, 9]);
+    }
+
+    #[test]
+    fn conv3d_gradients() {
+        let mut conv = Conv3d::new(2, 2, 2)
+            .with_stride(2)
+            .with_padding(1)
+            .with_dilation(2);
+        assert_gradients(&mut conv, vec![1, 2, 4, 5, 4]);
+    }
+
+    #[test]
+    fn conv_transpose2d_gradients() {
+        let mut conv = ConvTranspose2d::new(2, 3, 3)
+            .with_stride(2)
+            .with_padding(1)
+            .with_output_padding(1);
+        assert_gradients(&mut conv, vec![2, 2, 3, 4]);
+    }
+
+    #[test]
+    fn conv_transpose_gradients_with_groups_and_dilation() {
+        let mut conv = ConvTranspose1d::new(4, 2, 2)
+            .with_groups(2)
+            .with_dilation(2)
+            .without_bias();
+        assert_gradients(&mut conv, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn conv_transpose_inverts_conv_output_shape() {
+        let (kernel, stride, padding, dilation) = (3, 2, 1, 2);
+        for size in 5..12 {
+            let conv = Conv2d::new(2, 3, kernel)
+                .with_stride(stride)
+                .with_padding(padding)
+                .with_dilation(dilation);
+            let convolved = conv.output_shape(&[2, size, size]).unwrap();
+
+            //The input sizes Conv rounds down to the same output differ by the leftover
+            let output_padding = (size + 2 * padding - dilation * (kernel - 1) - 1) % stride;
+            let transpose = ConvTranspose2d::new(3, 2, kernel)
+                .with_stride(stride)
+                .with_padding(padding)
+                .with_dilation(dilation)
+                .with_output_padding(output_padding);
+            assert_eq!(
+                transpose.output_shape(&convolved).unwrap(),
+                vec![2, size, size]
+            );
+        }
+    }
+
+    #[test]
+    fn depthwise_conv2d_gradients() {
+        let mut conv = DepthwiseConv2d::new(3, 3).with_stride(2).with_padding(1);
+        assert_gradients(&mut conv, vec![2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn depthwise_conv2d_gradients_with_multiplier() {
+        let mut conv = DepthwiseConv2d::with_multiplier(2, 2, 2)
+            .with_dilation(2)
+            .without_bias();
+        assert_eq!(conv.output_shape(&[2, 5, 5]).unwrap(), vec![4, 3, 3]);
+        assert_gradients(&mut conv, vec![2, 2, 5, 5]);
+    }
+
+    //A depthwise convolution is a grouped one with a group per input channel
+    #[test]
+    fn depthwise_conv2d_matches_grouped_conv2d() {
+        let mut depthwise = DepthwiseConv2d::with_multiplier(2, 2, 3).with_padding(1);
+        let mut grouped = Conv2d::new(2, 4, 3).with_groups(2).with_padding(1);
+        grouped.weights = depthwise.weights.clone();
+        grouped.biases = depthwise.biases.clone();
+
+        let input = Tensor::random(vec![2, 2, 4, 5]);
+        let (a, b) = (depthwise.forward(&input), grouped.forward(&input));
+        assert_eq!(a.shape, b.shape);
+        for (x, y) in a.data.iter().zip(&b.data) {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn separable_conv2d_gradients() {
+        let mut conv = SeparableConv2d::new(3, 4, 3).with_stride(2).with_padding(1);
+        assert_gradients(&mut conv, vec![2, 3, 5, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Channel multiplier must be positive")]
+    fn zero_multiplier_is_rejected() {
+        DepthwiseConv2d::with_multiplier(3, 0, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Kernel size must be positive")]
+    fn zero_kernel_is_rejected() {
+        Conv2d::new(1, 1, 0);
+    }
+}
@@ -1,175 +1,171 @@
-use crate::lib::loss::MSE;
-
-use super::{activations::Activation, tensor::Tensor};
+use super::{
+    activations::Activation,
+    dense::TensorLayer,
+    initializers::Initializer,
+    layer::{InputLayer, Layer, ShapeError},
+    loss::{LossFunction, Reduction, MSE},
+    summary::{LayerSummary, Summary},
+    tensor::Tensor,
+};
 use std::collections::VecDeque;
 
-//#[derive(Clone)]
-pub enum Layer {
-    InputLayer {
-        size: usize,
-    },
-    TensorLayer {
-        weights: Tensor,
-        biases: Tensor,
-        activations: Activation<'static>,
-        result: Tensor,
-    },
-}
 //
 pub struct CPUTensorNetwork {
-    layers: VecDeque<Layer>,
+    layers: VecDeque<Box<dyn Layer>>,
+    loss: Box<dyn LossFunction>,
+    training: bool,
 }
 
 //
 impl CPUTensorNetwork {
     //Constructor
     pub fn new(input_size: usize) -> CPUTensorNetwork {
+        CPUTensorNetwork::with_input_shape(vec![input_size])
+    }
+
+    //Network taking samples of any shape, e.g. [C, H, W] images or [T, F] sequences
+    pub fn with_input_shape(input_shape: Vec<usize>) -> CPUTensorNetwork {
         //Initiallizes the network
-        let mut layersVec = VecDeque::new();
-        layersVec.push_back(Layer::InputLayer { size: input_size });
-        CPUTensorNetwork { layers: layersVec }
+        let mut layers_vec: VecDeque<Box<dyn Layer>> = VecDeque::new();
+        layers_vec.push_back(Box::new(InputLayer::new(input_shape)));
+        CPUTensorNetwork {
+            layers: layers_vec,
+            loss: Box::new(MSE),
+            training: true,
+        }
     }
 
     //--------------------------------------------------------------Layers---------------------------------------------------------------------
 
-    //
-    pub fn add_tensor_layer(&mut self, amount: usize, act: Activation<'static>) {
-        let mut w: Tensor = Tensor::new(Vec::new());
-        if let Some(Layer::TensorLayer {
-            weights, biases, ..
-        }) = self.layers.back()
-        {
-            //Calculate Weight based off previous inputs and the amount of nodes
-            w = Tensor::random(vec![amount, biases.data.len()]);
-        } else if let Some(Layer::InputLayer { size }) = self.layers.back() {
-            w = Tensor::random(vec![amount, size.clone()]);
-        }
+    //Appends any layer, including ones defined outside this crate
+    pub fn add_layer<L: Layer + 'static>(&mut self, mut layer: L) {
+        layer.set_training(self.training);
+        self.layers.push_back(Box::new(layer));
+    }
 
-        //Adds tensor layers to the network depending on amount specified
-        let weights: Tensor = w; //Creates iterator and creates that many tensors and puts it into a Vector
-        let mut biases: Tensor = Tensor::random(vec![amount]); //Creates iterator and creates that many tensors and puts it into a Vector
-        let result: Tensor = Tensor::new(vec![amount]);
+    //Sized from the features coming out of the last layer, which must be a flat [features] sample.
+    //Weights use the initializer suited to the activation.
+    pub fn add_tensor_layer<A: Activation + 'static>(&mut self, amount: usize, act: A) {
+        let initializer = act.default_initializer();
+        self.add_tensor_layer_with_initializer(amount, act, initializer);
+    }
 
-        if biases.shape.len() < 2 {
-            biases.increase_dim(1);
+    pub fn add_tensor_layer_with_initializer<A: Activation + 'static>(
+        &mut self,
+        amount: usize,
+        act: A,
+        initializer: Initializer,
+    ) {
+        //Calculate Weight based off previous inputs and the amount of nodes
+        let inputs = match self.build() {
+            Ok(shape) if shape.len() == 1 => shape[0],
+            Ok(shape) => panic!(
+                "Cannot add a TensorLayer after samples shaped {:?}, add a Flatten layer first",
+                shape
+            ),
+            Err(error) => panic!("Cannot add a TensorLayer to an invalid network: {}", error),
+        };
+        self.add_layer(TensorLayer::new(inputs, amount, act).with_initializer(initializer));
+    }
+
+    //--------------------------------------------------------------Loss---------------------------------------------------------------------
+
+    //Objective minimised by back_propogate, MSE unless set
+    pub fn set_loss<L: LossFunction + 'static>(&mut self, loss: L) {
+        self.loss = Box::new(loss);
+    }
+
+    pub fn loss(&self) -> &dyn LossFunction {
+        self.loss.as_ref()
+    }
+
+    //--------------------------------------------------------------Shapes---------------------------------------------------------------------
+
+    //Shape of a single sample after every layer, stopping at the first layer that
+    //cannot take the shape it is given
+    pub fn layer_shapes(&self) -> Result<Vec<Vec<usize>>, ShapeError> {
+        let mut shapes: Vec<Vec<usize>> = Vec::new();
+        for (position, layer) in self.layers.iter().enumerate() {
+            let input_shape = shapes.last().map(|shape| &shape[..]).unwrap_or(&[]);
+            let shape = layer
+                .output_shape(input_shape)
+                .map_err(|error| error.at(position))?;
+            shapes.push(shape);
         }
-        let activations = act;
+        Ok(shapes)
+    }
 
-        // println!("------------------------------------");
-        // println!(
-        //     "Weights shape: {:?} \n Weights data: {:?} \n Biases shape: {:?} \n Biases data: {:?}",
-        //     weights.shape, weights.data, biases.shape, biases.data
-        // );
-        self.layers.push_back(Layer::TensorLayer {
-            weights,
-            biases,
-            activations,
-            result,
-        });
+    //Checks that every layer accepts the output of the one before it and returns the shape
+    //of a single output sample. Call after adding the layers and before training.
+    pub fn build(&self) -> Result<Vec<usize>, ShapeError> {
+        Ok(self.layer_shapes()?.pop().unwrap_or_default())
     }
 
-    //
+    //--------------------------------------------------------------Mode---------------------------------------------------------------------
+
+    //Training mode uses batch statistics and stochastic layers, evaluation mode is deterministic.
+    //Networks start in training mode.
+    pub fn set_training(&mut self, training: bool) {
+        self.training = training;
+        for layer in self.layers.iter_mut() {
+            layer.set_training(training);
+        }
+    }
+
+    pub fn is_training(&self) -> bool {
+        self.training
+    }
 
     //--------------------------------------------------------------Feed Forward / Back Propogation---------------------------------------------------------------------
 
     pub fn feed_forward(&mut self, input: Tensor) -> Tensor {
-        println!(
-            "Feeding forward {:?} with shape {:?}",
-            input.data, input.shape
-        );
-
-        let mut current_output = input.clone();
+        let mut current_output = input;
 
         for layer in &mut self.layers {
-            match layer {
-                Layer::TensorLayer {
-                    weights,
-                    biases,
-                    activations,
-                    result,
-                } => {
-                    // println!("Processing through Tensor Layer");
-
-                    // Perform matrix multiplication
-                    current_output = weights.multiply(&current_output);
-
-                    // Add the biases
-                    current_output.add(biases);
-
-                    // Apply the activation function
-                    current_output = current_output.map(activations.function);
-
-                    // Assign the computed output to the result
-                    *result = current_output.clone(); // Store the output in result
-                }
-                Layer::InputLayer { size } => {
-                    println!("Input Layer")
-                }
-            }
+            current_output = layer.forward(&current_output);
         }
 
         current_output
     }
 
+    //Loss on a batch: the loss of every element when the loss function keeps them
+    //(Reduction::None), otherwise the reduced value as a single element tensor.
+    //Runs in evaluation mode and restores the previous mode afterwards.
+    pub fn evaluate(&mut self, input: Tensor, targets: &Tensor) -> Tensor {
+        let training = self.training;
+        self.set_training(false);
+        let outputs = self.feed_forward(input);
+        self.set_training(training);
+
+        match self.loss.reduction() {
+            Reduction::None => self.loss.losses(&outputs, targets),
+            Reduction::Mean | Reduction::Sum => {
+                Tensor::from(vec![1], vec![self.loss.function(&outputs, targets)])
+            }
+        }
+    }
+
     pub fn back_propogate(&mut self, _input: &Tensor, targets: Tensor, learning_rate: f64) {
-        let mut outputs = self.feed_forward(_input.clone());
+        let outputs = self.feed_forward(_input.clone());
 
-        let init_activation = match self
-            .layers
-            .iter()
-            .rev()
-            .find(|layer| matches!(layer, Layer::TensorLayer { .. }))
-        {
-            Some(Layer::TensorLayer { activations, .. }) => activations,
-            _ => panic!("No TensorLayer found in the network"),
-        };
+        let mut delta = self.loss.derivative(&outputs, &targets);
 
-        //get all the results and put them into a vector for access during backprop
-        let results: Vec<Tensor> = self
-            .layers
-            .iter()
-            .rev()
-            .filter_map(|layer| match layer {
-                Layer::TensorLayer { result, .. } => Some(result.clone()),
-                _ => None,
-            })
-            .collect();
-        let mut delta = (MSE.derivative)(&outputs, &targets, init_activation);
-
-        for (i, layer) in self.layers.iter_mut().rev().enumerate() {
-            if let Layer::TensorLayer {
-                weights,
-                biases,
-                activations,
-                result,
-            } = layer
-            {
-                // Calculate delta for the next layer (if any)
-                if i < results.len() - 1 {
-                    // Update biases
-                    biases.subtract(&delta.multiply_scalar(learning_rate));
-
-                    // Calculate weight gradient
-                    let mut weight_gradient =
-                        results[i + 1].multiply(&delta.transpose()).transpose();
-
-                    // panic!(
-                    //     delta.shape, biases.shape, weights.shape, weight_gradient.shape
-                    // );
-
-                    assert_eq!(&weights.shape, &weight_gradient.shape);
-
-                    weights.subtract(&weight_gradient.multiply_scalar(learning_rate));
-
-                    delta = weights.transpose().multiply(&delta);
-                    delta = delta.multiply(&outputs.map(activations.derivative));
-                    outputs = results[i + 1].clone(); // Set outputs for the next layer
-                }
-            }
+        for layer in self.layers.iter_mut().rev() {
+            delta = layer.backward(&delta);
+        }
+
+        self.update(learning_rate);
+    }
+
+    //Gradient descent step on every layer, see Layer::update
+    fn update(&mut self, learning_rate: f64) {
+        for layer in self.layers.iter_mut() {
+            layer.update(learning_rate);
         }
     }
 
     pub fn train(&mut self, input: Tensor, targets: Tensor, epoch: usize, learning_rate: f64) {
+        self.set_training(true);
         for i in 0..epoch {
             println!("\n\n-------Current Epoch: {:?}-------", i);
 
@@ -178,28 +174,134 @@ impl CPUTensorNetwork {
         }
     }
 
-    //-------------------------------Debug Tools----------------------------------
-    pub fn print_network(&mut self) {
-        //targets => the correct value
+    //--------------------------------------------------------------Summary---------------------------------------------------------------------
 
-        for layer in self.layers.iter_mut() {
-            match layer {
-                Layer::InputLayer { size } => {
-                    println!("Input shape: {:?}", size);
-                }
-                Layer::TensorLayer {
-                    weights,
-                    biases,
-                    activations,
-                    result,
-                } => {
-                    println!("Layer weight shape: {:?}", weights.shape);
-                    println!("Layer biases shape: {:?}", biases.shape);
-                    println!("Activation: {:?} ", activations.name);
-                    println!("Layer result shape: {:?}", result.shape);
-                    println!("Layer result data: {:?}", result.data);
-                }
-            }
+    //Per-layer report of output shapes, parameter counts, FLOPs and memory for batches of
+    //batch_size samples. Print it for a Keras-style table.
+    pub fn summary(&self, batch_size: usize) -> Result<Summary, ShapeError> {
+        let shapes = self.layer_shapes()?;
+        let mut layers = Vec::new();
+
+        for (position, layer) in self.layers.iter().enumerate() {
+            let input_shape = if position == 0 {
+                &[][..]
+            } else {
+                &shapes[position - 1][..]
+            };
+            let output_shape = shapes[position].clone();
+            let trainable_parameters = layer.trainable_parameters();
+            let non_trainable_parameters = layer.non_trainable_parameters();
+            let activations = batch_size * output_shape.iter().product::<usize>();
+
+            layers.push(LayerSummary {
+                name: layer.name().to_string(),
+                flops: batch_size * layer.flops(input_shape),
+                memory: (trainable_parameters + non_trainable_parameters + activations)
+                    * size_of::<f64>(),
+                output_shape,
+                trainable_parameters,
+                non_trainable_parameters,
+            });
         }
+
+        Ok(Summary { batch_size, layers })
+    }
+
+    //-------------------------------Debug Tools----------------------------------
+    pub fn print_network(&self) {
+        match self.summary(1) {
+            Ok(summary) => println!("{}", summary),
+            Err(error) => println!("Invalid network: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{
+        activations::{IDENTITY, RELU},
+        conv::Conv2d,
+        dropout::Dropout,
+        layer::Flatten,
+        loss::MAE,
+        normalization::BatchNorm2d,
+    };
+
+    #[test]
+    fn evaluate_keeps_element_losses_without_reduction() {
+        let mut network = CPUTensorNetwork::new(2);
+        network.add_tensor_layer(3, IDENTITY);
+        let input = Tensor::random(vec![2, 4]);
+        let targets = Tensor::random(vec![3, 4]);
+
+        network.set_loss(MAE::new().with_reduction(Reduction::None));
+        let losses = network.evaluate(input.clone(), &targets);
+        assert_eq!(losses.shape, vec![3, 4]);
+
+        network.set_loss(MAE::new());
+        let mean = network.evaluate(input, &targets);
+        assert_eq!(mean.shape, vec![1]);
+        let expected = losses.data.iter().sum::<f64>() / 12.0;
+        assert!((mean.data[0] - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn evaluate_runs_in_evaluation_mode_and_restores_the_mode() {
+        let mut network = CPUTensorNetwork::new(2);
+        network.add_tensor_layer(6, IDENTITY);
+        network.add_layer(Dropout::new(0.5));
+        network.add_tensor_layer(3, IDENTITY);
+        network.set_loss(MAE::new());
+        let input = Tensor::random(vec![2, 4]);
+        let targets = Tensor::random(vec![3, 4]);
+
+        //Without dropout masks every call sees the same network
+        let first = network.evaluate(input.clone(), &targets);
+        let second = network.evaluate(input.clone(), &targets);
+        assert_eq!(first.data, second.data);
+        assert!(network.is_training());
+
+        network.set_training(false);
+        let outputs = network.feed_forward(input.clone());
+        assert_eq!(network.loss().function(&outputs, &targets), first.data[0]);
+        network.evaluate(input, &targets);
+        assert!(!network.is_training());
+    }
+
+    #[test]
+    fn summary_counts_parameters_and_prints_a_table() {
+        let mut network = CPUTensorNetwork::with_input_shape(vec![1, 4, 4]);
+        network.add_layer(Conv2d::new(1, 2, 3));
+        network.add_layer(BatchNorm2d::new(2));
+        network.add_layer(Flatten::new());
+        network.add_tensor_layer(3, RELU);
+
+        let summary = network.summary(2).unwrap();
+        let trainable: Vec<usize> = summary
+            .layers
+            .iter()
+            .map(|l| l.trainable_parameters)
+            .collect();
+        let non_trainable: Vec<usize> = summary
+            .layers
+            .iter()
+            .map(|l| l.non_trainable_parameters)
+            .collect();
+        //Conv 2 * 1 * 3 * 3 + 2, BatchNorm scale and shift, dense 3 * 8 + 3
+        assert_eq!(trainable, vec![0, 20, 4, 0, 27]);
+        assert_eq!(non_trainable, vec![0, 0, 4, 0, 0]);
+        assert_eq!(summary.trainable_parameters(), 51);
+        assert_eq!(summary.total_parameters(), 55);
+        assert_eq!(summary.layers[4].output_shape, vec![3]);
+        //Parameters and the batch of outputs, 8 bytes each
+        assert_eq!(summary.layers[1].memory, (20 + 2 * 8) * 8);
+
+        let table = summary.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "Batch size: 2");
+        assert!(lines[2].starts_with("Layer"));
+        assert!(lines[5].starts_with("Conv2d") && lines[5].contains("[2, 2, 2]"));
+        assert!(table.contains("Total params: 55\nTrainable params: 51\nNon-trainable params: 4"));
     }
 }
@@ -0,0 +1,235 @@
+use super::{
+    activations::Activation,
+    initializers::Initializer,
+    layer::{Layer, ShapeError},
+    tensor::Tensor,
+};
+
+//Fully connected layer: activation(weights * input + biases).
+//Samples are columns, so the input is [inputs, batch] and the output is [amount, batch].
+pub struct TensorLayer {
+    pub weights: Tensor,
+    pub biases: Tensor,
+    pub activations: Box<dyn Activation>,
+    pub result: Tensor,
+    input: Tensor,
+    //weights * input + biases, handed back to the activation in backward
+    pre_activation: Tensor,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+impl TensorLayer {
+    //Weights start from the initializer suited to the activation, biases from zero
+    pub fn new<A: Activation + 'static>(inputs: usize, amount: usize, act: A) -> TensorLayer {
+        let weights = act.default_initializer().initialize(vec![amount, inputs]);
+        let biases = TensorLayer::column(Initializer::Zeros.initialize(vec![amount]));
+
+        TensorLayer {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            bias_gradient: Tensor::new(biases.shape.clone()),
+            weights,
+            biases,
+            activations: Box::new(act),
+            result: Tensor::new(vec![amount]),
+            pre_activation: Tensor::new(vec![amount]),
+            input: Tensor::new(vec![inputs]),
+        }
+    }
+
+    pub fn with_initializer(mut self, initializer: Initializer) -> TensorLayer {
+        self.weights = initializer.initialize(self.weights.shape.clone());
+        self
+    }
+
+    pub fn with_bias_initializer(mut self, initializer: Initializer) -> TensorLayer {
+        self.biases = TensorLayer::column(initializer.initialize(vec![self.biases.shape[0]]));
+        self
+    }
+
+    //Biases are stored as [amount, 1] so they broadcast over the batch columns
+    fn column(mut biases: Tensor) -> Tensor {
+        biases.increase_dim(1);
+        biases
+    }
+}
+
+impl Layer for TensorLayer {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        // Perform matrix multiplication
+        let mut current_output = self.weights.multiply(input);
+
+        // Add the biases
+        current_output.add(&self.biases);
+
+        // Apply the activation function
+        self.pre_activation = current_output;
+        let current_output = self.activations.forward(&self.pre_activation);
+
+        self.input = input.clone();
+        self.result = current_output.clone();
+        current_output
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        //Chain through the activation at the values it was applied to
+        let delta = self
+            .activations
+            .backward(&self.pre_activation, &self.result, grad_output);
+
+        self.weight_gradient = delta.multiply(&self.input.transpose());
+
+        //Biases are shared by every sample in the batch
+        let (rows, cols) = (delta.shape[0], delta.shape[1]);
+        for i in 0..rows {
+            self.bias_gradient.data[i] = delta.data[i * cols..(i + 1) * cols].iter().sum();
+        }
+
+        self.weights.transpose().multiply(&delta)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        let mut parameters = vec![&mut self.weights, &mut self.biases];
+        parameters.extend(self.activations.parameters());
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        let mut gradients = vec![&self.weight_gradient, &self.bias_gradient];
+        gradients.extend(self.activations.gradients());
+        gradients
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let inputs = self.weights.shape[1];
+        if input_shape != [inputs] {
+            return Err(ShapeError::new(
+                self.name(),
+                format!("[{}]", inputs),
+                input_shape,
+            ));
+        }
+        Ok(vec![self.weights.shape[0]])
+    }
+
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        let (outputs, inputs) = (self.weights.shape[0], self.weights.shape[1]);
+        2 * outputs * inputs + 2 * outputs
+    }
+
+    fn name(&self) -> &str {
+        "TensorLayer"
+    }
+}
+
+//--------------------------------------------------------------Maxout---------------------------------------------------------------------
+
+//Each unit outputs the largest of `pieces` learned affine functions of the input, a learnable
+//piecewise linear activation. Like TensorLayer it maps [inputs, batch] columns to [units, batch].
+pub struct Maxout {
+    //Rows u * pieces .. (u + 1) * pieces belong to unit u
+    pub weights: Tensor,
+    pub biases: Tensor,
+    pieces: usize,
+    input: Tensor,
+    //Row of the winning piece for every output element
+    argmax: Vec<usize>,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+impl Maxout {
+    pub fn new(inputs: usize, units: usize, pieces: usize) -> Maxout {
+        assert!(pieces > 0, "Maxout needs at least one piece per unit");
+        let weights = Initializer::XavierUniform.initialize(vec![units * pieces, inputs]);
+        let biases = TensorLayer::column(Initializer::Zeros.initialize(vec![units * pieces]));
+
+        Maxout {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            bias_gradient: Tensor::new(biases.shape.clone()),
+            weights,
+            biases,
+            pieces,
+            input: Tensor::new(vec![inputs]),
+            argmax: Vec::new(),
+        }
+    }
+
+    pub fn with_initializer(mut self, initializer: Initializer) -> Maxout {
+        self.weights = initializer.initialize(self.weights.shape.clone());
+        self
+    }
+}
+
+impl Layer for Maxout {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let mut pieces = self.weights.multiply(input);
+        pieces.add(&self.biases);
+
+        let units = self.weights.shape[0] / self.pieces;
+        let batch = input.shape[1];
+        let mut output = Tensor::new(vec![units, batch]);
+        self.argmax = vec![0; units * batch];
+
+        for u in 0..units {
+            for n in 0..batch {
+                let rows = u * self.pieces..(u + 1) * self.pieces;
+                let best = rows
+                    .max_by(|&a, &b| {
+                        pieces.data[a * batch + n].total_cmp(&pieces.data[b * batch + n])
+                    })
+                    .unwrap();
+                output.data[u * batch + n] = pieces.data[best * batch + n];
+                self.argmax[u * batch + n] = best;
+            }
+        }
+
+        self.input = input.clone();
+        output
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        //Only the winning piece of each unit saw the input
+        let batch = grad_output.shape[1];
+        let mut delta = Tensor::new(vec![self.weights.shape[0], batch]);
+        for (index, &row) in self.argmax.iter().enumerate() {
+            delta.data[row * batch + index % batch] = grad_output.data[index];
+        }
+
+        self.weight_gradient = delta.multiply(&self.input.transpose());
+        for i in 0..delta.shape[0] {
+            self.bias_gradient.data[i] = delta.data[i * batch..(i + 1) * batch].iter().sum();
+        }
+
+        self.weights.transpose().multiply(&delta)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.weights, &mut self.biases]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.weight_gradient, &self.bias_gradient]
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let inputs = self.weights.shape[1];
+        if input_shape != [inputs] {
+            return Err(ShapeError::new(
+                self.name(),
+                format!("[{}]", inputs),
+                input_shape,
+            ));
+        }
+        Ok(vec![self.weights.shape[0] / self.pieces])
+    }
+
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        let (rows, inputs) = (self.weights.shape[0], self.weights.shape[1]);
+        2 * rows * inputs + 2 * rows
+    }
+
+    fn name(&self) -> &str {
+        "Maxout"
+    }
+}
@@ -0,0 +1,437 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::{standard_normal, Tensor},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+//Stochastic regularization layers. They only act in training mode and pass their input
+//through unchanged in evaluation mode. Each one owns its random generator, so a layer
+//built with with_seed produces the same sequence of masks on every run.
+
+//--------------------------------------------------------------Dropout---------------------------------------------------------------------
+
+//Zeroes each element with probability p and scales the survivors by 1 / (1 - p)
+//(inverted dropout), so nothing needs rescaling at evaluation time
+pub struct Dropout {
+    p: f64,
+    rng: StdRng,
+    training: bool,
+    mask: Tensor,
+}
+
+impl Dropout {
+    pub fn new(p: f64) -> Dropout {
+        assert!(
+            (0.0..1.0).contains(&p),
+            "Dropout probability must be in [0, 1), got {}",
+            p
+        );
+        Dropout {
+            p,
+            rng: StdRng::from_entropy(),
+            training: true,
+            mask: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Dropout {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl Layer for Dropout {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        if !self.training {
+            return input.clone();
+        }
+
+        let scale = 1.0 / (1.0 - self.p);
+        self.mask = Tensor::new(input.shape.clone());
+        for m in self.mask.data.iter_mut() {
+            *m = if self.rng.gen::<f64>() < self.p {
+                0.0
+            } else {
+                scale
+            };
+        }
+
+        input.dot(&self.mask)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        if !self.training {
+            return grad_output.clone();
+        }
+        grad_output.dot(&self.mask)
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(input_shape.to_vec())
+    }
+
+    fn name(&self) -> &str {
+        "Dropout"
+    }
+}
+
+//--------------------------------------------------------------Dropout2d---------------------------------------------------------------------
+
+//Drops whole channels of a [N, C, *] tensor. Neighbouring pixels of a feature map are
+//strongly correlated, so element-wise dropout barely regularizes convolutional layers.
+pub struct Dropout2d {
+    p: f64,
+    rng: StdRng,
+    training: bool,
+    mask: Tensor,
+}
+
+impl Dropout2d {
+    pub fn new(p: f64) -> Dropout2d {
+        assert!(
+            (0.0..1.0).contains(&p),
+            "Dropout probability must be in [0, 1), got {}",
+            p
+        );
+        Dropout2d {
+            p,
+            rng: StdRng::from_entropy(),
+            training: true,
+            mask: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Dropout2d {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl Layer for Dropout2d {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        if !self.training {
+            return input.clone();
+        }
+        assert!(
+            input.shape.len() >= 3,
+            "Dropout2d expects [N, C, ...], got {:?}",
+            input.shape
+        );
+
+        let scale = 1.0 / (1.0 - self.p);
+        let spatial: usize = input.shape[2..].iter().product();
+        self.mask = Tensor::new(input.shape.clone());
+        for channel in self.mask.data.chunks_mut(spatial) {
+            let keep = if self.rng.gen::<f64>() < self.p {
+                0.0
+            } else {
+                scale
+            };
+            channel.fill(keep);
+        }
+
+        input.dot(&self.mask)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        if !self.training {
+            return grad_output.clone();
+        }
+        grad_output.dot(&self.mask)
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.len() < 2 {
+            return Err(ShapeError::new(self.name(), "[C, *spatial]", input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn name(&self) -> &str {
+        "Dropout2d"
+    }
+}
+
+//--------------------------------------------------------------AlphaDropout---------------------------------------------------------------------
+
+//Dropout for self-normalizing (SELU) networks. Dropped elements are set to SELU's negative
+//saturation value instead of 0 and an affine correction keeps the mean and variance
+//of the activations unchanged.
+pub struct AlphaDropout {
+    p: f64,
+    rng: StdRng,
+    training: bool,
+    //1.0 where the element was kept, 0.0 where it was dropped
+    keep: Tensor,
+}
+
+//-scale * alpha of SELU
+const SELU_SATURATION: f64 = -1.7580993408473766;
+
+impl AlphaDropout {
+    pub fn new(p: f64) -> AlphaDropout {
+        assert!(
+            (0.0..1.0).contains(&p),
+            "Dropout probability must be in [0, 1), got {}",
+            p
+        );
+        AlphaDropout {
+            p,
+            rng: StdRng::from_entropy(),
+            training: true,
+            keep: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> AlphaDropout {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    //Affine correction a * x + b restoring zero mean and unit variance
+    fn correction(&self) -> (f64, f64) {
+        let q = 1.0 - self.p;
+        let a = 1.0 / (q + SELU_SATURATION.powi(2) * q * self.p).sqrt();
+        (a, -a * SELU_SATURATION * self.p)
+    }
+}
+
+impl Layer for AlphaDropout {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        if !self.training {
+            return input.clone();
+        }
+
+        let (a, b) = self.correction();
+        self.keep = Tensor::new(input.shape.clone());
+        for k in self.keep.data.iter_mut() {
+            *k = if self.rng.gen::<f64>() < self.p {
+                0.0
+            } else {
+                1.0
+            };
+        }
+
+        let mut res = Tensor::new(input.shape.clone());
+        for (i, value) in res.data.iter_mut().enumerate() {
+            let dropped = if self.keep.data[i] == 1.0 {
+                input.data[i]
+            } else {
+                SELU_SATURATION
+            };
+            *value = a * dropped + b;
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        if !self.training {
+            return grad_output.clone();
+        }
+        let (a, _) = self.correction();
+        let mut grad_input = grad_output.dot(&self.keep);
+        grad_input.multiply_scalar(a)
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(input_shape.to_vec())
+    }
+
+    fn name(&self) -> &str {
+        "AlphaDropout"
+    }
+}
+
+//--------------------------------------------------------------GaussianNoise---------------------------------------------------------------------
+
+//Adds zero-mean gaussian noise with the given standard deviation. The noise does not depend
+//on the input, so the gradient passes through unchanged.
+pub struct GaussianNoise {
+    stddev: f64,
+    rng: StdRng,
+    training: bool,
+}
+
+impl GaussianNoise {
+    pub fn new(stddev: f64) -> GaussianNoise {
+        assert!(
+            stddev >= 0.0,
+            "Standard deviation must be non-negative, got {}",
+            stddev
+        );
+        GaussianNoise {
+            stddev,
+            rng: StdRng::from_entropy(),
+            training: true,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> GaussianNoise {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+}
+
+impl Layer for GaussianNoise {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        if !self.training {
+            return input.clone();
+        }
+
+        let mut res = input.clone();
+        for value in res.data.iter_mut() {
+            *value += self.stddev * standard_normal(&mut self.rng);
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        grad_output.clone()
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(input_shape.to_vec())
+    }
+
+    fn name(&self) -> &str {
+        "GaussianNoise"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ones(shape: Vec<usize>) -> Tensor {
+        let size = shape.iter().product();
+        Tensor::from(shape, vec![1.0; size])
+    }
+
+    fn mean_and_variance(data: &[f64]) -> (f64, f64) {
+        let n = data.len() as f64;
+        let mean = data.iter().sum::<f64>() / n;
+        let variance = data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        (mean, variance)
+    }
+
+    //Same seed, same input => same output, and evaluation mode is the identity
+    fn assert_seeded_and_identity_in_eval(mut a: Box<dyn Layer>, mut b: Box<dyn Layer>) {
+        let input = Tensor::random(vec![4, 3, 5]);
+        let out_a = a.forward(&input);
+        let out_b = b.forward(&input);
+        assert_eq!(out_a.data, out_b.data, "{} is not reproducible", a.name());
+        assert_ne!(
+            out_a.data,
+            input.data,
+            "{} did nothing in training",
+            a.name()
+        );
+
+        a.set_training(false);
+        assert_eq!(a.forward(&input).data, input.data);
+        let grad = Tensor::random(input.shape.clone());
+        assert_eq!(a.backward(&grad).data, grad.data);
+    }
+
+    #[test]
+    fn same_seed_gives_same_output_and_eval_is_identity() {
+        assert_seeded_and_identity_in_eval(
+            Box::new(Dropout::new(0.5).with_seed(7)),
+            Box::new(Dropout::new(0.5).with_seed(7)),
+        );
+        assert_seeded_and_identity_in_eval(
+            Box::new(Dropout2d::new(0.5).with_seed(7)),
+            Box::new(Dropout2d::new(0.5).with_seed(7)),
+        );
+        assert_seeded_and_identity_in_eval(
+            Box::new(AlphaDropout::new(0.5).with_seed(7)),
+            Box::new(AlphaDropout::new(0.5).with_seed(7)),
+        );
+        assert_seeded_and_identity_in_eval(
+            Box::new(GaussianNoise::new(0.5).with_seed(7)),
+            Box::new(GaussianNoise::new(0.5).with_seed(7)),
+        );
+    }
+
+    #[test]
+    fn dropout_backward_applies_the_forward_mask() {
+        let mut dropout = Dropout::new(0.3).with_seed(1);
+        let mask = dropout.forward(&ones(vec![6, 50]));
+        assert!(mask.data.iter().all(|&m| m == 0.0 || m == 1.0 / 0.7));
+        assert!(mask.data.contains(&0.0));
+
+        let grad = Tensor::random(vec![6, 50]);
+        assert_eq!(dropout.backward(&grad).data, grad.dot(&mask).data);
+    }
+
+    #[test]
+    fn dropout2d_zeroes_whole_channels() {
+        let mut dropout = Dropout2d::new(0.5).with_seed(3);
+        let mask = dropout.forward(&ones(vec![4, 6, 3, 3]));
+        let mut dropped = 0;
+        for channel in mask.data.chunks(9) {
+            assert!(channel.iter().all(|&m| m == channel[0]));
+            assert!(channel[0] == 0.0 || channel[0] == 2.0);
+            if channel[0] == 0.0 {
+                dropped += 1;
+            }
+        }
+        assert!(dropped > 0 && dropped < 24);
+
+        let grad = Tensor::random(vec![4, 6, 3, 3]);
+        assert_eq!(dropout.backward(&grad).data, grad.dot(&mask).data);
+    }
+
+    #[test]
+    fn alpha_dropout_keeps_mean_and_variance() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let data: Vec<f64> = (0..20000).map(|_| standard_normal(&mut rng)).collect();
+        let input = Tensor::from(vec![20000], data);
+
+        let mut dropout = AlphaDropout::new(0.2).with_seed(5);
+        let output = dropout.forward(&input);
+        let (mean, variance) = mean_and_variance(&output.data);
+        assert!(mean.abs() < 0.05, "mean {}", mean);
+        assert!((variance - 1.0).abs() < 0.05, "variance {}", variance);
+
+        //Dropped elements get a constant value, so they pass no gradient
+        let (a, _) = dropout.correction();
+        let grad = dropout.backward(&ones(vec![20000]));
+        for (i, g) in grad.data.iter().enumerate() {
+            let expected = if dropout.keep.data[i] == 1.0 { a } else { 0.0 };
+            assert_eq!(*g, expected);
+        }
+    }
+
+    #[test]
+    fn gaussian_noise_has_the_given_stddev_and_passes_gradients() {
+        let mut noise = GaussianNoise::new(0.5).with_seed(2);
+        let output = noise.forward(&Tensor::new(vec![20000]));
+        let (mean, variance) = mean_and_variance(&output.data);
+        assert!(mean.abs() < 0.02, "mean {}", mean);
+        assert!(
+            (variance.sqrt() - 0.5).abs() < 0.02,
+            "stddev {}",
+            variance.sqrt()
+        );
+
+        let grad = Tensor::random(vec![20000]);
+        assert_eq!(noise.backward(&grad).data, grad.data);
+    }
+}
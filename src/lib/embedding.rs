@@ -0,0 +1,343 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::Tensor,
+};
+
+//Lookup table turning integer ids into dense vectors. The input holds the ids as whole
+//numbers in any shape [*], the output is [*, dim] with one table row per id.
+pub struct Embedding {
+    pub weights: Tensor,
+    padding_idx: Option<usize>,
+    max_norm: Option<f64>,
+    frozen: bool,
+    indices: Vec<usize>,
+    input_shape: Vec<usize>,
+    weight_gradient: Tensor,
+    //Rows of weight_gradient written by the last backward call, sorted and unique
+    touched: Vec<usize>,
+}
+
+impl Embedding {
+    pub fn new(num_embeddings: usize, dim: usize) -> Embedding {
+        Embedding::from_pretrained(Tensor::random(vec![num_embeddings, dim]))
+    }
+
+    //Uses an existing [num_embeddings, dim] matrix as the table, e.g. word vectors trained elsewhere
+    pub fn from_pretrained(weights: Tensor) -> Embedding {
+        assert!(
+            weights.shape.len() == 2,
+            "Embedding table must be [num_embeddings, dim], got {:?}",
+            weights.shape
+        );
+        Embedding {
+            weight_gradient: Tensor::new(weights.shape.clone()),
+            weights,
+            padding_idx: None,
+            max_norm: None,
+            frozen: false,
+            indices: Vec::new(),
+            input_shape: Vec::new(),
+            touched: Vec::new(),
+        }
+    }
+
+    //The padding row starts at zero and never receives a gradient
+    pub fn with_padding_idx(mut self, padding_idx: usize) -> Embedding {
+        assert!(
+            padding_idx < self.num_embeddings(),
+            "Padding index {} out of range for {} embeddings",
+            padding_idx,
+            self.num_embeddings()
+        );
+        let dim = self.dim();
+        self.weights.data[padding_idx * dim..(padding_idx + 1) * dim].fill(0.0);
+        self.padding_idx = Some(padding_idx);
+        self
+    }
+
+    //Rows looked up in forward are rescaled in place so their L2 norm is at most max_norm
+    pub fn with_max_norm(mut self, max_norm: f64) -> Embedding {
+        assert!(
+            max_norm > 0.0,
+            "Max norm must be positive, got {}",
+            max_norm
+        );
+        self.max_norm = Some(max_norm);
+        self
+    }
+
+    //A frozen table exposes no parameters, so the network never updates it
+    pub fn frozen(mut self) -> Embedding {
+        self.frozen = true;
+        self
+    }
+
+    pub fn num_embeddings(&self) -> usize {
+        self.weights.shape[0]
+    }
+
+    pub fn dim(&self) -> usize {
+        self.weights.shape[1]
+    }
+
+    //Non-zero rows of the last weight gradient, as (row, gradient) pairs. update only
+    //steps these rows, so a batch costs O(ids * dim) instead of the whole table.
+    pub fn sparse_gradient(&self) -> Vec<(usize, &[f64])> {
+        let dim = self.dim();
+        self.touched
+            .iter()
+            .map(|&row| (row, &self.weight_gradient.data[row * dim..(row + 1) * dim]))
+            .collect()
+    }
+
+    fn renormalize(&mut self, max_norm: f64) {
+        let dim = self.dim();
+        let mut rows = self.indices.clone();
+        rows.sort_unstable();
+        rows.dedup();
+
+        for row in rows {
+            let values = &mut self.weights.data[row * dim..(row + 1) * dim];
+            let norm = values.iter().map(|v| v * v).sum::<f64>().sqrt();
+            if norm > max_norm {
+                let scale = max_norm / (norm + 1e-7);
+                values.iter_mut().for_each(|v| *v *= scale);
+            }
+        }
+    }
+}
+
+impl Layer for Embedding {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let num_embeddings = self.num_embeddings();
+        self.indices = input
+            .data
+            .iter()
+            .map(|&id| {
+                assert!(
+                    id >= 0.0 && id.fract() == 0.0 && (id as usize) < num_embeddings,
+                    "Embedding index {} is not an id in 0..{}",
+                    id,
+                    num_embeddings
+                );
+                id as usize
+            })
+            .collect();
+        self.input_shape = input.shape.clone();
+
+        if let Some(max_norm) = self.max_norm {
+            self.renormalize(max_norm);
+        }
+
+        let dim = self.dim();
+        let mut shape = input.shape.clone();
+        shape.push(dim);
+        let mut res = Tensor::new(shape);
+        for (out, &row) in res.data.chunks_mut(dim).zip(&self.indices) {
+            out.copy_from_slice(&self.weights.data[row * dim..(row + 1) * dim]);
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let dim = self.dim();
+
+        //Only the rows written last time can be non-zero, so clearing them is enough
+        for &row in &self.touched {
+            self.weight_gradient.data[row * dim..(row + 1) * dim].fill(0.0);
+        }
+
+        //Repeated ids accumulate into the same row
+        for (grad, &row) in grad_output.data.chunks(dim).zip(&self.indices) {
+            if Some(row) == self.padding_idx {
+                continue;
+            }
+            let target = &mut self.weight_gradient.data[row * dim..(row + 1) * dim];
+            for (t, g) in target.iter_mut().zip(grad) {
+                *t += g;
+            }
+        }
+
+        self.touched = self
+            .indices
+            .iter()
+            .copied()
+            .filter(|&row| Some(row) != self.padding_idx)
+            .collect();
+        self.touched.sort_unstable();
+        self.touched.dedup();
+
+        //Ids are not differentiable
+        Tensor::new(self.input_shape.clone())
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        if self.frozen {
+            return Vec::new();
+        }
+        vec![&mut self.weights]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        if self.frozen {
+            return Vec::new();
+        }
+        vec![&self.weight_gradient]
+    }
+
+    fn update(&mut self, learning_rate: f64) {
+        if self.frozen {
+            return;
+        }
+        let dim = self.dim();
+        for &row in &self.touched {
+            let range = row * dim..(row + 1) * dim;
+            for (w, g) in self.weights.data[range.clone()]
+                .iter_mut()
+                .zip(&self.weight_gradient.data[range])
+            {
+                *w -= learning_rate * g;
+            }
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let mut shape = input_shape.to_vec();
+        shape.push(self.dim());
+        Ok(shape)
+    }
+
+    fn non_trainable_parameters(&self) -> usize {
+        if self.frozen {
+            self.weights.data.len()
+        } else {
+            0
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Embedding"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(shape: Vec<usize>, ids: &[usize]) -> Tensor {
+        Tensor::from(shape, ids.iter().map(|&id| id as f64).collect())
+    }
+
+    fn row(table: &Tensor, row: usize) -> &[f64] {
+        let dim = table.shape[1];
+        &table.data[row * dim..(row + 1) * dim]
+    }
+
+    #[test]
+    fn forward_looks_up_pretrained_rows() {
+        let table = Tensor::from(vec![3, 2], vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut embedding = Embedding::from_pretrained(table);
+        let output = embedding.forward(&ids(vec![2, 2], &[2, 0, 1, 2]));
+        assert_eq!(output.shape, vec![2, 2, 2]);
+        assert_eq!(output.data, vec![5.0, 6.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn repeated_ids_accumulate() {
+        let mut embedding = Embedding::new(5, 3);
+        embedding.forward(&ids(vec![4], &[1, 3, 1, 1]));
+        let grad = Tensor::random(vec![4, 3]);
+        embedding.backward(&grad);
+
+        let gradient = embedding.gradients()[0];
+        for d in 0..3 {
+            let expected = grad.data[d] + grad.data[6 + d] + grad.data[9 + d];
+            assert!((row(gradient, 1)[d] - expected).abs() < 1e-12);
+            assert_eq!(row(gradient, 3)[d], grad.data[3 + d]);
+        }
+        assert!(row(gradient, 0).iter().all(|&g| g == 0.0));
+
+        let sparse = embedding.sparse_gradient();
+        assert_eq!(sparse.len(), 2);
+        assert_eq!((sparse[0].0, sparse[1].0), (1, 3));
+        assert_eq!(sparse[0].1, row(gradient, 1));
+    }
+
+    #[test]
+    fn stale_rows_are_cleared_between_batches() {
+        let mut embedding = Embedding::new(4, 2);
+        embedding.forward(&ids(vec![1], &[0]));
+        embedding.backward(&Tensor::from(vec![1, 2], vec![1.0, 1.0]));
+        embedding.forward(&ids(vec![1], &[2]));
+        embedding.backward(&Tensor::from(vec![1, 2], vec![1.0, 1.0]));
+
+        assert!(row(embedding.gradients()[0], 0).iter().all(|&g| g == 0.0));
+        assert_eq!(embedding.sparse_gradient().len(), 1);
+    }
+
+    #[test]
+    fn padding_row_gets_no_gradient() {
+        let mut embedding = Embedding::new(4, 3).with_padding_idx(0);
+        assert!(row(&embedding.weights, 0).iter().all(|&w| w == 0.0));
+
+        embedding.forward(&ids(vec![2, 2], &[0, 2, 0, 0]));
+        embedding.backward(&Tensor::random(vec![2, 2, 3]));
+        assert!(row(embedding.gradients()[0], 0).iter().all(|&g| g == 0.0));
+        assert_eq!(embedding.sparse_gradient().len(), 1);
+
+        embedding.update(0.5);
+        assert!(row(&embedding.weights, 0).iter().all(|&w| w == 0.0));
+    }
+
+    #[test]
+    fn update_steps_only_the_touched_rows() {
+        let mut embedding = Embedding::new(6, 2);
+        let before = embedding.weights.clone();
+        embedding.forward(&ids(vec![3], &[4, 1, 4]));
+        embedding.backward(&Tensor::random(vec![3, 2]));
+        embedding.update(0.1);
+
+        for r in 0..6 {
+            let gradient = row(&embedding.weight_gradient, r);
+            for ((w, b), g) in row(&embedding.weights, r)
+                .iter()
+                .zip(row(&before, r))
+                .zip(gradient)
+            {
+                assert_eq!(*w, b - 0.1 * g);
+            }
+            if r != 1 && r != 4 {
+                assert_eq!(row(&embedding.weights, r), row(&before, r));
+            }
+        }
+    }
+
+    #[test]
+    fn max_norm_renormalizes_looked_up_rows() {
+        let table = Tensor::from(vec![3, 2], vec![3.0, 4.0, 0.3, 0.4, 6.0, 8.0]);
+        let mut embedding = Embedding::from_pretrained(table).with_max_norm(1.0);
+        let output = embedding.forward(&ids(vec![2], &[0, 1]));
+
+        for (actual, expected) in output.data.iter().zip([0.6, 0.8, 0.3, 0.4]) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+        assert!((row(&embedding.weights, 0)[0] - 0.6).abs() < 1e-6);
+        assert_eq!(row(&embedding.weights, 1), &[0.3, 0.4]);
+        //Rows that were not looked up keep their norm
+        assert_eq!(row(&embedding.weights, 2), &[6.0, 8.0]);
+    }
+
+    #[test]
+    fn frozen_table_is_not_trained() {
+        let table = Tensor::random(vec![4, 3]);
+        let mut embedding = Embedding::from_pretrained(table.clone()).frozen();
+        assert!(embedding.parameters().is_empty());
+        assert!(embedding.gradients().is_empty());
+        assert_eq!(embedding.non_trainable_parameters(), 12);
+
+        embedding.forward(&ids(vec![2], &[1, 3]));
+        embedding.backward(&Tensor::random(vec![2, 3]));
+        embedding.update(1.0);
+        assert_eq!(embedding.weights.data, table.data);
+    }
+}
@@ -0,0 +1,576 @@
+use super::{complex::Complex, tensor::Tensor};
+use std::f64::consts::PI;
+
+//Fast Fourier transforms over the trailing axes of a Tensor.
+//Power of two lengths use an iterative radix-2 Cooley-Tukey transform,
+//every other length goes through Bluestein's algorithm so any size is supported.
+
+//--------------------------------------------------------------Complex Transforms---------------------------------------------------------------------
+
+//1-D FFT along the last axis, batched over all leading axes
+pub fn fft(input: &Tensor<Complex>) -> Tensor<Complex> {
+    assert!(!input.shape.is_empty(), "Cannot take the FFT of a scalar");
+    transform_axis(input, input.shape.len() - 1, false)
+}
+
+//Inverse of fft, normalized by 1/n so that ifft(fft(x)) == x
+pub fn ifft(input: &Tensor<Complex>) -> Tensor<Complex> {
+    assert!(!input.shape.is_empty(), "Cannot take the FFT of a scalar");
+    transform_axis(input, input.shape.len() - 1, true)
+}
+
+//2-D FFT over the last two axes
+pub fn fft2(input: &Tensor<Complex>) -> Tensor<Complex> {
+    let rank = input.shape.len();
+    assert!(
+        rank >= 2,
+        "fft2 needs at least 2 dimensions, got {:?}",
+        input.shape
+    );
+    let rows = transform_axis(input, rank - 1, false);
+    transform_axis(&rows, rank - 2, false)
+}
+
+pub fn ifft2(input: &Tensor<Complex>) -> Tensor<Complex> {
+    let rank = input.shape.len();
+    assert!(
+        rank >= 2,
+        "ifft2 needs at least 2 dimensions, got {:?}",
+        input.shape
+    );
+    let rows = transform_axis(input, rank - 1, true);
+    transform_axis(&rows, rank - 2, true)
+}
+
+//--------------------------------------------------------------Real Transforms---------------------------------------------------------------------
+
+//FFT of a real signal along the last axis.
+//Only the n / 2 + 1 non-redundant frequencies are returned since the rest are conjugates.
+pub fn rfft(input: &Tensor) -> Tensor<Complex> {
+    let full = fft(&Tensor::from_real(input));
+    let n = *full.shape.last().unwrap();
+    truncate_last_axis(&full, n / 2 + 1)
+}
+
+//Inverse of rfft. `n` is the length of the original real signal,
+//which can not be recovered from the half spectrum alone.
+pub fn irfft(input: &Tensor<Complex>, n: usize) -> Tensor {
+    assert!(!input.shape.is_empty(), "Cannot take the FFT of a scalar");
+    let bins = *input.shape.last().unwrap();
+    assert!(
+        bins == n / 2 + 1,
+        "Expected {} frequency bins for a signal of length {}, got {}",
+        n / 2 + 1,
+        n,
+        bins
+    );
+
+    //Rebuild the full Hermitian spectrum from the half spectrum
+    let lanes = input.data.len() / bins;
+    let mut shape = input.shape.clone();
+    *shape.last_mut().unwrap() = n;
+    let mut full = Tensor::new_complex(shape);
+
+    for lane in 0..lanes {
+        let half = &input.data[lane * bins..(lane + 1) * bins];
+        let out = &mut full.data[lane * n..(lane + 1) * n];
+        out[..bins].copy_from_slice(half);
+        for k in bins..n {
+            out[k] = half[n - k].conj();
+        }
+    }
+
+    ifft(&full).real()
+}
+
+//--------------------------------------------------------------Short-Time Fourier Transform---------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    //Periodic window coefficients, the form used for spectral analysis
+    pub fn coefficients(&self, size: usize) -> Vec<f64> {
+        let n = size as f64;
+        (0..size)
+            .map(|i| {
+                let x = 2.0 * PI * i as f64 / n;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * x.cos(),
+                    Window::Hamming => 0.54 - 0.46 * x.cos(),
+                    Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+//Short-time Fourier transform of a real signal along its last axis.
+//Frames of n_fft samples are taken every hop_length samples, windowed and transformed.
+//A [L] signal gives a [n_fft / 2 + 1, frames] spectrogram, leading axes are kept as batch axes.
+pub fn stft(signal: &Tensor, n_fft: usize, hop_length: usize, window: Window) -> Tensor<Complex> {
+    assert!(!signal.shape.is_empty(), "Cannot take the STFT of a scalar");
+    assert!(
+        n_fft > 0 && hop_length > 0,
+        "n_fft and hop_length must be positive"
+    );
+
+    let length = *signal.shape.last().unwrap();
+    assert!(
+        length >= n_fft,
+        "Signal of length {} is shorter than n_fft {}",
+        length,
+        n_fft
+    );
+
+    let frames = 1 + (length - n_fft) / hop_length;
+    let bins = n_fft / 2 + 1;
+    let lanes = signal.data.len() / length;
+    let coefficients = window.coefficients(n_fft);
+
+    let mut shape = signal.shape[..signal.shape.len() - 1].to_vec();
+    shape.push(bins);
+    shape.push(frames);
+    let mut res = Tensor::new_complex(shape);
+
+    let mut frame = vec![Complex::ZERO; n_fft];
+    for lane in 0..lanes {
+        let samples = &signal.data[lane * length..(lane + 1) * length];
+        for f in 0..frames {
+            let start = f * hop_length;
+            for i in 0..n_fft {
+                frame[i] = Complex::from(samples[start + i] * coefficients[i]);
+            }
+            transform(&mut frame, false);
+            for (k, value) in frame.iter().take(bins).enumerate() {
+                res.data[lane * bins * frames + k * frames + f] = *value;
+            }
+        }
+    }
+
+    res
+}
+
+//--------------------------------------------------------------Convolution---------------------------------------------------------------------
+
+//Full linear convolution of every lane along the last axis of `signal` with a 1-D kernel.
+//Cheaper than direct convolution once the kernel gets large: O(n log n) instead of O(n * k).
+pub fn fft_convolve(signal: &Tensor, kernel: &Tensor) -> Tensor {
+    assert!(!signal.shape.is_empty(), "Cannot convolve a scalar");
+    assert!(
+        kernel.shape.len() == 1,
+        "fft_convolve expects a 1-D kernel, got {:?}",
+        kernel.shape
+    );
+
+    let length = *signal.shape.last().unwrap();
+    let out_length = length + kernel.data.len() - 1;
+    let size = out_length.next_power_of_two();
+    let lanes = signal.data.len() / length;
+
+    let mut kernel_spectrum = vec![Complex::ZERO; size];
+    for (i, &k) in kernel.data.iter().enumerate() {
+        kernel_spectrum[i] = Complex::from(k);
+    }
+    transform(&mut kernel_spectrum, false);
+
+    let mut shape = signal.shape.clone();
+    *shape.last_mut().unwrap() = out_length;
+    let mut res = Tensor::new(shape);
+
+    let mut buffer = vec![Complex::ZERO; size];
+    for lane in 0..lanes {
+        buffer.fill(Complex::ZERO);
+        let samples = &signal.data[lane * length..(lane + 1) * length];
+        for (b, &x) in buffer.iter_mut().zip(samples.iter()) {
+            *b = Complex::from(x);
+        }
+        transform(&mut buffer, false);
+        for (b, &k) in buffer.iter_mut().zip(kernel_spectrum.iter()) {
+            *b = *b * k;
+        }
+        transform(&mut buffer, true);
+        let out = &mut res.data[lane * out_length..(lane + 1) * out_length];
+        for (o, b) in out.iter_mut().zip(buffer.iter()) {
+            *o = b.re;
+        }
+    }
+
+    res
+}
+
+//Full 2-D linear convolution over the last two axes of `input` with a [kh, kw] kernel
+pub fn fft_convolve2d(input: &Tensor, kernel: &Tensor) -> Tensor {
+    let rank = input.shape.len();
+    assert!(
+        rank >= 2,
+        "fft_convolve2d needs at least 2 dimensions, got {:?}",
+        input.shape
+    );
+    assert!(
+        kernel.shape.len() == 2,
+        "fft_convolve2d expects a 2-D kernel, got {:?}",
+        kernel.shape
+    );
+
+    let (h, w) = (input.shape[rank - 2], input.shape[rank - 1]);
+    let (kh, kw) = (kernel.shape[0], kernel.shape[1]);
+    let (out_h, out_w) = (h + kh - 1, w + kw - 1);
+    let (size_h, size_w) = (out_h.next_power_of_two(), out_w.next_power_of_two());
+    let lanes = input.data.len() / (h * w);
+
+    let mut lead = input.shape[..rank - 2].to_vec();
+    let mut padded = Tensor::new_complex([lead.clone(), vec![size_h, size_w]].concat());
+    for lane in 0..lanes {
+        for i in 0..h {
+            for j in 0..w {
+                padded.data[lane * size_h * size_w + i * size_w + j] =
+                    Complex::from(input.data[lane * h * w + i * w + j]);
+            }
+        }
+    }
+
+    let mut padded_kernel = Tensor::new_complex(vec![size_h, size_w]);
+    for i in 0..kh {
+        for j in 0..kw {
+            padded_kernel.data[i * size_w + j] = Complex::from(kernel.data[i * kw + j]);
+        }
+    }
+
+    let spectrum = fft2(&padded);
+    let kernel_spectrum = fft2(&padded_kernel);
+    let mut product = spectrum;
+    for lane in 0..lanes {
+        let block = &mut product.data[lane * size_h * size_w..(lane + 1) * size_h * size_w];
+        for (b, &k) in block.iter_mut().zip(kernel_spectrum.data.iter()) {
+            *b = *b * k;
+        }
+    }
+    let spatial = ifft2(&product);
+
+    lead.push(out_h);
+    lead.push(out_w);
+    let mut res = Tensor::new(lead);
+    for lane in 0..lanes {
+        for i in 0..out_h {
+            for j in 0..out_w {
+                res.data[lane * out_h * out_w + i * out_w + j] =
+                    spatial.data[lane * size_h * size_w + i * size_w + j].re;
+            }
+        }
+    }
+
+    res
+}
+
+//--------------------------------------------------------------Helpers---------------------------------------------------------------------
+
+//Applies the 1-D transform to every lane of the tensor along `axis`
+fn transform_axis(input: &Tensor<Complex>, axis: usize, inverse: bool) -> Tensor<Complex> {
+    let n = input.shape[axis];
+    let stride: usize = input.shape[axis + 1..].iter().product();
+    let outer: usize = input.shape[..axis].iter().product();
+
+    let mut res = input.clone();
+    let mut lane = vec![Complex::ZERO; n];
+
+    for o in 0..outer {
+        for s in 0..stride {
+            let base = o * n * stride + s;
+            for (i, value) in lane.iter_mut().enumerate() {
+                *value = res.data[base + i * stride];
+            }
+            transform(&mut lane, inverse);
+            for (i, value) in lane.iter().enumerate() {
+                res.data[base + i * stride] = *value;
+            }
+        }
+    }
+
+    res
+}
+
+fn truncate_last_axis(input: &Tensor<Complex>, keep: usize) -> Tensor<Complex> {
+    let n = *input.shape.last().unwrap();
+    let mut shape = input.shape.clone();
+    *shape.last_mut().unwrap() = keep;
+
+    let data = input
+        .data
+        .chunks(n)
+        .flat_map(|lane| lane[..keep].iter().copied())
+        .collect();
+
+    Tensor { shape, data }
+}
+
+//In place 1-D transform of any length. The inverse is normalized by 1/n.
+fn transform(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    if n <= 1 {
+        return;
+    }
+
+    if n.is_power_of_two() {
+        radix2(data, inverse);
+    } else {
+        bluestein(data, inverse);
+    }
+
+    if inverse {
+        let scale = 1.0 / n as f64;
+        for value in data.iter_mut() {
+            *value = value.scale(scale);
+        }
+    }
+}
+
+//Iterative Cooley-Tukey, expects a power of two length. Not normalized.
+fn radix2(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+
+    //Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let step = Complex::from_polar(1.0, sign * 2.0 * PI / len as f64);
+        for start in (0..n).step_by(len) {
+            let mut twiddle = Complex::ONE;
+            for k in 0..len / 2 {
+                let even = data[start + k];
+                let odd = data[start + k + len / 2] * twiddle;
+                data[start + k] = even + odd;
+                data[start + k + len / 2] = even - odd;
+                twiddle = twiddle * step;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+//Bluestein's chirp-z algorithm, rewrites an arbitrary length DFT as a
+//power of two convolution. Not normalized.
+fn bluestein(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    let m = (2 * n - 1).next_power_of_two();
+    let sign = if inverse { 1.0 } else { -1.0 };
+
+    //chirp[k] = e^(sign * i * pi * k^2 / n), k^2 is reduced mod 2n to keep the angle precise
+    let chirp: Vec<Complex> = (0..n)
+        .map(|k| {
+            let k2 = (k * k) % (2 * n);
+            Complex::from_polar(1.0, sign * PI * k2 as f64 / n as f64)
+        })
+        .collect();
+
+    let mut a = vec![Complex::ZERO; m];
+    for k in 0..n {
+        a[k] = data[k] * chirp[k];
+    }
+
+    let mut b = vec![Complex::ZERO; m];
+    b[0] = chirp[0].conj();
+    for k in 1..n {
+        b[k] = chirp[k].conj();
+        b[m - k] = chirp[k].conj();
+    }
+
+    radix2(&mut a, false);
+    radix2(&mut b, false);
+    for (x, y) in a.iter_mut().zip(b.iter()) {
+        *x = *x * *y;
+    }
+    radix2(&mut a, true);
+
+    let scale = 1.0 / m as f64;
+    for k in 0..n {
+        data[k] = a[k].scale(scale) * chirp[k];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    //Power of two, prime and composite lengths, so both radix-2 and Bluestein are covered
+    const LENGTHS: [usize; 7] = [1, 2, 5, 8, 12, 13, 16];
+
+    fn random_complex(shape: Vec<usize>) -> Tensor<Complex> {
+        Tensor::from_parts(&Tensor::random(shape.clone()), &Tensor::random(shape))
+    }
+
+    //O(n^2) DFT straight from the definition
+    fn naive_dft(signal: &[Complex]) -> Vec<Complex> {
+        let n = signal.len();
+        (0..n)
+            .map(|k| {
+                signal
+                    .iter()
+                    .enumerate()
+                    .fold(Complex::ZERO, |sum, (t, &x)| {
+                        let theta = -2.0 * PI * (k * t) as f64 / n as f64;
+                        sum + x * Complex::from_polar(1.0, theta)
+                    })
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: &[Complex], expected: &[Complex]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((*a - *e).norm() < TOLERANCE, "{:?} != {:?}", a, e);
+        }
+    }
+
+    fn assert_real_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < TOLERANCE, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn fft_matches_naive_dft() {
+        for n in LENGTHS {
+            let input = random_complex(vec![3, n]);
+            let output = fft(&input);
+            assert_eq!(output.shape, vec![3, n]);
+            for lane in 0..3 {
+                let lane_data = &input.data[lane * n..(lane + 1) * n];
+                assert_close(
+                    &output.data[lane * n..(lane + 1) * n],
+                    &naive_dft(lane_data),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ifft_inverts_fft() {
+        for n in LENGTHS {
+            let input = random_complex(vec![2, n]);
+            assert_close(&ifft(&fft(&input)).data, &input.data);
+        }
+    }
+
+    #[test]
+    fn irfft_inverts_rfft() {
+        for n in LENGTHS {
+            let input = Tensor::random(vec![2, n]);
+            let spectrum = rfft(&input);
+            assert_eq!(spectrum.shape, vec![2, n / 2 + 1]);
+            assert_real_close(&irfft(&spectrum, n).data, &input.data);
+        }
+    }
+
+    #[test]
+    fn fft2_matches_naive_dft_over_both_axes() {
+        let (rows, cols) = (3, 5);
+        let input = random_complex(vec![rows, cols]);
+
+        let mut expected = input.data.clone();
+        for r in 0..rows {
+            let row = naive_dft(&expected[r * cols..(r + 1) * cols]);
+            expected[r * cols..(r + 1) * cols].copy_from_slice(&row);
+        }
+        for c in 0..cols {
+            let column: Vec<Complex> = (0..rows).map(|r| expected[r * cols + c]).collect();
+            for (r, value) in naive_dft(&column).into_iter().enumerate() {
+                expected[r * cols + c] = value;
+            }
+        }
+
+        assert_close(&fft2(&input).data, &expected);
+        assert_close(&ifft2(&fft2(&input)).data, &input.data);
+    }
+
+    #[test]
+    fn stft_frames_are_windowed_ffts() {
+        let (n_fft, hop) = (8, 3);
+        let signal = Tensor::random(vec![20]);
+        let spectrogram = stft(&signal, n_fft, hop, Window::Hann);
+        let frames = 1 + (20 - n_fft) / hop;
+        let bins = n_fft / 2 + 1;
+        assert_eq!(spectrogram.shape, vec![bins, frames]);
+
+        let window = Window::Hann.coefficients(n_fft);
+        for f in 0..frames {
+            let frame: Vec<Complex> = (0..n_fft)
+                .map(|i| Complex::from(signal.data[f * hop + i] * window[i]))
+                .collect();
+            let expected = naive_dft(&frame);
+            let actual: Vec<Complex> = (0..bins)
+                .map(|k| spectrogram.data[k * frames + f])
+                .collect();
+            assert_close(&actual, &expected[..bins]);
+        }
+    }
+
+    #[test]
+    fn fft_convolve_matches_direct_convolution() {
+        for (length, taps) in [(1, 1), (7, 3), (13, 5), (16, 16)] {
+            let signal = Tensor::random(vec![2, length]);
+            let kernel = Tensor::random(vec![taps]);
+            let out_length = length + taps - 1;
+
+            let mut expected = vec![0.0; 2 * out_length];
+            for lane in 0..2 {
+                for i in 0..length {
+                    for j in 0..taps {
+                        expected[lane * out_length + i + j] +=
+                            signal.data[lane * length + i] * kernel.data[j];
+                    }
+                }
+            }
+
+            let output = fft_convolve(&signal, &kernel);
+            assert_eq!(output.shape, vec![2, out_length]);
+            assert_real_close(&output.data, &expected);
+        }
+    }
+
+    #[test]
+    fn fft_convolve2d_matches_direct_convolution() {
+        let (h, w, kh, kw) = (5, 6, 3, 2);
+        let input = Tensor::random(vec![h, w]);
+        let kernel = Tensor::random(vec![kh, kw]);
+        let (oh, ow) = (h + kh - 1, w + kw - 1);
+
+        let mut expected = vec![0.0; oh * ow];
+        for y in 0..h {
+            for x in 0..w {
+                for i in 0..kh {
+                    for j in 0..kw {
+                        expected[(y + i) * ow + x + j] +=
+                            input.data[y * w + x] * kernel.data[i * kw + j];
+                    }
+                }
+            }
+        }
+
+        let output = fft_convolve2d(&input, &kernel);
+        assert_eq!(output.shape, vec![oh, ow]);
+        assert_real_close(&output.data, &expected);
+    }
+}
@@ -0,0 +1,108 @@
+use super::{activations::Activation, layer::Layer, loss::LossFunction, tensor::Tensor};
+
+//Compares a layer's analytic gradients against central finite differences.
+//The loss used is sum(output * upstream) for a fixed random upstream gradient,
+//so every output element contributes. Returns the largest relative error found
+//across the input gradient and every parameter gradient.
+pub fn gradient_check(layer: &mut dyn Layer, input: &Tensor, epsilon: f64) -> f64 {
+    let output = layer.forward(input);
+    let upstream = Tensor::random(output.shape.clone());
+    let input_gradient = layer.backward(&upstream);
+    let parameter_gradients: Vec<Tensor> = layer.gradients().into_iter().cloned().collect();
+
+    let loss = |layer: &mut dyn Layer, input: &Tensor| -> f64 {
+        let output = layer.forward(input);
+        output
+            .data
+            .iter()
+            .zip(upstream.data.iter())
+            .map(|(o, u)| o * u)
+            .sum()
+    };
+
+    let mut max_error: f64 = 0.0;
+
+    //Input gradient
+    let mut perturbed = input.clone();
+    for i in 0..input.data.len() {
+        perturbed.data[i] = input.data[i] + epsilon;
+        let plus = loss(layer, &perturbed);
+        perturbed.data[i] = input.data[i] - epsilon;
+        let minus = loss(layer, &perturbed);
+        perturbed.data[i] = input.data[i];
+
+        let numeric = (plus - minus) / (2.0 * epsilon);
+        max_error = max_error.max(relative_error(input_gradient.data[i], numeric));
+    }
+
+    //Parameter gradients
+    for (p, analytic) in parameter_gradients.iter().enumerate() {
+        for i in 0..analytic.data.len() {
+            let original = layer.parameters()[p].data[i];
+
+            layer.parameters()[p].data[i] = original + epsilon;
+            let plus = loss(layer, input);
+            layer.parameters()[p].data[i] = original - epsilon;
+            let minus = loss(layer, input);
+            layer.parameters()[p].data[i] = original;
+
+            let numeric = (plus - minus) / (2.0 * epsilon);
+            max_error = max_error.max(relative_error(analytic.data[i], numeric));
+        }
+    }
+
+    max_error
+}
+
+//Compares an activation's derivative against central finite differences at each point and
+//returns the largest relative error. Keep points away from kinks like 0 for ReLU.
+pub fn activation_check(activation: &mut dyn Activation, points: &[f64], epsilon: f64) -> f64 {
+    let input = Tensor::from(vec![points.len()], points.to_vec());
+    let output = activation.forward(&input);
+    let ones = Tensor::from(input.shape.clone(), vec![1.0; points.len()]);
+    let analytic = activation.backward(&input, &output, &ones);
+
+    let shifted = |offset: f64| {
+        let data = points.iter().map(|x| x + offset).collect();
+        activation.forward(&Tensor::from(input.shape.clone(), data))
+    };
+    let (plus, minus) = (shifted(epsilon), shifted(-epsilon));
+
+    (0..points.len())
+        .map(|i| {
+            let numeric = (plus.data[i] - minus.data[i]) / (2.0 * epsilon);
+            relative_error(analytic.data[i], numeric)
+        })
+        .fold(0.0, f64::max)
+}
+
+//Compares a loss's derivative against central finite differences in every predicted value
+//and returns the largest relative error
+pub fn loss_check(
+    loss: &dyn LossFunction,
+    predicted: &Tensor,
+    actual: &Tensor,
+    epsilon: f64,
+) -> f64 {
+    let analytic = loss.derivative(predicted, actual);
+    let mut perturbed = predicted.clone();
+    let mut max_error: f64 = 0.0;
+
+    for i in 0..predicted.data.len() {
+        perturbed.data[i] = predicted.data[i] + epsilon;
+        let plus = loss.function(&perturbed, actual);
+        perturbed.data[i] = predicted.data[i] - epsilon;
+        let minus = loss.function(&perturbed, actual);
+        perturbed.data[i] = predicted.data[i];
+
+        let numeric = (plus - minus) / (2.0 * epsilon);
+        max_error = max_error.max(relative_error(analytic.data[i], numeric));
+    }
+
+    max_error
+}
+
+//|a - b| scaled by their magnitude, falling back to the absolute error near zero
+pub(crate) fn relative_error(analytic: f64, numeric: f64) -> f64 {
+    (analytic - numeric).abs() / (analytic.abs() + numeric.abs()).max(1.0)
+}
@@ -0,0 +1,655 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::Tensor,
+};
+
+//Models whose layers form a directed acyclic graph instead of a chain, for residual
+//connections, skip connections and models with several inputs or outputs.
+//Nodes can only consume nodes created before them, so creation order is always a valid
+//topological order. Every node knows which tensor axis holds its batch (see
+//Layer::batch_axis), so merges join samples whether they are batch-first [N, ...],
+//[features, N] columns or time-major [T, N, F].
+//
+//    let mut graph = GraphBuilder::new();
+//    let x = graph.input(vec![16, 8, 8]);
+//    let h = graph.layer(Conv2d::new(16, 16, 3).with_padding(1), x);
+//    let y = graph.add(&[x, h]);
+//    let mut model = graph.build(&[y])?;
+
+//Handle to a node of a graph under construction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+enum NodeKind {
+    //Index into the model inputs
+    Input(usize),
+    Layer(Box<dyn Layer>),
+    //Elementwise sum of inputs of equal shape
+    Add,
+    //Concatenation along a sample axis, counted without the batch axis
+    Concat(usize),
+}
+
+struct Node {
+    kind: NodeKind,
+    inputs: Vec<usize>,
+    //Shape of a single sample, filled in by build
+    shape: Vec<usize>,
+    //Tensor axis holding the batch, filled in by build
+    batch_axis: usize,
+}
+
+//--------------------------------------------------------------Builder---------------------------------------------------------------------
+
+pub struct GraphBuilder {
+    nodes: Vec<Node>,
+    input_shapes: Vec<Vec<usize>>,
+    input_batch_axes: Vec<usize>,
+}
+
+impl GraphBuilder {
+    pub fn new() -> GraphBuilder {
+        GraphBuilder {
+            nodes: Vec::new(),
+            input_shapes: Vec::new(),
+            input_batch_axes: Vec::new(),
+        }
+    }
+
+    //A new model input taking samples of the given shape. Inputs are fed in creation order.
+    //Flat [features] samples come as [features, N] columns like TensorLayer takes, every other
+    //shape batch-first.
+    pub fn input(&mut self, shape: Vec<usize>) -> NodeId {
+        let batch_axis = if shape.len() == 1 { 1 } else { 0 };
+        self.input_with_batch_axis(shape, batch_axis)
+    }
+
+    //Input whose tensors hold the batch on the given axis, e.g. 1 for time-major [T, N, F]
+    pub fn input_with_batch_axis(&mut self, shape: Vec<usize>, batch_axis: usize) -> NodeId {
+        assert!(
+            batch_axis <= shape.len(),
+            "Batch axis {} is out of range for samples shaped {:?}",
+            batch_axis,
+            shape
+        );
+        self.input_shapes.push(shape);
+        self.input_batch_axes.push(batch_axis);
+        self.push(NodeKind::Input(self.input_shapes.len() - 1), Vec::new())
+    }
+
+    pub fn layer<L: Layer + 'static>(&mut self, layer: L, input: NodeId) -> NodeId {
+        self.push(NodeKind::Layer(Box::new(layer)), vec![input])
+    }
+
+    pub fn add(&mut self, inputs: &[NodeId]) -> NodeId {
+        assert!(!inputs.is_empty(), "Add node needs at least one input");
+        self.push(NodeKind::Add, inputs.to_vec())
+    }
+
+    pub fn concat(&mut self, inputs: &[NodeId], axis: usize) -> NodeId {
+        assert!(!inputs.is_empty(), "Concat node needs at least one input");
+        self.push(NodeKind::Concat(axis), inputs.to_vec())
+    }
+
+    fn push(&mut self, kind: NodeKind, inputs: Vec<NodeId>) -> NodeId {
+        for &input in &inputs {
+            self.check_node(input);
+        }
+        self.nodes.push(Node {
+            kind,
+            inputs: inputs.iter().map(|id| id.0).collect(),
+            shape: Vec::new(),
+            batch_axis: 0,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    //Ids of another builder can point past the end of this one
+    fn check_node(&self, id: NodeId) {
+        assert!(
+            id.0 < self.nodes.len(),
+            "Node {:?} does not belong to this graph",
+            id
+        );
+    }
+
+    //Infers the shape of every node, failing on the first node that cannot take the shapes
+    //it is given (its position is the node index), and drops nodes no output depends on
+    pub fn build(mut self, outputs: &[NodeId]) -> Result<GraphModel, ShapeError> {
+        assert!(
+            !outputs.is_empty(),
+            "A graph model needs at least one output"
+        );
+
+        let mut used = vec![false; self.nodes.len()];
+        for &output in outputs {
+            self.check_node(output);
+            used[output.0] = true;
+        }
+        for index in (0..self.nodes.len()).rev() {
+            if used[index] {
+                for &input in &self.nodes[index].inputs {
+                    used[input] = true;
+                }
+            }
+        }
+
+        for index in 0..self.nodes.len() {
+            let input_shapes: Vec<&[usize]> = self.nodes[index]
+                .inputs
+                .iter()
+                .map(|&i| &self.nodes[i].shape[..])
+                .collect();
+            let batch_axes: Vec<usize> = self.nodes[index]
+                .inputs
+                .iter()
+                .map(|&i| self.nodes[i].batch_axis)
+                .collect();
+            let (shape, batch_axis) = match &self.nodes[index].kind {
+                NodeKind::Input(i) => {
+                    (Ok(self.input_shapes[*i].clone()), self.input_batch_axes[*i])
+                }
+                NodeKind::Layer(layer) => (
+                    layer.output_shape(input_shapes[0]),
+                    layer.batch_axis(batch_axes[0]),
+                ),
+                NodeKind::Add => (
+                    same_batch_axis("Add", &input_shapes, &batch_axes)
+                        .and_then(|_| add_shape(&input_shapes)),
+                    batch_axes[0],
+                ),
+                NodeKind::Concat(axis) => (
+                    same_batch_axis("Concat", &input_shapes, &batch_axes)
+                        .and_then(|_| concat_shape(&input_shapes, *axis)),
+                    batch_axes[0],
+                ),
+            };
+            self.nodes[index].shape = shape.map_err(|error| error.at(index))?;
+            self.nodes[index].batch_axis = batch_axis;
+        }
+
+        //Keep every input even if unused, so the model still takes all of them
+        let order = (0..self.nodes.len())
+            .filter(|&i| used[i] || matches!(self.nodes[i].kind, NodeKind::Input(_)))
+            .collect();
+
+        Ok(GraphModel {
+            nodes: self.nodes,
+            order,
+            outputs: outputs.iter().map(|id| id.0).collect(),
+            input_shapes: self.input_shapes,
+            values: Vec::new(),
+        })
+    }
+}
+
+impl Default for GraphBuilder {
+    fn default() -> GraphBuilder {
+        GraphBuilder::new()
+    }
+}
+
+//Merged tensors must hold their batch on the same axis, or samples would be mixed up
+fn same_batch_axis(
+    merge: &str,
+    shapes: &[&[usize]],
+    batch_axes: &[usize],
+) -> Result<(), ShapeError> {
+    for (shape, &batch_axis) in shapes.iter().zip(batch_axes) {
+        if batch_axis != batch_axes[0] {
+            let expected = format!("inputs batched along tensor axis {}", batch_axes[0]);
+            return Err(ShapeError::new(merge, expected, shape));
+        }
+    }
+    Ok(())
+}
+
+//Every input must have the shape of the first one
+fn add_shape(shapes: &[&[usize]]) -> Result<Vec<usize>, ShapeError> {
+    assert!(!shapes.is_empty(), "Add node has no inputs");
+    for shape in shapes {
+        if *shape != shapes[0] {
+            return Err(ShapeError::new("Add", format!("{:?}", shapes[0]), shape));
+        }
+    }
+    Ok(shapes[0].to_vec())
+}
+
+//Every input must match the first one on all axes but the joined one
+fn concat_shape(shapes: &[&[usize]], axis: usize) -> Result<Vec<usize>, ShapeError> {
+    assert!(!shapes.is_empty(), "Concat node has no inputs");
+    let mut res = shapes[0].to_vec();
+    let expected: Vec<String> = (0..res.len())
+        .map(|d| {
+            if d == axis {
+                "*".to_string()
+            } else {
+                res[d].to_string()
+            }
+        })
+        .collect();
+    let expected = format!("[{}]", expected.join(", "));
+
+    for shape in shapes {
+        let compatible = axis < shape.len()
+            && shape.len() == res.len()
+            && (0..res.len()).all(|d| d == axis || shape[d] == shapes[0][d]);
+        if !compatible {
+            return Err(ShapeError::new("Concat", expected, shape));
+        }
+    }
+    res[axis] = shapes.iter().map(|shape| shape[axis]).sum();
+    Ok(res)
+}
+
+//--------------------------------------------------------------Model---------------------------------------------------------------------
+
+pub struct GraphModel {
+    nodes: Vec<Node>,
+    //Nodes to run, in topological order
+    order: Vec<usize>,
+    outputs: Vec<usize>,
+    input_shapes: Vec<Vec<usize>>,
+    //Output of every node from the last forward call
+    values: Vec<Option<Tensor>>,
+}
+
+impl GraphModel {
+    //Runs every node once in topological order and returns the outputs in build order
+    pub fn forward_all(&mut self, inputs: &[Tensor]) -> Vec<Tensor> {
+        assert!(
+            inputs.len() == self.input_shapes.len(),
+            "Graph model takes {} inputs, got {}",
+            self.input_shapes.len(),
+            inputs.len()
+        );
+        let mut values: Vec<Option<Tensor>> = vec![None; self.nodes.len()];
+
+        for &index in &self.order {
+            let node = &mut self.nodes[index];
+            let value = {
+                let args: Vec<&Tensor> = node
+                    .inputs
+                    .iter()
+                    .map(|&i| values[i].as_ref().expect("Node scheduled before its input"))
+                    .collect();
+                match &mut node.kind {
+                    NodeKind::Input(i) => inputs[*i].clone(),
+                    NodeKind::Layer(layer) => layer.forward(args[0]),
+                    NodeKind::Add => {
+                        let mut sum = args[0].clone();
+                        for arg in &args[1..] {
+                            sum.add(arg);
+                        }
+                        sum
+                    }
+                    NodeKind::Concat(axis) => concat(&args, tensor_axis(*axis, node.batch_axis)),
+                }
+            };
+            values[index] = Some(value);
+        }
+
+        let res = self
+            .outputs
+            .iter()
+            .map(|&i| values[i].clone().expect("Output was not computed"))
+            .collect();
+        self.values = values;
+        res
+    }
+
+    //Takes one gradient per output and returns one gradient per input. Nodes feeding
+    //several others receive the sum of their gradients.
+    pub fn backward_all(&mut self, grad_outputs: &[Tensor]) -> Vec<Tensor> {
+        assert!(
+            grad_outputs.len() == self.outputs.len(),
+            "Graph model has {} outputs, got {} gradients",
+            self.outputs.len(),
+            grad_outputs.len()
+        );
+        let mut grads: Vec<Option<Tensor>> = vec![None; self.nodes.len()];
+        for (&output, grad) in self.outputs.iter().zip(grad_outputs) {
+            accumulate(&mut grads[output], grad.clone());
+        }
+
+        let mut input_grads: Vec<Option<Tensor>> = vec![None; self.input_shapes.len()];
+        for &index in self.order.iter().rev() {
+            let grad = match grads[index].take() {
+                Some(grad) => grad,
+                None => continue,
+            };
+            let node = &mut self.nodes[index];
+            match &mut node.kind {
+                NodeKind::Input(i) => input_grads[*i] = Some(grad),
+                NodeKind::Layer(layer) => {
+                    accumulate(&mut grads[node.inputs[0]], layer.backward(&grad));
+                }
+                NodeKind::Add => {
+                    for &input in &node.inputs {
+                        accumulate(&mut grads[input], grad.clone());
+                    }
+                }
+                NodeKind::Concat(axis) => {
+                    let shapes: Vec<&[usize]> = node
+                        .inputs
+                        .iter()
+                        .map(|&i| &self.values[i].as_ref().unwrap().shape[..])
+                        .collect();
+                    let axis = tensor_axis(*axis, node.batch_axis);
+                    for (&input, part) in node.inputs.iter().zip(split(&grad, &shapes, axis)) {
+                        accumulate(&mut grads[input], part);
+                    }
+                }
+            }
+        }
+
+        //Inputs no output depends on get a zero gradient
+        input_grads
+            .into_iter()
+            .enumerate()
+            .map(|(i, grad)| {
+                grad.unwrap_or_else(|| {
+                    let index = self
+                        .order
+                        .iter()
+                        .copied()
+                        .find(|&n| matches!(self.nodes[n].kind, NodeKind::Input(j) if j == i))
+                        .unwrap();
+                    Tensor::new(self.values[index].as_ref().unwrap().shape.clone())
+                })
+            })
+            .collect()
+    }
+
+    //Shape of a single sample of every output, as inferred by build
+    pub fn output_shapes(&self) -> Vec<Vec<usize>> {
+        self.outputs
+            .iter()
+            .map(|&i| self.nodes[i].shape.clone())
+            .collect()
+    }
+
+    fn layers_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Layer>> {
+        let order = &self.order;
+        self.nodes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| order.contains(i))
+            .filter_map(|(_, node)| match &mut node.kind {
+                NodeKind::Layer(layer) => Some(layer),
+                _ => None,
+            })
+    }
+}
+
+//A model with one input and one output is itself a layer, so a residual block
+//can be built as a graph and added to a CPUTensorNetwork
+impl Layer for GraphModel {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            self.outputs.len() == 1,
+            "Only single output graph models can be used as a layer"
+        );
+        self.forward_all(std::slice::from_ref(input)).remove(0)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        self.backward_all(std::slice::from_ref(grad_output))
+            .remove(0)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        self.layers_mut()
+            .flat_map(|layer| layer.parameters())
+            .collect()
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.order
+            .iter()
+            .filter_map(|&i| match &self.nodes[i].kind {
+                NodeKind::Layer(layer) => Some(layer.gradients()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    //Each layer takes its own step, so sparse layers stay sparse inside a graph
+    fn update(&mut self, learning_rate: f64) {
+        for layer in self.layers_mut() {
+            layer.update(learning_rate);
+        }
+    }
+
+    fn set_training(&mut self, training: bool) {
+        for layer in self.layers_mut() {
+            layer.set_training(training);
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if self.input_shapes.len() != 1 || input_shape != self.input_shapes[0] {
+            let expected = format!("{:?}", self.input_shapes);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(self.nodes[self.outputs[0]].shape.clone())
+    }
+
+    //Every scheduled layer on the shapes inferred by build, plus one operation per merged element
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        self.order
+            .iter()
+            .map(|&i| {
+                let node = &self.nodes[i];
+                match &node.kind {
+                    NodeKind::Layer(layer) => layer.flops(&self.nodes[node.inputs[0]].shape),
+                    NodeKind::Add => node.shape.iter().product::<usize>() * (node.inputs.len() - 1),
+                    _ => 0,
+                }
+            })
+            .sum()
+    }
+
+    //Where the output node holds its batch, for inputs laid out as declared at build
+    fn batch_axis(&self, _input_batch_axis: usize) -> usize {
+        self.nodes[self.outputs[0]].batch_axis
+    }
+
+    fn non_trainable_parameters(&self) -> usize {
+        self.order
+            .iter()
+            .map(|&i| match &self.nodes[i].kind {
+                NodeKind::Layer(layer) => layer.non_trainable_parameters(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn name(&self) -> &str {
+        "GraphModel"
+    }
+}
+
+fn accumulate(slot: &mut Option<Tensor>, grad: Tensor) {
+    match slot {
+        Some(sum) => sum.add(&grad),
+        None => *slot = Some(grad),
+    }
+}
+
+//Tensor axis of a sample axis once the batch axis is put back in
+fn tensor_axis(sample_axis: usize, batch_axis: usize) -> usize {
+    if sample_axis < batch_axis {
+        sample_axis
+    } else {
+        sample_axis + 1
+    }
+}
+
+//Joins tensors along a tensor axis, every other axis must match
+fn concat(tensors: &[&Tensor], axis: usize) -> Tensor {
+    let outer: usize = tensors[0].shape[..axis].iter().product();
+    let mut shape = tensors[0].shape.clone();
+    shape[axis] = tensors.iter().map(|t| t.shape[axis]).sum();
+
+    let chunks: Vec<usize> = tensors.iter().map(|t| t.data.len() / outer).collect();
+    let mut data = Vec::with_capacity(shape.iter().product());
+    for o in 0..outer {
+        for (tensor, &chunk) in tensors.iter().zip(&chunks) {
+            data.extend_from_slice(&tensor.data[o * chunk..(o + 1) * chunk]);
+        }
+    }
+    Tensor::from(shape, data)
+}
+
+//Inverse of concat, cuts a tensor into pieces shaped like `shapes`
+fn split(tensor: &Tensor, shapes: &[&[usize]], axis: usize) -> Vec<Tensor> {
+    let outer: usize = tensor.shape[..axis].iter().product();
+    let mut parts: Vec<Vec<f64>> = shapes.iter().map(|_| Vec::new()).collect();
+    let chunks: Vec<usize> = shapes
+        .iter()
+        .map(|s| s.iter().product::<usize>() / outer)
+        .collect();
+
+    let mut offset = 0;
+    for _ in 0..outer {
+        for (part, &chunk) in parts.iter_mut().zip(&chunks) {
+            part.extend_from_slice(&tensor.data[offset..offset + chunk]);
+            offset += chunk;
+        }
+    }
+    parts
+        .into_iter()
+        .zip(shapes)
+        .map(|(data, shape)| Tensor::from(shape.to_vec(), data))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{
+        activations::IDENTITY,
+        conv::Conv2d,
+        dense::TensorLayer,
+        gradient_check::gradient_check,
+        initializers::Initializer,
+        layer::Flatten,
+        rnn::{Recurrent, RnnCell},
+    };
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn constant_layer(inputs: usize, amount: usize, value: f64) -> TensorLayer {
+        TensorLayer::new(inputs, amount, IDENTITY).with_initializer(Initializer::Constant(value))
+    }
+
+    #[test]
+    fn concat_joins_dense_features_not_batches() {
+        let mut graph = GraphBuilder::new();
+        let x = graph.input(vec![2]);
+        let a = graph.layer(constant_layer(2, 3, 1.0), x);
+        let b = graph.layer(constant_layer(2, 5, 2.0), x);
+        let y = graph.concat(&[a, b], 0);
+        let mut model = graph.build(&[y]).unwrap();
+        assert_eq!(model.output_shapes(), vec![vec![8]]);
+
+        let input = Tensor::from(vec![2, 4], vec![1.0, 2.0, 3.0, 4.0, 10.0, 20.0, 30.0, 40.0]);
+        let output = model.forward(&input);
+        assert_eq!(output.shape, vec![8, 4]);
+        for row in 0..8 {
+            let scale = if row < 3 { 1.0 } else { 2.0 };
+            for n in 0..4 {
+                let expected = scale * (input.data[n] + input.data[4 + n]);
+                assert_eq!(output.get(vec![row, n]), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn dense_concat_gradients() {
+        let mut graph = GraphBuilder::new();
+        let x = graph.input(vec![2]);
+        let a = graph.layer(TensorLayer::new(2, 3, IDENTITY), x);
+        let b = graph.layer(TensorLayer::new(2, 3, IDENTITY), x);
+        let y = graph.concat(&[a, b], 0);
+        let mut model = graph.build(&[y]).unwrap();
+
+        let input = Tensor::random(vec![2, 4]);
+        assert_eq!(model.forward(&input).shape, vec![6, 4]);
+        let error = gradient_check(&mut model, &input, 1e-6);
+        assert!(error < TOLERANCE, "relative error {}", error);
+    }
+
+    #[test]
+    fn batch_first_concat_gradients() {
+        let mut graph = GraphBuilder::new();
+        let x = graph.input(vec![2, 4, 4]);
+        let a = graph.layer(Conv2d::new(2, 3, 3).with_padding(1), x);
+        let y = graph.concat(&[x, a], 0);
+        let mut model = graph.build(&[y]).unwrap();
+
+        let input = Tensor::random(vec![3, 2, 4, 4]);
+        assert_eq!(model.forward(&input).shape, vec![3, 5, 4, 4]);
+        let error = gradient_check(&mut model, &input, 1e-6);
+        assert!(error < TOLERANCE, "relative error {}", error);
+    }
+
+    #[test]
+    fn time_major_concat_gradients() {
+        let mut graph = GraphBuilder::new();
+        let x = graph.input_with_batch_axis(vec![5, 2], 1);
+        let a = graph.layer(Recurrent::<RnnCell>::new(2, 3), x);
+        let y = graph.concat(&[x, a], 1);
+        let mut model = graph.build(&[y]).unwrap();
+
+        let input = Tensor::random(vec![5, 4, 2]);
+        assert_eq!(model.forward(&input).shape, vec![5, 4, 5]);
+        let error = gradient_check(&mut model, &input, 1e-6);
+        assert!(error < TOLERANCE, "relative error {}", error);
+    }
+
+    #[test]
+    fn merging_different_batch_axes_is_rejected() {
+        let mut graph = GraphBuilder::new();
+        let columns = graph.input(vec![4]);
+        let rows = graph.input_with_batch_axis(vec![4], 0);
+        let y = graph.concat(&[columns, rows], 0);
+        let error = graph.build(&[y]).err().unwrap();
+        assert_eq!(error.layer, "Concat");
+        assert_eq!(error.position, Some(y.0));
+
+        //Flatten hands over [features, N] columns, which merge with dense inputs
+        let mut graph = GraphBuilder::new();
+        let x = graph.input(vec![2, 2]);
+        let flat = graph.layer(Flatten::new(), x);
+        let dense = graph.input(vec![4]);
+        let y = graph.add(&[flat, dense]);
+        assert!(graph.build(&[y]).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not belong to this graph")]
+    fn foreign_output_is_rejected() {
+        let mut other = GraphBuilder::new();
+        other.input(vec![2]);
+        let foreign = other.input(vec![2]);
+
+        let mut graph = GraphBuilder::new();
+        graph.input(vec![2]);
+        let _ = graph.build(&[foreign]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Add node needs at least one input")]
+    fn empty_add_is_rejected() {
+        GraphBuilder::new().add(&[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Concat node needs at least one input")]
+    fn empty_concat_is_rejected() {
+        GraphBuilder::new().concat(&[], 0);
+    }
+}
@@ -0,0 +1,209 @@
+use super::tensor::{standard_normal, Tensor};
+use rand::{thread_rng, Rng};
+
+//Ways of filling a fresh parameter tensor. Scaled schemes read the fan in and fan out from the
+//shape: [outputs, inputs, *kernel] for weights, with the kernel size multiplying both fans.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Initializer {
+    //Glorot: variance 2 / (fan_in + fan_out), keeps sigmoid and tanh layers out of saturation
+    XavierUniform,
+    XavierNormal,
+    //Kaiming: variance 2 / fan_in, makes up for ReLU zeroing half of its inputs
+    HeUniform,
+    HeNormal,
+    //Variance 1 / fan_in, the scheme SELU networks rely on
+    LeCunUniform,
+    LeCunNormal,
+    //Rows (or columns, whichever are fewer) form an orthonormal set
+    Orthogonal,
+    Zeros,
+    Ones,
+    Constant(f64),
+}
+
+impl Initializer {
+    pub fn initialize(&self, shape: Vec<usize>) -> Tensor {
+        let mut rng = thread_rng();
+        let (fan_in, fan_out) = fans(&shape);
+        let mut tensor = Tensor::new(shape);
+
+        match *self {
+            Initializer::XavierUniform => uniform(
+                &mut tensor,
+                (6.0 / (fan_in + fan_out) as f64).sqrt(),
+                &mut rng,
+            ),
+            Initializer::XavierNormal => normal(
+                &mut tensor,
+                (2.0 / (fan_in + fan_out) as f64).sqrt(),
+                &mut rng,
+            ),
+            Initializer::HeUniform => uniform(&mut tensor, (6.0 / fan_in as f64).sqrt(), &mut rng),
+            Initializer::HeNormal => normal(&mut tensor, (2.0 / fan_in as f64).sqrt(), &mut rng),
+            Initializer::LeCunUniform => {
+                uniform(&mut tensor, (3.0 / fan_in as f64).sqrt(), &mut rng)
+            }
+            Initializer::LeCunNormal => normal(&mut tensor, (1.0 / fan_in as f64).sqrt(), &mut rng),
+            Initializer::Orthogonal => orthogonal(&mut tensor, &mut rng),
+            Initializer::Zeros => {}
+            Initializer::Ones => tensor.data.fill(1.0),
+            Initializer::Constant(value) => tensor.data.fill(value),
+        }
+
+        tensor
+    }
+}
+
+//(fan_in, fan_out) of a parameter. Vectors like biases count as both.
+fn fans(shape: &[usize]) -> (usize, usize) {
+    let fans = match shape {
+        [] => (1, 1),
+        [n] => (*n, *n),
+        [outputs, inputs, kernel @ ..] => {
+            let receptive_field: usize = kernel.iter().product();
+            (inputs * receptive_field, outputs * receptive_field)
+        }
+    };
+    (fans.0.max(1), fans.1.max(1))
+}
+
+fn uniform(tensor: &mut Tensor, bound: f64, rng: &mut impl Rng) {
+    for value in tensor.data.iter_mut() {
+        *value = (rng.gen::<f64>() * 2.0 - 1.0) * bound;
+    }
+}
+
+fn normal(tensor: &mut Tensor, stddev: f64, rng: &mut impl Rng) {
+    for value in tensor.data.iter_mut() {
+        *value = standard_normal(rng) * stddev;
+    }
+}
+
+//Gram-Schmidt over gaussian vectors, treating the tensor as [shape[0], rest]
+fn orthogonal(tensor: &mut Tensor, rng: &mut impl Rng) {
+    let rows = tensor.shape.first().copied().unwrap_or(1);
+    let cols = tensor.data.len() / rows.max(1);
+    //Orthonormalise along whichever side has room for it
+    let (count, length) = if rows <= cols {
+        (rows, cols)
+    } else {
+        (cols, rows)
+    };
+
+    let mut vectors: Vec<Vec<f64>> = Vec::with_capacity(count);
+    while vectors.len() < count {
+        let mut vector: Vec<f64> = (0..length).map(|_| standard_normal(rng)).collect();
+        for basis in &vectors {
+            let projection: f64 = vector.iter().zip(basis).map(|(v, b)| v * b).sum();
+            for (v, b) in vector.iter_mut().zip(basis) {
+                *v -= projection * b;
+            }
+        }
+        let norm = vector.iter().map(|v| v * v).sum::<f64>().sqrt();
+        //A draw that was nearly dependent on the basis is thrown away
+        if norm > 1e-6 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+            vectors.push(vector);
+        }
+    }
+
+    for (i, vector) in vectors.iter().enumerate() {
+        for (j, v) in vector.iter().enumerate() {
+            let index = if rows <= cols {
+                i * cols + j
+            } else {
+                j * cols + i
+            };
+            tensor.data[index] = *v;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::activations::{Activation, PReLU, RELU, SELU, SIGMOID, TANH};
+
+    fn variance(tensor: &Tensor) -> f64 {
+        let n = tensor.data.len() as f64;
+        let mean = tensor.data.iter().sum::<f64>() / n;
+        tensor.data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n
+    }
+
+    //Largest |A A^T - I| entry over the rows of a [rows, rest] matrix
+    fn gram_error(tensor: &Tensor, rows: usize) -> f64 {
+        let cols = tensor.data.len() / rows;
+        let row = |i: usize| &tensor.data[i * cols..(i + 1) * cols];
+        let mut max_error: f64 = 0.0;
+        for i in 0..rows {
+            for j in 0..rows {
+                let dot: f64 = row(i).iter().zip(row(j)).map(|(a, b)| a * b).sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                max_error = max_error.max((dot - expected).abs());
+            }
+        }
+        max_error
+    }
+
+    #[test]
+    fn fans_of_dense_and_conv_shapes() {
+        //Dense [outputs, inputs]
+        assert_eq!(fans(&[64, 32]), (32, 64));
+        //Conv2d [out_channels, in_channels, kh, kw]
+        assert_eq!(fans(&[16, 8, 3, 3]), (72, 144));
+        //Conv1d [out_channels, in_channels, k]
+        assert_eq!(fans(&[4, 2, 5]), (10, 20));
+        //Biases
+        assert_eq!(fans(&[10]), (10, 10));
+        assert_eq!(fans(&[]), (1, 1));
+    }
+
+    #[test]
+    fn he_normal_variance() {
+        let weights = Initializer::HeNormal.initialize(vec![200, 8, 3, 3]);
+        let expected = 2.0 / 72.0;
+        let actual = variance(&weights);
+        assert!(
+            (actual - expected).abs() < 0.05 * expected,
+            "variance {}",
+            actual
+        );
+    }
+
+    #[test]
+    fn xavier_uniform_variance() {
+        let weights = Initializer::XavierUniform.initialize(vec![300, 100]);
+        let expected = 2.0 / 400.0;
+        let bound = (6.0f64 / 400.0).sqrt();
+        let actual = variance(&weights);
+        assert!(
+            (actual - expected).abs() < 0.05 * expected,
+            "variance {}",
+            actual
+        );
+        assert!(weights.data.iter().all(|w| w.abs() <= bound));
+    }
+
+    #[test]
+    fn orthogonal_rows_are_orthonormal() {
+        let wide = Initializer::Orthogonal.initialize(vec![4, 9]);
+        assert!(gram_error(&wide, 4) < 1e-9);
+
+        //More rows than columns, so the columns are orthonormal instead
+        let tall = Initializer::Orthogonal.initialize(vec![9, 4]);
+        assert!(gram_error(&tall.transpose(), 4) < 1e-9);
+
+        //Kernels are flattened to [out_channels, in_channels * kernel]
+        let kernel = Initializer::Orthogonal.initialize(vec![3, 2, 2, 2]);
+        assert!(gram_error(&kernel, 3) < 1e-9);
+    }
+
+    #[test]
+    fn activations_pick_their_initializer() {
+        assert_eq!(RELU.default_initializer(), Initializer::HeNormal);
+        assert_eq!(PReLU::new(1).default_initializer(), Initializer::HeNormal);
+        assert_eq!(SELU.default_initializer(), Initializer::LeCunNormal);
+        assert_eq!(SIGMOID.default_initializer(), Initializer::XavierUniform);
+        assert_eq!(TANH.default_initializer(), Initializer::XavierUniform);
+    }
+}
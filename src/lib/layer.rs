@@ -0,0 +1,241 @@
+use super::{activations::Activation, tensor::Tensor};
+use std::{error::Error, fmt};
+
+//A single step of a network. Layers cache whatever they need from forward
+//so that backward can turn the gradient of the loss w.r.t. their output
+//into the gradient w.r.t. their input, storing the parameter gradients on the way.
+pub trait Layer {
+    fn forward(&mut self, input: &Tensor) -> Tensor;
+
+    //Takes dLoss/dOutput of the last forward call and returns dLoss/dInput
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor;
+
+    //Trainable tensors, in the same order as gradients()
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        Vec::new()
+    }
+
+    //Gradients computed by the last backward call, in the same order as parameters()
+    fn gradients(&self) -> Vec<&Tensor> {
+        Vec::new()
+    }
+
+    //Plain gradient descent step on every parameter. Layers whose gradients are mostly
+    //zero, like Embedding, override it to only touch the rows the last backward call wrote.
+    fn update(&mut self, learning_rate: f64) {
+        let gradients: Vec<Tensor> = self.gradients().into_iter().cloned().collect();
+        for (parameter, mut gradient) in self.parameters().into_iter().zip(gradients) {
+            parameter.subtract(&gradient.multiply_scalar(learning_rate));
+        }
+    }
+
+    //Switches between training and evaluation behaviour, for layers like BatchNorm that differ
+    fn set_training(&mut self, _training: bool) {}
+
+    //Shape of a single sample after this layer, given the shape of a single sample before it,
+    //or why the layer cannot take samples of that shape
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError>;
+
+    //Estimated floating point operations for one sample of the given shape, counting a
+    //multiply-add as two. Layers that only move data report 0.
+    fn flops(&self, _input_shape: &[usize]) -> usize {
+        0
+    }
+
+    //Number of trainable values. gradients() mirrors parameters(), so it can be counted
+    //without mutable access.
+    fn trainable_parameters(&self) -> usize {
+        self.gradients().iter().map(|g| g.data.len()).sum()
+    }
+
+    //Values the layer keeps without training them, like BatchNorm running statistics
+    fn non_trainable_parameters(&self) -> usize {
+        0
+    }
+
+    //Axis of the output tensor that holds the batch, given the axis holding it in the input:
+    //0 for batch-first [N, ...] tensors, 1 for [features, N] columns and time-major [T, N, F].
+    //Most layers leave the batch where it is.
+    fn batch_axis(&self, input_batch_axis: usize) -> usize {
+        input_batch_axis
+    }
+
+    fn name(&self) -> &str;
+}
+
+//--------------------------------------------------------------Shape Errors---------------------------------------------------------------------
+
+//A layer given samples of a shape it cannot process, found while inferring shapes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeError {
+    pub layer: String,
+    //Position of the layer in its network, when known
+    pub position: Option<usize>,
+    //Description of the accepted sample shapes, e.g. "[3, *, *]"
+    pub expected: String,
+    pub actual: Vec<usize>,
+}
+
+impl ShapeError {
+    pub fn new(layer: &str, expected: impl Into<String>, actual: &[usize]) -> ShapeError {
+        ShapeError {
+            layer: layer.to_string(),
+            position: None,
+            expected: expected.into(),
+            actual: actual.to_vec(),
+        }
+    }
+
+    pub fn at(mut self, position: usize) -> ShapeError {
+        self.position = Some(position);
+        self
+    }
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "Layer {} ({})", position, self.layer)?,
+            None => write!(f, "{}", self.layer)?,
+        }
+        write!(
+            f,
+            " expects samples shaped {}, got {:?}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl Error for ShapeError {}
+
+//--------------------------------------------------------------Input Layer---------------------------------------------------------------------
+
+//Marks the start of a network, passes its input through unchanged.
+//The shape is that of a single sample, e.g. [features] or [C, H, W].
+pub struct InputLayer {
+    pub shape: Vec<usize>,
+}
+
+impl InputLayer {
+    pub fn new(shape: Vec<usize>) -> InputLayer {
+        InputLayer { shape }
+    }
+}
+
+impl Layer for InputLayer {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        input.clone()
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        grad_output.clone()
+    }
+
+    fn output_shape(&self, _input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(self.shape.clone())
+    }
+
+    fn name(&self) -> &str {
+        "InputLayer"
+    }
+}
+
+//--------------------------------------------------------------Flatten---------------------------------------------------------------------
+
+//Turns a batch-first [N, ...] tensor into the [features, N] columns a TensorLayer expects,
+//so convolutional blocks can feed into fully connected ones
+pub struct Flatten {
+    input_shape: Vec<usize>,
+}
+
+impl Flatten {
+    pub fn new() -> Flatten {
+        Flatten {
+            input_shape: Vec::new(),
+        }
+    }
+}
+
+impl Default for Flatten {
+    fn default() -> Flatten {
+        Flatten::new()
+    }
+}
+
+impl Layer for Flatten {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let n = input.shape[0];
+        let features = input.data.len() / n;
+        self.input_shape = input.shape.clone();
+        Tensor::from(vec![n, features], input.data.clone()).transpose()
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        grad_output.transpose().reshape(self.input_shape.clone())
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(vec![input_shape.iter().product()])
+    }
+
+    //Batch-first in, [features, N] columns out
+    fn batch_axis(&self, _input_batch_axis: usize) -> usize {
+        1
+    }
+
+    fn name(&self) -> &str {
+        "Flatten"
+    }
+}
+
+//--------------------------------------------------------------Activation Layer---------------------------------------------------------------------
+
+//Applies an activation on its own to a tensor of any shape, e.g. after a convolution
+pub struct ActivationLayer<A: Activation> {
+    pub activation: A,
+    input: Tensor,
+    output: Tensor,
+}
+
+impl<A: Activation> ActivationLayer<A> {
+    pub fn new(activation: A) -> ActivationLayer<A> {
+        ActivationLayer {
+            activation,
+            input: Tensor::new(vec![0]),
+            output: Tensor::new(vec![0]),
+        }
+    }
+}
+
+impl<A: Activation> Layer for ActivationLayer<A> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.input = input.clone();
+        self.output = self.activation.forward(input);
+        self.output.clone()
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        self.activation
+            .backward(&self.input, &self.output, grad_output)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        self.activation.parameters()
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.activation.gradients()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        Ok(input_shape.to_vec())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn name(&self) -> &str {
+        self.activation.name()
+    }
+}
@@ -1,14 +1,34 @@
-use super::{activations::Activation, tensor::Tensor};
+use super::{
+    activations::{Activation, LogSoftmax},
+    tensor::Tensor,
+};
+
+//Objective a network is trained to minimise. `function` reduces the loss to a single value,
+//`derivative` is its gradient with respect to the prediction.
+pub trait LossFunction {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64;
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor;
+
+    //Weighted loss of every element before the reduction, for losses that keep them.
+    //Others return `function` as a single element tensor.
+    fn losses(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        Tensor::from(vec![1], vec![self.function(predicted, actual)])
+    }
 
-pub struct LossFunction {
-    pub name: &'static str,
-    pub function: fn(&Tensor, &Tensor) -> f64,
-    pub derivative: fn(&Tensor, &Tensor, &Activation<'static>) -> Tensor,
+    //How `function` reduces the element losses. Reduction::None asks callers for `losses`.
+    fn reduction(&self) -> Reduction {
+        Reduction::Mean
+    }
+
+    fn name(&self) -> &str;
 }
 
-pub const MSE: LossFunction = LossFunction {
-    name: "Mean Squared Error",
-    function: |predicted: &Tensor, actual: &Tensor| {
+pub struct MeanSquaredError;
+pub const MSE: MeanSquaredError = MeanSquaredError;
+
+impl LossFunction for MeanSquaredError {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
         //Function
 
         //MSE = 1/n Sum[i=1] (actual - predicted)^2
@@ -20,8 +40,9 @@ pub const MSE: LossFunction = LossFunction {
 
         //Sum[i=1] / n
         diff.data.iter().sum::<f64>() / (actual.data.len() as f64)
-    },
-    derivative: |predicted: &Tensor, actual: &Tensor, _activation: &Activation<'static>| {
+    }
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
         // Derivative of MSE
         assert!(
             predicted.shape == actual.shape,
@@ -38,5 +59,1050 @@ pub const MSE: LossFunction = LossFunction {
         }
 
         gradient
-    },
-};
+    }
+
+    fn name(&self) -> &str {
+        "Mean Squared Error"
+    }
+}
+
+//--------------------------------------------------------------Cross Entropy---------------------------------------------------------------------
+
+//Multi-class cross entropy on raw logits, fused with log-softmax so large logits cannot overflow.
+//Classes run along the axis, 0 for the [classes, batch] columns of a TensorLayer. Targets are
+//either class indices with the class axis removed ([batch] for [classes, batch] logits) or
+//one-hot/soft distributions shaped like the logits.
+pub struct CrossEntropy {
+    axis: usize,
+    ignore_index: Option<i64>,
+    weights: Option<Vec<f64>>,
+    label_smoothing: f64,
+}
+
+impl CrossEntropy {
+    pub fn new() -> CrossEntropy {
+        CrossEntropy {
+            axis: 0,
+            ignore_index: None,
+            weights: None,
+            label_smoothing: 0.0,
+        }
+    }
+
+    //Axis 1 for batch-first [N, C] logits, the last axis for [N, L, vocab] sequences
+    pub fn with_axis(mut self, axis: usize) -> CrossEntropy {
+        self.axis = axis;
+        self
+    }
+
+    //Samples with this target add nothing to the loss or the mean, e.g. padding tokens.
+    //Negative sentinels like -100 work too.
+    pub fn with_ignore_index(mut self, ignore_index: i64) -> CrossEntropy {
+        self.ignore_index = Some(ignore_index);
+        self
+    }
+
+    //Rescales each class's contribution. With index targets the mean is taken over the
+    //weights of the targets rather than the number of samples.
+    pub fn with_weights(mut self, weights: Vec<f64>) -> CrossEntropy {
+        self.weights = Some(weights);
+        self
+    }
+
+    //Mixes every target with the uniform distribution: (1 - smoothing) * target + smoothing / C
+    pub fn with_label_smoothing(mut self, smoothing: f64) -> CrossEntropy {
+        assert!(
+            (0.0..=1.0).contains(&smoothing),
+            "Label smoothing must be in [0, 1], got {}",
+            smoothing
+        );
+        self.label_smoothing = smoothing;
+        self
+    }
+
+    //Mean loss and its gradient with respect to the logits.
+    //Per sample, with q the (smoothed) target and w the class weights,
+    //loss = -sum_c w_c q_c log p_c and dloss/dz_j = p_j sum_c w_c q_c - w_j q_j.
+    fn evaluate(&self, predicted: &Tensor, actual: &Tensor) -> (f64, Tensor) {
+        let log_probs = LogSoftmax::new(self.axis).forward(predicted);
+        let classes = predicted.shape[self.axis];
+        let inner: usize = predicted.shape[self.axis + 1..].iter().product();
+        let outer: usize = predicted.shape[..self.axis].iter().product();
+
+        let soft = actual.shape == predicted.shape;
+        assert!(
+            soft || actual.data.len() * classes == predicted.data.len(),
+            "Cross entropy targets must be shaped like the logits {:?} or hold one class index per sample, got {:?}",
+            predicted.shape,
+            actual.shape
+        );
+        if let Some(weights) = &self.weights {
+            assert!(
+                weights.len() == classes,
+                "Got {} class weights for {} classes",
+                weights.len(),
+                classes
+            );
+        }
+        let weight = |class: usize| self.weights.as_ref().map_or(1.0, |w| w[class]);
+
+        let mut total = 0.0;
+        let mut normaliser = 0.0;
+        let mut gradient = Tensor::new(predicted.shape.clone());
+        let mut target = vec![0.0; classes];
+
+        for o in 0..outer {
+            for i in 0..inner {
+                let index = |k: usize| (o * classes + k) * inner + i;
+
+                if soft {
+                    for (k, t) in target.iter_mut().enumerate() {
+                        *t = actual.data[index(k)];
+                    }
+                    normaliser += 1.0;
+                } else {
+                    //Checked before casting, which would turn negative targets into class 0
+                    let value = actual.data[o * inner + i];
+                    if self.ignore_index.map(|ignore| ignore as f64) == Some(value) {
+                        continue;
+                    }
+                    assert!(
+                        value >= 0.0 && value.fract() == 0.0 && value < classes as f64,
+                        "Class index {} is out of range for {} classes",
+                        value,
+                        classes
+                    );
+                    let class = value as usize;
+                    target.fill(0.0);
+                    target[class] = 1.0;
+                    normaliser += weight(class);
+                }
+
+                for t in target.iter_mut() {
+                    *t = (1.0 - self.label_smoothing) * *t + self.label_smoothing / classes as f64;
+                }
+
+                let weighted_target: f64 = (0..classes).map(|k| weight(k) * target[k]).sum();
+                for (k, &t) in target.iter().enumerate() {
+                    let log_prob = log_probs.data[index(k)];
+                    total -= weight(k) * t * log_prob;
+                    gradient.data[index(k)] = log_prob.exp() * weighted_target - weight(k) * t;
+                }
+            }
+        }
+
+        //Every sample ignored
+        if normaliser == 0.0 {
+            return (0.0, gradient);
+        }
+        (
+            total / normaliser,
+            gradient.multiply_scalar(1.0 / normaliser),
+        )
+    }
+}
+
+impl Default for CrossEntropy {
+    fn default() -> CrossEntropy {
+        CrossEntropy::new()
+    }
+}
+
+impl LossFunction for CrossEntropy {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
+        self.evaluate(predicted, actual).0
+    }
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        self.evaluate(predicted, actual).1
+    }
+
+    fn name(&self) -> &str {
+        "Cross Entropy"
+    }
+}
+
+//--------------------------------------------------------------Binary Cross Entropy---------------------------------------------------------------------
+
+//Independent yes/no targets in [0, 1] for every element, like multi-label classification.
+//Predictions are probabilities, e.g. the output of a sigmoid layer. Class weights apply along
+//axis 0, the features of a TensorLayer.
+pub struct BinaryCrossEntropy {
+    weights: Option<Vec<f64>>,
+    label_smoothing: f64,
+}
+
+impl BinaryCrossEntropy {
+    pub fn new() -> BinaryCrossEntropy {
+        BinaryCrossEntropy {
+            weights: None,
+            label_smoothing: 0.0,
+        }
+    }
+
+    pub fn with_weights(mut self, weights: Vec<f64>) -> BinaryCrossEntropy {
+        self.weights = Some(weights);
+        self
+    }
+
+    //Pulls targets towards 0.5: (1 - smoothing) * target + smoothing / 2
+    pub fn with_label_smoothing(mut self, smoothing: f64) -> BinaryCrossEntropy {
+        assert!(
+            (0.0..=1.0).contains(&smoothing),
+            "Label smoothing must be in [0, 1], got {}",
+            smoothing
+        );
+        self.label_smoothing = smoothing;
+        self
+    }
+}
+
+impl Default for BinaryCrossEntropy {
+    fn default() -> BinaryCrossEntropy {
+        BinaryCrossEntropy::new()
+    }
+}
+
+//Keeps log(p) and log(1 - p) finite for saturated predictions
+const PROBABILITY_EPSILON: f64 = 1e-12;
+
+impl LossFunction for BinaryCrossEntropy {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
+        let weight = class_weights(&self.weights, predicted, actual);
+        let total: f64 = (0..predicted.data.len())
+            .map(|i| {
+                let p = predicted.data[i].clamp(PROBABILITY_EPSILON, 1.0 - PROBABILITY_EPSILON);
+                let t = smooth_binary(actual.data[i], self.label_smoothing);
+                -weight(i) * (t * p.ln() + (1.0 - t) * (1.0 - p).ln())
+            })
+            .sum();
+        total / predicted.data.len() as f64
+    }
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        let weight = class_weights(&self.weights, predicted, actual);
+        let n = predicted.data.len() as f64;
+        let data = (0..predicted.data.len())
+            .map(|i| {
+                let p = predicted.data[i].clamp(PROBABILITY_EPSILON, 1.0 - PROBABILITY_EPSILON);
+                let t = smooth_binary(actual.data[i], self.label_smoothing);
+                weight(i) * (p - t) / (p * (1.0 - p)) / n
+            })
+            .collect();
+        Tensor::from(predicted.shape.clone(), data)
+    }
+
+    fn name(&self) -> &str {
+        "Binary Cross Entropy"
+    }
+}
+
+//Binary cross entropy on raw logits with the sigmoid folded in, which stays accurate where a
+//separate sigmoid would round to exactly 0 or 1. Prefer it to BinaryCrossEntropy.
+pub struct BCEWithLogits {
+    weights: Option<Vec<f64>>,
+    label_smoothing: f64,
+}
+
+impl BCEWithLogits {
+    pub fn new() -> BCEWithLogits {
+        BCEWithLogits {
+            weights: None,
+            label_smoothing: 0.0,
+        }
+    }
+
+    pub fn with_weights(mut self, weights: Vec<f64>) -> BCEWithLogits {
+        self.weights = Some(weights);
+        self
+    }
+
+    pub fn with_label_smoothing(mut self, smoothing: f64) -> BCEWithLogits {
+        assert!(
+            (0.0..=1.0).contains(&smoothing),
+            "Label smoothing must be in [0, 1], got {}",
+            smoothing
+        );
+        self.label_smoothing = smoothing;
+        self
+    }
+}
+
+impl Default for BCEWithLogits {
+    fn default() -> BCEWithLogits {
+        BCEWithLogits::new()
+    }
+}
+
+impl LossFunction for BCEWithLogits {
+    //max(x, 0) - x t + log(1 + e^-|x|)
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
+        let weight = class_weights(&self.weights, predicted, actual);
+        let total: f64 = (0..predicted.data.len())
+            .map(|i| {
+                let x = predicted.data[i];
+                let t = smooth_binary(actual.data[i], self.label_smoothing);
+                weight(i) * (x.max(0.0) - x * t + (-x.abs()).exp().ln_1p())
+            })
+            .sum();
+        total / predicted.data.len() as f64
+    }
+
+    //sigmoid(x) - t
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        let weight = class_weights(&self.weights, predicted, actual);
+        let n = predicted.data.len() as f64;
+        let data = (0..predicted.data.len())
+            .map(|i| {
+                let x = predicted.data[i];
+                let t = smooth_binary(actual.data[i], self.label_smoothing);
+                let sigmoid = if x >= 0.0 {
+                    1.0 / (1.0 + (-x).exp())
+                } else {
+                    x.exp() / (1.0 + x.exp())
+                };
+                weight(i) * (sigmoid - t) / n
+            })
+            .collect();
+        Tensor::from(predicted.shape.clone(), data)
+    }
+
+    fn name(&self) -> &str {
+        "Binary Cross Entropy With Logits"
+    }
+}
+
+//--------------------------------------------------------------Helpers---------------------------------------------------------------------
+
+fn check_shapes(predicted: &Tensor, actual: &Tensor) {
+    assert!(
+        predicted.shape == actual.shape,
+        "Shape mismatch: predicted shape {:?} and actual shape {:?}",
+        predicted.shape,
+        actual.shape
+    );
+}
+
+//Weight of every element from per-sample weights, samples running along the last axis
+fn sample_weights<'a>(
+    weights: &'a Option<Vec<f64>>,
+    shape: &[usize],
+) -> impl Fn(usize) -> f64 + 'a {
+    let samples = shape.last().copied().unwrap_or(1);
+    if let Some(weights) = weights {
+        assert!(
+            weights.len() == samples,
+            "Got {} sample weights for {} samples along the last axis of {:?}",
+            weights.len(),
+            samples,
+            shape
+        );
+    }
+    move |index| weights.as_ref().map_or(1.0, |w| w[index % samples])
+}
+
+//Factor turning the weighted sum of element losses into the reduced loss
+fn reduction_scale(reduction: Reduction, weights: &Option<Vec<f64>>, shape: &[usize]) -> f64 {
+    match reduction {
+        Reduction::Sum | Reduction::None => 1.0,
+        Reduction::Mean => {
+            let elements: usize = shape.iter().product();
+            let total_weight = match weights {
+                Some(weights) => weights.iter().sum::<f64>() * (elements / weights.len()) as f64,
+                None => elements as f64,
+            };
+            if total_weight == 0.0 {
+                0.0
+            } else {
+                1.0 / total_weight
+            }
+        }
+    }
+}
+
+fn smooth_binary(target: f64, smoothing: f64) -> f64 {
+    (1.0 - smoothing) * target + smoothing / 2.0
+}
+
+//Weight of every element from per-class weights along axis 0, checking the shapes on the way
+fn class_weights<'a>(
+    weights: &'a Option<Vec<f64>>,
+    predicted: &Tensor,
+    actual: &Tensor,
+) -> impl Fn(usize) -> f64 + 'a {
+    assert!(
+        predicted.shape == actual.shape,
+        "Shape mismatch: predicted shape {:?} and actual shape {:?}",
+        predicted.shape,
+        actual.shape
+    );
+    let classes = predicted.shape.first().copied().unwrap_or(1);
+    if let Some(weights) = weights {
+        assert!(
+            weights.len() == classes,
+            "Got {} class weights for {} classes along axis 0",
+            weights.len(),
+            classes
+        );
+    }
+    let per_class = predicted.data.len() / classes.max(1);
+    move |index| weights.as_ref().map_or(1.0, |w| w[index / per_class])
+}
+
+//--------------------------------------------------------------Regression---------------------------------------------------------------------
+
+//How the loss of every element becomes the single value a network minimises.
+//None keeps the per-element losses, which callers read through LossFunction::losses.
+//Where a single value is still needed it is their sum, so the derivative of every element
+//is that of its own loss.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reduction {
+    Mean,
+    Sum,
+    None,
+}
+
+//Loss of a single prediction against its target, made into a LossFunction by Regression
+pub trait ElementLoss {
+    fn loss(&self, predicted: f64, actual: f64) -> f64;
+
+    //With respect to the prediction
+    fn derivative(&self, predicted: f64, actual: f64) -> f64;
+
+    fn name(&self) -> &str;
+}
+
+//Elementwise loss over predictions and targets of the same shape, with a reduction and
+//optional per-sample weights. Samples run along the last axis, the batch of [features, batch]
+//columns, and a weighted mean divides by the total weight.
+pub struct Regression<K: ElementLoss> {
+    pub kernel: K,
+    reduction: Reduction,
+    sample_weights: Option<Vec<f64>>,
+}
+
+pub type MAE = Regression<AbsoluteError>;
+pub type L1Loss = Regression<AbsoluteError>;
+pub type HuberLoss = Regression<HuberError>;
+pub type SmoothL1Loss = Regression<SmoothL1Error>;
+pub type LogCosh = Regression<LogCoshError>;
+pub type QuantileLoss = Regression<PinballError>;
+pub type PinballLoss = Regression<PinballError>;
+pub type PoissonNLL = Regression<PoissonError>;
+
+impl<K: ElementLoss> Regression<K> {
+    pub fn from_kernel(kernel: K) -> Regression<K> {
+        Regression {
+            kernel,
+            reduction: Reduction::Mean,
+            sample_weights: None,
+        }
+    }
+
+    pub fn with_reduction(mut self, reduction: Reduction) -> Regression<K> {
+        self.reduction = reduction;
+        self
+    }
+
+    pub fn with_sample_weights(mut self, weights: Vec<f64>) -> Regression<K> {
+        self.sample_weights = Some(weights);
+        self
+    }
+
+    //Weights change with every batch, so they can be swapped without rebuilding the loss
+    pub fn set_sample_weights(&mut self, weights: Option<Vec<f64>>) {
+        self.sample_weights = weights;
+    }
+}
+
+impl<K: ElementLoss> LossFunction for Regression<K> {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
+        let total: f64 = self.losses(predicted, actual).data.iter().sum();
+        total * reduction_scale(self.reduction, &self.sample_weights, &actual.shape)
+    }
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        check_shapes(predicted, actual);
+        let weight = sample_weights(&self.sample_weights, &actual.shape);
+        let scale = reduction_scale(self.reduction, &self.sample_weights, &actual.shape);
+        let data = (0..predicted.data.len())
+            .map(|i| scale * weight(i) * self.kernel.derivative(predicted.data[i], actual.data[i]))
+            .collect();
+        Tensor::from(predicted.shape.clone(), data)
+    }
+
+    //Shaped like the prediction
+    fn losses(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        check_shapes(predicted, actual);
+        let weight = sample_weights(&self.sample_weights, &actual.shape);
+        let data = (0..predicted.data.len())
+            .map(|i| weight(i) * self.kernel.loss(predicted.data[i], actual.data[i]))
+            .collect();
+        Tensor::from(predicted.shape.clone(), data)
+    }
+
+    fn reduction(&self) -> Reduction {
+        self.reduction
+    }
+
+    fn name(&self) -> &str {
+        self.kernel.name()
+    }
+}
+
+//|predicted - actual|
+pub struct AbsoluteError;
+
+impl ElementLoss for AbsoluteError {
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        (predicted - actual).abs()
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        let residual = predicted - actual;
+        if residual == 0.0 {
+            0.0
+        } else {
+            residual.signum()
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Mean Absolute Error"
+    }
+}
+
+impl Regression<AbsoluteError> {
+    pub fn new() -> MAE {
+        Regression::from_kernel(AbsoluteError)
+    }
+}
+
+impl Default for Regression<AbsoluteError> {
+    fn default() -> MAE {
+        MAE::new()
+    }
+}
+
+//Squared error within delta of the target, absolute error scaled by delta beyond it
+pub struct HuberError {
+    pub delta: f64,
+}
+
+impl ElementLoss for HuberError {
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        let residual = (predicted - actual).abs();
+        if residual <= self.delta {
+            0.5 * residual * residual
+        } else {
+            self.delta * (residual - 0.5 * self.delta)
+        }
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        (predicted - actual).clamp(-self.delta, self.delta)
+    }
+
+    fn name(&self) -> &str {
+        "Huber"
+    }
+}
+
+impl Regression<HuberError> {
+    pub fn new(delta: f64) -> HuberLoss {
+        assert!(delta > 0.0, "Huber delta must be positive, got {}", delta);
+        Regression::from_kernel(HuberError { delta })
+    }
+}
+
+//Huber divided by beta, so large errors cost |residual| - beta / 2 whatever beta is
+pub struct SmoothL1Error {
+    pub beta: f64,
+}
+
+impl ElementLoss for SmoothL1Error {
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        let residual = (predicted - actual).abs();
+        if residual < self.beta {
+            0.5 * residual * residual / self.beta
+        } else {
+            residual - 0.5 * self.beta
+        }
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        ((predicted - actual) / self.beta).clamp(-1.0, 1.0)
+    }
+
+    fn name(&self) -> &str {
+        "Smooth L1"
+    }
+}
+
+impl Regression<SmoothL1Error> {
+    pub fn new(beta: f64) -> SmoothL1Loss {
+        assert!(beta > 0.0, "Smooth L1 beta must be positive, got {}", beta);
+        Regression::from_kernel(SmoothL1Error { beta })
+    }
+}
+
+//log(cosh(residual)): quadratic for small errors, linear for large ones, smooth everywhere
+pub struct LogCoshError;
+
+impl ElementLoss for LogCoshError {
+    //|r| + log(1 + e^-2|r|) - log 2, which cannot overflow
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        let residual = (predicted - actual).abs();
+        residual + (-2.0 * residual).exp().ln_1p() - std::f64::consts::LN_2
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        (predicted - actual).tanh()
+    }
+
+    fn name(&self) -> &str {
+        "Log Cosh"
+    }
+}
+
+impl Regression<LogCoshError> {
+    pub fn new() -> LogCosh {
+        Regression::from_kernel(LogCoshError)
+    }
+}
+
+impl Default for Regression<LogCoshError> {
+    fn default() -> LogCosh {
+        LogCosh::new()
+    }
+}
+
+//Pinball loss, minimised by the given quantile of the target distribution.
+//Under-predictions cost quantile per unit, over-predictions 1 - quantile.
+pub struct PinballError {
+    pub quantile: f64,
+}
+
+impl ElementLoss for PinballError {
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        let residual = actual - predicted;
+        (self.quantile * residual).max((self.quantile - 1.0) * residual)
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        if actual > predicted {
+            -self.quantile
+        } else {
+            1.0 - self.quantile
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Quantile"
+    }
+}
+
+impl Regression<PinballError> {
+    pub fn new(quantile: f64) -> QuantileLoss {
+        assert!(
+            quantile > 0.0 && quantile < 1.0,
+            "Quantile must be in (0, 1), got {}",
+            quantile
+        );
+        Regression::from_kernel(PinballError { quantile })
+    }
+}
+
+//Negative log likelihood of count targets under a Poisson distribution, dropping the
+//constant log(actual!). By default the prediction is the log of the rate.
+pub struct PoissonError {
+    pub log_input: bool,
+}
+
+//Keeps log(rate) finite when the rate is given directly
+const RATE_EPSILON: f64 = 1e-8;
+
+impl ElementLoss for PoissonError {
+    fn loss(&self, predicted: f64, actual: f64) -> f64 {
+        if self.log_input {
+            predicted.exp() - actual * predicted
+        } else {
+            predicted - actual * (predicted + RATE_EPSILON).ln()
+        }
+    }
+
+    fn derivative(&self, predicted: f64, actual: f64) -> f64 {
+        if self.log_input {
+            predicted.exp() - actual
+        } else {
+            1.0 - actual / (predicted + RATE_EPSILON)
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Poisson NLL"
+    }
+}
+
+impl Regression<PoissonError> {
+    pub fn new() -> PoissonNLL {
+        Regression::from_kernel(PoissonError { log_input: true })
+    }
+
+    //Predictions are the rate itself, e.g. after a softplus
+    pub fn with_rate_input(mut self) -> PoissonNLL {
+        self.kernel.log_input = false;
+        self
+    }
+}
+
+impl Default for Regression<PoissonError> {
+    fn default() -> PoissonNLL {
+        PoissonNLL::new()
+    }
+}
+
+//Negative log likelihood of the targets under a gaussian whose mean and log variance are both
+//predicted. The prediction stacks the two along axis 0: a [features, batch] target takes a
+//[2 * features, batch] prediction with the means first and the log variances after.
+pub struct GaussianNLL {
+    full: bool,
+    reduction: Reduction,
+    sample_weights: Option<Vec<f64>>,
+}
+
+impl GaussianNLL {
+    pub fn new() -> GaussianNLL {
+        GaussianNLL {
+            full: false,
+            reduction: Reduction::Mean,
+            sample_weights: None,
+        }
+    }
+
+    //Includes the constant log(2 pi) / 2, so the loss is the true negative log likelihood
+    pub fn with_full(mut self) -> GaussianNLL {
+        self.full = true;
+        self
+    }
+
+    pub fn with_reduction(mut self, reduction: Reduction) -> GaussianNLL {
+        self.reduction = reduction;
+        self
+    }
+
+    pub fn with_sample_weights(mut self, weights: Vec<f64>) -> GaussianNLL {
+        self.sample_weights = Some(weights);
+        self
+    }
+
+    pub fn set_sample_weights(&mut self, weights: Option<Vec<f64>>) {
+        self.sample_weights = weights;
+    }
+
+    fn check_shapes(&self, predicted: &Tensor, actual: &Tensor) -> usize {
+        let mut expected = actual.shape.clone();
+        if let Some(features) = expected.first_mut() {
+            *features *= 2;
+        }
+        assert!(
+            predicted.shape == expected,
+            "Gaussian NLL takes predictions shaped {:?} (means then log variances) for targets shaped {:?}, got {:?}",
+            expected,
+            actual.shape,
+            predicted.shape
+        );
+        actual.data.len()
+    }
+}
+
+impl Default for GaussianNLL {
+    fn default() -> GaussianNLL {
+        GaussianNLL::new()
+    }
+}
+
+impl LossFunction for GaussianNLL {
+    fn function(&self, predicted: &Tensor, actual: &Tensor) -> f64 {
+        let total: f64 = self.losses(predicted, actual).data.iter().sum();
+        total * reduction_scale(self.reduction, &self.sample_weights, &actual.shape)
+    }
+
+    fn derivative(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        let n = self.check_shapes(predicted, actual);
+        let weight = sample_weights(&self.sample_weights, &actual.shape);
+        let scale = reduction_scale(self.reduction, &self.sample_weights, &actual.shape);
+        let mut gradient = Tensor::new(predicted.shape.clone());
+
+        for i in 0..n {
+            let (mean, log_variance) = (predicted.data[i], predicted.data[n + i]);
+            let residual = actual.data[i] - mean;
+            let precision = (-log_variance).exp();
+            let factor = scale * weight(i);
+            gradient.data[i] = -factor * residual * precision;
+            gradient.data[n + i] = factor * 0.5 * (1.0 - residual * residual * precision);
+        }
+
+        gradient
+    }
+
+    //Shaped like the target
+    fn losses(&self, predicted: &Tensor, actual: &Tensor) -> Tensor {
+        let n = self.check_shapes(predicted, actual);
+        let weight = sample_weights(&self.sample_weights, &actual.shape);
+        let constant = if self.full {
+            0.5 * (2.0 * std::f64::consts::PI).ln()
+        } else {
+            0.0
+        };
+        let data = (0..n)
+            .map(|i| {
+                let (mean, log_variance) = (predicted.data[i], predicted.data[n + i]);
+                let residual = actual.data[i] - mean;
+                let nll = 0.5 * (log_variance + residual * residual * (-log_variance).exp());
+                weight(i) * (nll + constant)
+            })
+            .collect();
+        Tensor::from(actual.shape.clone(), data)
+    }
+
+    fn reduction(&self) -> Reduction {
+        self.reduction
+    }
+
+    fn name(&self) -> &str {
+        "Gaussian NLL"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{activations::SOFTMAX, gradient_check::loss_check};
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_derivative(loss: &dyn LossFunction, predicted: &Tensor, actual: &Tensor) {
+        let error = loss_check(loss, predicted, actual, 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            loss.name(),
+            error
+        );
+    }
+
+    //Probabilities away from the clamped ends
+    fn probabilities(shape: Vec<usize>) -> Tensor {
+        let mut tensor = Tensor::random(shape);
+        tensor.map(&|x| 0.5 + 0.4 * x)
+    }
+
+    fn binary_targets(shape: Vec<usize>) -> Tensor {
+        let mut tensor = Tensor::random(shape);
+        tensor.map(&|x| if x > 0.0 { 1.0 } else { 0.0 })
+    }
+
+    #[test]
+    fn cross_entropy_with_index_targets() {
+        let logits = Tensor::random(vec![4, 5]);
+        let classes = Tensor::from(vec![5], vec![0.0, 3.0, 1.0, 3.0, 2.0]);
+        assert_derivative(&CrossEntropy::new(), &logits, &classes);
+        assert_derivative(
+            &CrossEntropy::new()
+                .with_weights(vec![0.5, 2.0, 1.0, 1.5])
+                .with_label_smoothing(0.1),
+            &logits,
+            &classes,
+        );
+
+        let batch_first = Tensor::random(vec![5, 4]);
+        assert_derivative(&CrossEntropy::new().with_axis(1), &batch_first, &classes);
+    }
+
+    #[test]
+    fn cross_entropy_with_soft_targets() {
+        let logits = Tensor::random(vec![4, 5]);
+        let targets = SOFTMAX.forward(&Tensor::random(vec![4, 5]).multiply_scalar(3.0));
+        assert_derivative(&CrossEntropy::new(), &logits, &targets);
+        assert_derivative(
+            &CrossEntropy::new()
+                .with_weights(vec![0.5, 2.0, 1.0, 1.5])
+                .with_label_smoothing(0.2),
+            &logits,
+            &targets,
+        );
+    }
+
+    #[test]
+    fn negative_ignore_index_skips_samples() {
+        let logits = Tensor::random(vec![3, 4]);
+        let classes = Tensor::from(vec![4], vec![2.0, -100.0, 0.0, -100.0]);
+        let loss = CrossEntropy::new().with_ignore_index(-100);
+        assert_derivative(&loss, &logits, &classes);
+
+        //Same as the loss over the kept samples alone
+        let kept = Tensor::from(
+            vec![3, 2],
+            (0..3)
+                .flat_map(|c| vec![logits.get(vec![c, 0]), logits.get(vec![c, 2])])
+                .collect(),
+        );
+        let kept_classes = Tensor::from(vec![2], vec![2.0, 0.0]);
+        let expected = CrossEntropy::new().function(&kept, &kept_classes);
+        assert!((loss.function(&logits, &classes) - expected).abs() < 1e-12);
+
+        let gradient = loss.derivative(&logits, &classes);
+        for c in 0..3 {
+            assert_eq!(gradient.get(vec![c, 1]), 0.0);
+            assert_eq!(gradient.get(vec![c, 3]), 0.0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn negative_class_is_rejected() {
+        let logits = Tensor::random(vec![3, 2]);
+        let classes = Tensor::from(vec![2], vec![1.0, -1.0]);
+        CrossEntropy::new().function(&logits, &classes);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn fractional_class_is_rejected() {
+        let logits = Tensor::random(vec![3, 2]);
+        let classes = Tensor::from(vec![2], vec![1.0, 0.5]);
+        CrossEntropy::new().function(&logits, &classes);
+    }
+
+    #[test]
+    fn binary_cross_entropy_derivatives() {
+        let predicted = probabilities(vec![3, 4]);
+        let targets = binary_targets(vec![3, 4]);
+        assert_derivative(&BinaryCrossEntropy::new(), &predicted, &targets);
+        assert_derivative(
+            &BinaryCrossEntropy::new().with_weights(vec![0.5, 2.0, 1.5]),
+            &predicted,
+            &targets,
+        );
+        assert_derivative(
+            &BinaryCrossEntropy::new()
+                .with_weights(vec![0.5, 2.0, 1.5])
+                .with_label_smoothing(0.1),
+            &predicted,
+            &targets,
+        );
+    }
+
+    #[test]
+    fn bce_with_logits_derivatives() {
+        let logits = Tensor::random(vec![3, 4]).multiply_scalar(4.0);
+        let targets = binary_targets(vec![3, 4]);
+        assert_derivative(&BCEWithLogits::new(), &logits, &targets);
+        assert_derivative(
+            &BCEWithLogits::new().with_weights(vec![0.5, 2.0, 1.5]),
+            &logits,
+            &targets,
+        );
+        assert_derivative(
+            &BCEWithLogits::new()
+                .with_weights(vec![0.5, 2.0, 1.5])
+                .with_label_smoothing(0.2),
+            &logits,
+            &targets,
+        );
+    }
+
+    //Every reduction, with and without sample weights along the last axis
+    fn assert_reductions(
+        make: impl Fn(Reduction, Option<Vec<f64>>) -> Box<dyn LossFunction>,
+        predicted: &Tensor,
+        actual: &Tensor,
+    ) {
+        let samples = *actual.shape.last().unwrap();
+        let weights: Vec<f64> = (0..samples).map(|i| 0.5 + i as f64).collect();
+        let total_weight = weights.iter().sum::<f64>() * (actual.data.len() / samples) as f64;
+
+        for weights in [None, Some(weights)] {
+            let total = match &weights {
+                Some(_) => total_weight,
+                None => actual.data.len() as f64,
+            };
+            for reduction in [Reduction::Mean, Reduction::Sum, Reduction::None] {
+                let loss = make(reduction, weights.clone());
+                assert_eq!(loss.reduction(), reduction);
+                assert_derivative(loss.as_ref(), predicted, actual);
+
+                let losses = loss.losses(predicted, actual);
+                assert_eq!(losses.shape, actual.shape);
+                let sum: f64 = losses.data.iter().sum();
+                let expected = match reduction {
+                    Reduction::Mean => sum / total,
+                    Reduction::Sum | Reduction::None => sum,
+                };
+                assert!((loss.function(predicted, actual) - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    fn regression<K: ElementLoss + 'static>(
+        new: impl Fn() -> Regression<K>,
+    ) -> impl Fn(Reduction, Option<Vec<f64>>) -> Box<dyn LossFunction> {
+        move |reduction, weights| {
+            let mut loss = new().with_reduction(reduction);
+            loss.set_sample_weights(weights);
+            Box::new(loss)
+        }
+    }
+
+    #[test]
+    fn regression_derivatives() {
+        let predicted = Tensor::random(vec![3, 4]);
+        let actual = Tensor::random(vec![3, 4]).multiply_scalar(2.0);
+        assert_reductions(regression(MAE::new), &predicted, &actual);
+        assert_reductions(regression(|| HuberLoss::new(0.5)), &predicted, &actual);
+        assert_reductions(regression(|| SmoothL1Loss::new(0.7)), &predicted, &actual);
+        assert_reductions(regression(LogCosh::new), &predicted, &actual);
+        assert_reductions(regression(|| QuantileLoss::new(0.3)), &predicted, &actual);
+    }
+
+    #[test]
+    fn poisson_derivatives() {
+        let counts = Tensor::from(vec![2, 4], vec![0.0, 1.0, 3.0, 2.0, 5.0, 0.0, 1.0, 4.0]);
+        let log_rates = Tensor::random(vec![2, 4]);
+        assert_reductions(regression(PoissonNLL::new), &log_rates, &counts);
+        let rates = probabilities(vec![2, 4]).multiply_scalar(3.0);
+        assert_reductions(
+            regression(|| PoissonNLL::new().with_rate_input()),
+            &rates,
+            &counts,
+        );
+    }
+
+    #[test]
+    fn gaussian_nll_derivatives() {
+        let predicted = Tensor::random(vec![6, 4]);
+        let actual = Tensor::random(vec![3, 4]);
+        for full in [false, true] {
+            let make = |reduction, weights| -> Box<dyn LossFunction> {
+                let mut loss = GaussianNLL::new().with_reduction(reduction);
+                if full {
+                    loss = loss.with_full();
+                }
+                loss.set_sample_weights(weights);
+                Box::new(loss)
+            };
+            assert_reductions(make, &predicted, &actual);
+        }
+    }
+
+    #[test]
+    fn scalar_losses_report_a_single_value() {
+        let predicted = Tensor::random(vec![3, 4]);
+        let actual = Tensor::random(vec![3, 4]);
+        let losses = MSE.losses(&predicted, &actual);
+        assert_eq!(losses.shape, vec![1]);
+        assert_eq!(losses.data[0], MSE.function(&predicted, &actual));
+        assert_eq!(MSE.reduction(), Reduction::Mean);
+    }
+}
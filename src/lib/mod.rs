@@ -1,5 +1,21 @@
 pub mod activations;
+pub mod attention;
+pub mod complex;
+pub mod conv;
 pub mod cpu_tensor_network;
+pub mod dense;
+pub mod dropout;
+pub mod embedding;
+pub mod fft;
+pub mod gradient_check;
+pub mod graph;
+pub mod initializers;
+pub mod layer;
 pub mod loss;
+pub mod normalization;
 pub mod pooling;
+pub mod rnn;
+pub mod summary;
 pub mod tensor;
+pub mod transformer;
+pub mod upsample;
@@ -0,0 +1,793 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::Tensor,
+};
+
+//--------------------------------------------------------------Batch Normalization---------------------------------------------------------------------
+
+//Normalizes every channel with the mean and variance of the current batch while training,
+//and with running estimates of them in evaluation mode, then applies a learnable
+//scale (gamma) and shift (beta) per channel.
+//BatchNorm1d works on the [features, N] columns TensorLayer produces, BatchNorm2d on [N, C, H, W].
+pub struct BatchNorm<const D: usize> {
+    pub gamma: Tensor,
+    pub beta: Tensor,
+    pub running_mean: Tensor,
+    pub running_var: Tensor,
+    momentum: f64,
+    epsilon: f64,
+    training: bool,
+    normalized: Tensor,
+    inverse_std: Vec<f64>,
+    gamma_gradient: Tensor,
+    beta_gradient: Tensor,
+}
+
+pub type BatchNorm1d = BatchNorm<1>;
+pub type BatchNorm2d = BatchNorm<2>;
+
+impl<const D: usize> BatchNorm<D> {
+    //gamma = 1, beta = 0, momentum 0.1 and epsilon 1e-5
+    pub fn new(channels: usize) -> BatchNorm<D> {
+        BatchNorm {
+            gamma: Tensor::from(vec![channels], vec![1.0; channels]),
+            beta: Tensor::new(vec![channels]),
+            running_mean: Tensor::new(vec![channels]),
+            running_var: Tensor::from(vec![channels], vec![1.0; channels]),
+            momentum: 0.1,
+            epsilon: 1e-5,
+            training: true,
+            normalized: Tensor::new(Vec::new()),
+            inverse_std: Vec::new(),
+            gamma_gradient: Tensor::new(vec![channels]),
+            beta_gradient: Tensor::new(vec![channels]),
+        }
+    }
+
+    //Weight of the newest batch in the running statistics
+    pub fn with_momentum(mut self, momentum: f64) -> BatchNorm<D> {
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> BatchNorm<D> {
+        self.epsilon = epsilon;
+        self
+    }
+
+    fn channels(&self) -> usize {
+        self.gamma.data.len()
+    }
+
+    //Channel of the element at flat offset i
+    fn channel_of(&self, shape: &[usize], i: usize) -> usize {
+        match D {
+            1 => i / shape[1],
+            _ => {
+                let spatial: usize = shape[2..].iter().product();
+                (i / spatial) % shape[1]
+            }
+        }
+    }
+
+    fn check_shape(&self, shape: &[usize]) {
+        //[features, N] or [N, C, H, W]
+        let (rank, channel_axis) = if D == 1 { (2, 0) } else { (4, 1) };
+        assert!(
+            shape.len() == rank && shape[channel_axis] == self.channels(),
+            "{} over {} channels got input {:?}",
+            self.name(),
+            self.channels(),
+            shape
+        );
+    }
+}
+
+impl<const D: usize> Layer for BatchNorm<D> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.check_shape(&input.shape);
+        let channels = self.channels();
+        let count = (input.data.len() / channels) as f64;
+
+        let (mean, var) = if self.training {
+            let mut mean = vec![0.0; channels];
+            let mut var = vec![0.0; channels];
+            for (i, &x) in input.data.iter().enumerate() {
+                mean[self.channel_of(&input.shape, i)] += x / count;
+            }
+            for (i, &x) in input.data.iter().enumerate() {
+                let c = self.channel_of(&input.shape, i);
+                var[c] += (x - mean[c]).powi(2) / count;
+            }
+
+            //The running variance is unbiased, the batch one used for normalizing is not
+            let correction = if count > 1.0 {
+                count / (count - 1.0)
+            } else {
+                1.0
+            };
+            for c in 0..channels {
+                self.running_mean.data[c] =
+                    (1.0 - self.momentum) * self.running_mean.data[c] + self.momentum * mean[c];
+                self.running_var.data[c] = (1.0 - self.momentum) * self.running_var.data[c]
+                    + self.momentum * var[c] * correction;
+            }
+            (mean, var)
+        } else {
+            (
+                self.running_mean.data.clone(),
+                self.running_var.data.clone(),
+            )
+        };
+
+        self.inverse_std = var
+            .iter()
+            .map(|v| 1.0 / (v + self.epsilon).sqrt())
+            .collect();
+
+        let mut normalized = Tensor::new(input.shape.clone());
+        let mut res = Tensor::new(input.shape.clone());
+        for (i, &x) in input.data.iter().enumerate() {
+            let c = self.channel_of(&input.shape, i);
+            normalized.data[i] = (x - mean[c]) * self.inverse_std[c];
+            res.data[i] = self.gamma.data[c] * normalized.data[i] + self.beta.data[c];
+        }
+
+        self.normalized = normalized;
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let channels = self.channels();
+        let shape = grad_output.shape.clone();
+        let count = (grad_output.data.len() / channels) as f64;
+
+        let mut grad_sum = vec![0.0; channels];
+        let mut grad_dot = vec![0.0; channels];
+        for (i, &g) in grad_output.data.iter().enumerate() {
+            let c = self.channel_of(&shape, i);
+            grad_sum[c] += g;
+            grad_dot[c] += g * self.normalized.data[i];
+        }
+
+        self.beta_gradient = Tensor::from(vec![channels], grad_sum.clone());
+        self.gamma_gradient = Tensor::from(vec![channels], grad_dot.clone());
+
+        let mut grad_input = Tensor::new(shape.clone());
+        for (i, &g) in grad_output.data.iter().enumerate() {
+            let c = self.channel_of(&shape, i);
+            let scale = self.gamma.data[c] * self.inverse_std[c];
+            grad_input.data[i] = if self.training {
+                //The batch statistics depend on every input too
+                scale * (g - grad_sum[c] / count - self.normalized.data[i] * grad_dot[c] / count)
+            } else {
+                scale * g
+            };
+        }
+
+        grad_input
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.gamma_gradient, &self.beta_gradient]
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+
+    //Samples are [features] for BatchNorm1d and [C, H, W] for BatchNorm2d
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let rank = if D == 1 { 1 } else { 3 };
+        if input_shape.len() != rank || input_shape[0] != self.channels() {
+            let expected = if D == 1 {
+                format!("[{}]", self.channels())
+            } else {
+                format!("[{}, *, *]", self.channels())
+            };
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    //Mean, variance, normalization, scale and shift
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        5 * input_shape.iter().product::<usize>()
+    }
+
+    //Running mean and variance
+    fn non_trainable_parameters(&self) -> usize {
+        self.running_mean.data.len() + self.running_var.data.len()
+    }
+
+    fn name(&self) -> &str {
+        match D {
+            1 => "BatchNorm1d",
+            _ => "BatchNorm2d",
+        }
+    }
+}
+
+//--------------------------------------------------------------Per-Sample Normalization---------------------------------------------------------------------
+
+//LayerNorm, GroupNorm, InstanceNorm and RMSNorm all normalize contiguous lanes of the input
+//(one lane per sample, or per sample and group) with statistics of that lane alone,
+//so unlike BatchNorm they behave the same for any batch size and in train and eval mode.
+
+//Normalizes every lane of `lane` elements, returning the normalized values and 1 / std per lane.
+//Without centering the lane is divided by its root mean square instead (RMSNorm).
+fn normalize_lanes(input: &Tensor, lane: usize, epsilon: f64, center: bool) -> (Tensor, Vec<f64>) {
+    let lanes = input.reshape(vec![input.data.len() / lane, lane]);
+    let (mean, spread) = if center {
+        (lanes.mean_axes(&[1]).data, lanes.variance_axes(&[1]).data)
+    } else {
+        let squares = lanes.dot(&lanes);
+        (vec![0.0; lanes.shape[0]], squares.mean_axes(&[1]).data)
+    };
+
+    let inverse_std: Vec<f64> = spread.iter().map(|v| 1.0 / (v + epsilon).sqrt()).collect();
+    let mut normalized = input.clone();
+    for (i, value) in normalized.data.iter_mut().enumerate() {
+        *value = (*value - mean[i / lane]) * inverse_std[i / lane];
+    }
+
+    (normalized, inverse_std)
+}
+
+//Gradient through normalize_lanes. `scaled_grad` is dLoss/dnormalized, i.e. the upstream
+//gradient already multiplied by the affine scale.
+fn normalize_lanes_backward(
+    scaled_grad: &Tensor,
+    normalized: &Tensor,
+    inverse_std: &[f64],
+    lane: usize,
+    center: bool,
+) -> Tensor {
+    let mut grad_input = Tensor::new(scaled_grad.shape.clone());
+
+    for (l, &inv) in inverse_std.iter().enumerate() {
+        let range = l * lane..(l + 1) * lane;
+        let g = &scaled_grad.data[range.clone()];
+        let x = &normalized.data[range.clone()];
+
+        let grad_mean = if center {
+            g.iter().sum::<f64>() / lane as f64
+        } else {
+            0.0
+        };
+        let grad_dot = g.iter().zip(x.iter()).map(|(g, x)| g * x).sum::<f64>() / lane as f64;
+
+        for (out, (g, x)) in grad_input.data[range]
+            .iter_mut()
+            .zip(g.iter().zip(x.iter()))
+        {
+            *out = inv * (g - grad_mean - x * grad_dot);
+        }
+    }
+
+    grad_input
+}
+
+//--------------------------------------------------------------Layer Normalization---------------------------------------------------------------------
+
+//Normalizes over the trailing normalized_shape axes of each sample, e.g. the feature axis
+//of a [N, T, E] sequence, with an elementwise learnable scale and shift of that shape
+pub struct LayerNorm {
+    pub weight: Tensor,
+    pub bias: Tensor,
+    epsilon: f64,
+    normalized: Tensor,
+    inverse_std: Vec<f64>,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+impl LayerNorm {
+    pub fn new(normalized_shape: Vec<usize>) -> LayerNorm {
+        let size = normalized_shape.iter().product();
+        LayerNorm {
+            weight: Tensor::from(normalized_shape.clone(), vec![1.0; size]),
+            bias: Tensor::new(normalized_shape.clone()),
+            epsilon: 1e-5,
+            normalized: Tensor::new(Vec::new()),
+            inverse_std: Vec::new(),
+            weight_gradient: Tensor::new(normalized_shape.clone()),
+            bias_gradient: Tensor::new(normalized_shape),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> LayerNorm {
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl Layer for LayerNorm {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let dims = self.weight.shape.len();
+        assert!(
+            input.shape.len() >= dims
+                && input.shape[input.shape.len() - dims..] == self.weight.shape[..],
+            "LayerNorm over {:?} got input {:?}",
+            self.weight.shape,
+            input.shape
+        );
+        let lane = self.weight.data.len();
+        (self.normalized, self.inverse_std) = normalize_lanes(input, lane, self.epsilon, true);
+
+        let mut res = self.normalized.clone();
+        for (i, value) in res.data.iter_mut().enumerate() {
+            *value = *value * self.weight.data[i % lane] + self.bias.data[i % lane];
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let lane = self.weight.data.len();
+        let mut scaled_grad = grad_output.clone();
+        self.weight_gradient = Tensor::new(self.weight.shape.clone());
+        self.bias_gradient = Tensor::new(self.bias.shape.clone());
+
+        for (i, g) in scaled_grad.data.iter_mut().enumerate() {
+            self.weight_gradient.data[i % lane] += *g * self.normalized.data[i];
+            self.bias_gradient.data[i % lane] += *g;
+            *g *= self.weight.data[i % lane];
+        }
+
+        normalize_lanes_backward(
+            &scaled_grad,
+            &self.normalized,
+            &self.inverse_std,
+            lane,
+            true,
+        )
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.weight, &mut self.bias]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.weight_gradient, &self.bias_gradient]
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let dims = self.weight.shape.len();
+        if input_shape.len() < dims
+            || input_shape[input_shape.len() - dims..] != self.weight.shape[..]
+        {
+            let expected = format!("[*, {:?}]", self.weight.shape);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        5 * input_shape.iter().product::<usize>()
+    }
+
+    fn name(&self) -> &str {
+        "LayerNorm"
+    }
+}
+
+//--------------------------------------------------------------RMS Normalization---------------------------------------------------------------------
+
+//LayerNorm without centering or shift: x / sqrt(mean(x^2) + epsilon) * weight.
+//Cheaper, and the usual choice in recent transformer models.
+pub struct RMSNorm {
+    pub weight: Tensor,
+    epsilon: f64,
+    normalized: Tensor,
+    inverse_rms: Vec<f64>,
+    weight_gradient: Tensor,
+}
+
+impl RMSNorm {
+    pub fn new(normalized_shape: Vec<usize>) -> RMSNorm {
+        let size = normalized_shape.iter().product();
+        RMSNorm {
+            weight: Tensor::from(normalized_shape.clone(), vec![1.0; size]),
+            epsilon: 1e-6,
+            normalized: Tensor::new(Vec::new()),
+            inverse_rms: Vec::new(),
+            weight_gradient: Tensor::new(normalized_shape),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> RMSNorm {
+        self.epsilon = epsilon;
+        self
+    }
+}
+
+impl Layer for RMSNorm {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let dims = self.weight.shape.len();
+        assert!(
+            input.shape.len() >= dims
+                && input.shape[input.shape.len() - dims..] == self.weight.shape[..],
+            "RMSNorm over {:?} got input {:?}",
+            self.weight.shape,
+            input.shape
+        );
+        let lane = self.weight.data.len();
+        (self.normalized, self.inverse_rms) = normalize_lanes(input, lane, self.epsilon, false);
+
+        let mut res = self.normalized.clone();
+        for (i, value) in res.data.iter_mut().enumerate() {
+            *value *= self.weight.data[i % lane];
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let lane = self.weight.data.len();
+        let mut scaled_grad = grad_output.clone();
+        self.weight_gradient = Tensor::new(self.weight.shape.clone());
+
+        for (i, g) in scaled_grad.data.iter_mut().enumerate() {
+            self.weight_gradient.data[i % lane] += *g * self.normalized.data[i];
+            *g *= self.weight.data[i % lane];
+        }
+
+        normalize_lanes_backward(
+            &scaled_grad,
+            &self.normalized,
+            &self.inverse_rms,
+            lane,
+            false,
+        )
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.weight]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.weight_gradient]
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let dims = self.weight.shape.len();
+        if input_shape.len() < dims
+            || input_shape[input_shape.len() - dims..] != self.weight.shape[..]
+        {
+            let expected = format!("[*, {:?}]", self.weight.shape);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        5 * input_shape.iter().product::<usize>()
+    }
+
+    fn name(&self) -> &str {
+        "RMSNorm"
+    }
+}
+
+//--------------------------------------------------------------Group Normalization---------------------------------------------------------------------
+
+//Splits the channels of [N, C, *] into groups and normalizes each group of each sample
+//over its channels and spatial positions, with a learnable scale and shift per channel
+pub struct GroupNorm {
+    pub weight: Tensor,
+    pub bias: Tensor,
+    groups: usize,
+    epsilon: f64,
+    normalized: Tensor,
+    inverse_std: Vec<f64>,
+    weight_gradient: Tensor,
+    bias_gradient: Tensor,
+}
+
+impl GroupNorm {
+    pub fn new(groups: usize, channels: usize) -> GroupNorm {
+        assert!(
+            groups > 0 && channels.is_multiple_of(groups),
+            "{} channels can not be split into {} groups",
+            channels,
+            groups
+        );
+        GroupNorm {
+            weight: Tensor::from(vec![channels], vec![1.0; channels]),
+            bias: Tensor::new(vec![channels]),
+            groups,
+            epsilon: 1e-5,
+            normalized: Tensor::new(Vec::new()),
+            inverse_std: Vec::new(),
+            weight_gradient: Tensor::new(vec![channels]),
+            bias_gradient: Tensor::new(vec![channels]),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> GroupNorm {
+        self.epsilon = epsilon;
+        self
+    }
+
+    //Elements per channel of a single sample
+    fn spatial(&self, shape: &[usize]) -> usize {
+        shape[2..].iter().product()
+    }
+}
+
+impl Layer for GroupNorm {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let channels = self.weight.data.len();
+        assert!(
+            input.shape.len() >= 2 && input.shape[1] == channels,
+            "{} over {} channels got input {:?}",
+            self.name(),
+            channels,
+            input.shape
+        );
+        let spatial = self.spatial(&input.shape);
+        let lane = channels / self.groups * spatial;
+        (self.normalized, self.inverse_std) = normalize_lanes(input, lane, self.epsilon, true);
+
+        let mut res = self.normalized.clone();
+        for (i, value) in res.data.iter_mut().enumerate() {
+            let c = (i / spatial) % channels;
+            *value = *value * self.weight.data[c] + self.bias.data[c];
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let channels = self.weight.data.len();
+        let spatial = self.spatial(&grad_output.shape);
+        let lane = channels / self.groups * spatial;
+        let mut scaled_grad = grad_output.clone();
+        self.weight_gradient = Tensor::new(vec![channels]);
+        self.bias_gradient = Tensor::new(vec![channels]);
+
+        for (i, g) in scaled_grad.data.iter_mut().enumerate() {
+            let c = (i / spatial) % channels;
+            self.weight_gradient.data[c] += *g * self.normalized.data[i];
+            self.bias_gradient.data[c] += *g;
+            *g *= self.weight.data[c];
+        }
+
+        normalize_lanes_backward(
+            &scaled_grad,
+            &self.normalized,
+            &self.inverse_std,
+            lane,
+            true,
+        )
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.weight, &mut self.bias]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.weight_gradient, &self.bias_gradient]
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let channels = self.weight.data.len();
+        if input_shape.len() < 2 || input_shape[0] != channels {
+            let expected = format!("[{}, *spatial]", channels);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        5 * input_shape.iter().product::<usize>()
+    }
+
+    fn name(&self) -> &str {
+        "GroupNorm"
+    }
+}
+
+//--------------------------------------------------------------Instance Normalization---------------------------------------------------------------------
+
+//GroupNorm with one group per channel: every channel of every sample is normalized over
+//its own spatial positions, as used in style transfer
+pub struct InstanceNorm {
+    pub norm: GroupNorm,
+}
+
+impl InstanceNorm {
+    pub fn new(channels: usize) -> InstanceNorm {
+        InstanceNorm {
+            norm: GroupNorm::new(channels, channels),
+        }
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f64) -> InstanceNorm {
+        self.norm = self.norm.with_epsilon(epsilon);
+        self
+    }
+}
+
+impl Layer for InstanceNorm {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.norm.forward(input)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        self.norm.backward(grad_output)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        self.norm.parameters()
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.norm.gradients()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        self.norm
+            .output_shape(input_shape)
+            .map_err(|error| ShapeError::new(self.name(), error.expected, input_shape))
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        5 * input_shape.iter().product::<usize>()
+    }
+
+    fn name(&self) -> &str {
+        "InstanceNorm"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::gradient_check;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(layer: &mut dyn Layer, input_shape: Vec<usize>) {
+        let error = gradient_check(layer, &Tensor::random(input_shape), 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            layer.name(),
+            error
+        );
+    }
+
+    //Moves the scale and shift off 1 and 0 so their gradients are exercised
+    fn randomize(parameters: Vec<&mut Tensor>) {
+        for parameter in parameters {
+            *parameter = Tensor::random(parameter.shape.clone());
+        }
+    }
+
+    #[test]
+    fn batch_norm1d_gradients() {
+        let mut norm = BatchNorm1d::new(3);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![3, 5]);
+
+        norm.set_training(false);
+        assert_gradients(&mut norm, vec![3, 5]);
+    }
+
+    #[test]
+    fn batch_norm2d_gradients() {
+        let mut norm = BatchNorm2d::new(2);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![3, 2, 3, 4]);
+
+        norm.set_training(false);
+        assert_gradients(&mut norm, vec![3, 2, 3, 4]);
+    }
+
+    #[test]
+    fn running_statistics_follow_momentum() {
+        let mut norm = BatchNorm1d::new(2).with_momentum(0.3);
+        let input = Tensor::from(vec![2, 4], vec![1.0, 2.0, 3.0, 6.0, -1.0, -1.0, 1.0, 1.0]);
+        norm.forward(&input);
+
+        //Batch means 3 and 0, unbiased variances 14 / 3 and 4 / 3
+        let expected_mean = [0.3 * 3.0, 0.0];
+        let expected_var = [0.7 + 0.3 * 14.0 / 3.0, 0.7 + 0.3 * 4.0 / 3.0];
+        for c in 0..2 {
+            assert!((norm.running_mean.data[c] - expected_mean[c]).abs() < 1e-12);
+            assert!((norm.running_var.data[c] - expected_var[c]).abs() < 1e-12);
+        }
+
+        norm.forward(&input);
+        let expected_mean = 0.7 * 0.9 + 0.3 * 3.0;
+        assert!((norm.running_mean.data[0] - expected_mean).abs() < 1e-12);
+    }
+
+    #[test]
+    fn eval_mode_uses_running_statistics() {
+        let mut norm = BatchNorm2d::new(2);
+        norm.running_mean = Tensor::from(vec![2], vec![1.0, -2.0]);
+        norm.running_var = Tensor::from(vec![2], vec![4.0, 0.25]);
+        norm.gamma = Tensor::from(vec![2], vec![2.0, 1.0]);
+        norm.beta = Tensor::from(vec![2], vec![0.5, 0.0]);
+        norm.set_training(false);
+
+        let input = Tensor::random(vec![2, 2, 2, 3]);
+        let output = norm.forward(&input);
+        for (i, (&x, &y)) in input.data.iter().zip(&output.data).enumerate() {
+            let c = (i / 6) % 2;
+            let expected = norm.gamma.data[c] * (x - norm.running_mean.data[c])
+                / (norm.running_var.data[c] + 1e-5).sqrt()
+                + norm.beta.data[c];
+            assert!((y - expected).abs() < 1e-12);
+        }
+
+        //Evaluating leaves the running statistics alone
+        assert_eq!(norm.running_mean.data, vec![1.0, -2.0]);
+        assert_eq!(norm.running_var.data, vec![4.0, 0.25]);
+    }
+
+    #[test]
+    fn training_mode_uses_batch_statistics() {
+        let mut norm = BatchNorm1d::new(1);
+        norm.running_mean = Tensor::from(vec![1], vec![100.0]);
+        let output = norm.forward(&Tensor::from(vec![1, 4], vec![1.0, 2.0, 3.0, 4.0]));
+        let mean: f64 = output.data.iter().sum::<f64>() / 4.0;
+        let var: f64 = output.data.iter().map(|y| y * y).sum::<f64>() / 4.0;
+        assert!(mean.abs() < 1e-12);
+        assert!((var - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn layer_norm_gradients() {
+        let mut norm = LayerNorm::new(vec![4]);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![2, 3, 4]);
+
+        let mut norm = LayerNorm::new(vec![2, 3]);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![2, 2, 3]);
+    }
+
+    #[test]
+    fn rms_norm_gradients() {
+        let mut norm = RMSNorm::new(vec![5]);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![3, 5]);
+    }
+
+    #[test]
+    fn group_norm_gradients() {
+        let mut norm = GroupNorm::new(2, 4);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![2, 4, 3, 2]);
+    }
+
+    #[test]
+    fn instance_norm_gradients() {
+        let mut norm = InstanceNorm::new(3);
+        randomize(norm.parameters());
+        assert_gradients(&mut norm, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn group_norm_with_a_group_per_channel_is_instance_norm() {
+        let input = Tensor::random(vec![2, 3, 2, 4]);
+        let grouped = GroupNorm::new(3, 3).forward(&input);
+        let instance = InstanceNorm::new(3).forward(&input);
+        assert_eq!(grouped.data, instance.data);
+
+        //Every channel of every sample on its own
+        for (lane, values) in input.data.chunks(8).enumerate() {
+            let mean = values.iter().sum::<f64>() / 8.0;
+            let var = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 8.0;
+            for (i, x) in values.iter().enumerate() {
+                let expected = (x - mean) / (var + 1e-5).sqrt();
+                assert!((instance.data[lane * 8 + i] - expected).abs() < 1e-12);
+            }
+        }
+    }
+}
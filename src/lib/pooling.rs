@@ -1,4 +1,7 @@
-use super::tensor::Tensor;
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::{unravel, Tensor},
+};
 
 #[derive(Clone, Debug)]
 pub enum PoolingType {
@@ -6,34 +9,611 @@ pub enum PoolingType {
     Average,
 }
 
+//N-d pooling over the trailing window_size.len() axes of a tensor, every leading axis
+//(batch, channels, ...) is pooled independently. A [N, C, H, W] input with a 2 long
+//window_size pools over H and W.
 pub struct Pooling {
     pooling_type: PoolingType,
-    window_size: Tensor,
+    window_size: Vec<usize>,
     stride: usize,
+    padding: usize,
+    input_shape: Vec<usize>,
+    //Flat input index that won each max window, used to route gradients back
+    argmax: Vec<usize>,
 }
 
 impl Pooling {
-    pub fn new(pooling_type: PoolingType, window_size: Tensor, stride: usize) -> Self {
+    pub fn new(pooling_type: PoolingType, window_size: Vec<usize>, stride: usize) -> Self {
+        assert!(
+            !window_size.is_empty(),
+            "Pooling needs at least one window axis"
+        );
+        assert!(
+            window_size.iter().all(|&w| w > 0),
+            "Window sizes must be positive, got {:?}",
+            window_size
+        );
+        assert!(stride > 0, "Stride must be positive");
         Pooling {
             pooling_type,
             window_size,
             stride,
+            padding: 0,
+            input_shape: Vec::new(),
+            argmax: Vec::new(),
         }
     }
 
-    pub fn apply(self, input: Tensor) -> Tensor {
-        let mut output_shape = Vec::new();
-        let num_dims = &input.shape.len();
-        let window_shape = &self.window_size.shape;
+    //Implicit padding on both sides of every pooled axis. Padded positions never win a max
+    //and count as zeros for an average.
+    pub fn with_padding(mut self, padding: usize) -> Self {
+        assert!(
+            self.window_size.iter().all(|&w| 2 * padding <= w),
+            "Padding {} must be at most half of the window {:?}",
+            padding,
+            self.window_size
+        );
+        self.padding = padding;
+        self
+    }
+
+    pub fn apply(&mut self, input: Tensor) -> Tensor {
+        let dims = self.window_size.len();
+        assert!(
+            input.shape.len() >= dims,
+            "Cannot pool {:?} with a {}-d window",
+            input.shape,
+            dims
+        );
+
+        let lead = input.shape.len() - dims;
+        let in_spatial = input.shape[lead..].to_vec();
+        let output_shape = self.pooled_shape(&input.shape);
+        let out_spatial = output_shape[lead..].to_vec();
+        let in_size: usize = in_spatial.iter().product();
+        let out_size: usize = out_spatial.iter().product();
+        let window_count: usize = self.window_size.iter().product();
+        let lanes = input.data.len() / in_size;
+
+        let mut res = Tensor::new(output_shape);
+        self.argmax = vec![0; res.data.len()];
+        self.input_shape = input.shape.clone();
+
+        for lane in 0..lanes {
+            for o in 0..out_size {
+                let out_index = unravel(o, &out_spatial);
+                //(value, flat input index) of the max so far
+                let mut best: Option<(f64, usize)> = None;
+                let mut sum = 0.0;
+
+                for w in 0..window_count {
+                    if let Some(i) = self.input_position(&out_index, w, &in_spatial) {
+                        let value = input.data[lane * in_size + i];
+                        sum += value;
+                        //NaN wins like in PyTorch, so it shows up in the output instead of
+                        //being skipped
+                        let better = match best {
+                            None => true,
+                            Some((b, _)) => value > b || value.is_nan() && !b.is_nan(),
+                        };
+                        if better {
+                            best = Some((value, lane * in_size + i));
+                        }
+                    }
+                }
+
+                //Padding is at most half a window, so every window holds an input element
+                let (best, best_index) = best.expect("Pooling window lies entirely in the padding");
+                res.data[lane * out_size + o] = match self.pooling_type {
+                    PoolingType::Max => best,
+                    PoolingType::Average => sum / window_count as f64,
+                };
+                self.argmax[lane * out_size + o] = best_index;
+            }
+        }
+
+        res
+    }
+
+    //Flat offset inside one input lane of the w-th window element for an output position,
+    //None if that element lies in the padding
+    fn input_position(&self, out_index: &[usize], w: usize, in_spatial: &[usize]) -> Option<usize> {
+        let window_index = unravel(w, &self.window_size);
+        let mut flat = 0;
+        for d in 0..in_spatial.len() {
+            let i = (out_index[d] * self.stride + window_index[d]) as isize - self.padding as isize;
+            if i < 0 || i >= in_spatial[d] as isize {
+                return None;
+            }
+            flat = flat * in_spatial[d] + i as usize;
+        }
+        Some(flat)
+    }
+
+    fn pooled_shape(&self, shape: &[usize]) -> Vec<usize> {
+        let lead = shape.len() - self.window_size.len();
+        let mut output_shape = shape[..lead].to_vec();
 
-        for (i, &dim) in input.clone().shape.iter().enumerate() {
+        for (i, &dim) in shape[lead..].iter().enumerate() {
             //calculates the remaining dimensions after pooling
-            let output_dim = (dim - window_shape[i] + self.stride) / self.stride;
-            output_shape.push(output_dim);
+            let padded = dim + 2 * self.padding;
+            assert!(
+                padded >= self.window_size[i],
+                "Window {:?} does not fit in input {:?}",
+                self.window_size,
+                shape
+            );
+            output_shape.push((padded - self.window_size[i]) / self.stride + 1);
+        }
+
+        output_shape
+    }
+}
+
+impl Layer for Pooling {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.apply(input.clone())
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let mut grad_input = Tensor::new(self.input_shape.clone());
+
+        match self.pooling_type {
+            PoolingType::Max => {
+                for (o, &g) in grad_output.data.iter().enumerate() {
+                    grad_input.data[self.argmax[o]] += g;
+                }
+            }
+            PoolingType::Average => {
+                let dims = self.window_size.len();
+                let lead = self.input_shape.len() - dims;
+                let in_spatial = self.input_shape[lead..].to_vec();
+                let out_spatial = grad_output.shape[lead..].to_vec();
+                let in_size: usize = in_spatial.iter().product();
+                let out_size: usize = out_spatial.iter().product();
+                let window_count: usize = self.window_size.iter().product();
+
+                for (o, &g) in grad_output.data.iter().enumerate() {
+                    let lane = o / out_size;
+                    let out_index = unravel(o % out_size, &out_spatial);
+                    for w in 0..window_count {
+                        if let Some(i) = self.input_position(&out_index, w, &in_spatial) {
+                            grad_input.data[lane * in_size + i] += g / window_count as f64;
+                        }
+                    }
+                }
+            }
+        }
+
+        grad_input
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let dims = self.window_size.len();
+        let fits = input_shape.len() > dims
+            && input_shape[input_shape.len() - dims..]
+                .iter()
+                .zip(&self.window_size)
+                .all(|(&size, &window)| size + 2 * self.padding >= window);
+        if !fits {
+            let spatial: Vec<String> = self
+                .window_size
+                .iter()
+                .map(|&window| format!(">= {}", window.saturating_sub(2 * self.padding).max(1)))
+                .collect();
+            let expected = format!("[C, {}]", spatial.join(", "));
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(self.pooled_shape(input_shape))
+    }
+
+    //One comparison or addition per window element per output
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let outputs: usize = self.pooled_shape(input_shape).iter().product();
+        outputs * self.window_size.iter().product::<usize>()
+    }
+
+    fn name(&self) -> &str {
+        match self.pooling_type {
+            PoolingType::Max => "MaxPooling",
+            PoolingType::Average => "AveragePooling",
+        }
+    }
+}
+
+//--------------------------------------------------------------Global Pooling---------------------------------------------------------------------
+
+//Reduces every axis after [N, C] to a single value, giving [N, C] for any input spatial size.
+//Follow with a Flatten to feed the channels into a TensorLayer.
+pub struct GlobalPooling {
+    pooling_type: PoolingType,
+    input_shape: Vec<usize>,
+    argmax: Vec<usize>,
+}
+
+impl GlobalPooling {
+    pub fn new(pooling_type: PoolingType) -> Self {
+        GlobalPooling {
+            pooling_type,
+            input_shape: Vec::new(),
+            argmax: Vec::new(),
+        }
+    }
+}
+
+impl Layer for GlobalPooling {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            input.shape.len() >= 3,
+            "Global pooling expects [N, C, ...], got {:?}",
+            input.shape
+        );
+        let (n, c) = (input.shape[0], input.shape[1]);
+        let size = input.data.len() / (n * c);
+
+        self.input_shape = input.shape.clone();
+        self.argmax = vec![0; n * c];
+        let mut res = Tensor::new(vec![n, c]);
+
+        for (lane, values) in input.data.chunks(size).enumerate() {
+            res.data[lane] = match self.pooling_type {
+                PoolingType::Max => {
+                    let mut best = 0;
+                    for (i, &value) in values.iter().enumerate() {
+                        if value > values[best] {
+                            best = i;
+                        }
+                    }
+                    self.argmax[lane] = lane * size + best;
+                    values[best]
+                }
+                PoolingType::Average => values.iter().sum::<f64>() / size as f64,
+            };
         }
-        let mut res = Tensor::new(output_shape);
-        let window_size = window_shape[0];
 
         res
     }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let mut grad_input = Tensor::new(self.input_shape.clone());
+        let size = grad_input.data.len() / grad_output.data.len();
+
+        for (lane, &g) in grad_output.data.iter().enumerate() {
+            match self.pooling_type {
+                PoolingType::Max => grad_input.data[self.argmax[lane]] = g,
+                PoolingType::Average => {
+                    for value in &mut grad_input.data[lane * size..(lane + 1) * size] {
+                        *value = g / size as f64;
+                    }
+                }
+            }
+        }
+
+        grad_input
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.len() < 2 {
+            return Err(ShapeError::new(self.name(), "[C, *spatial]", input_shape));
+        }
+        Ok(vec![input_shape[0]])
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn name(&self) -> &str {
+        match self.pooling_type {
+            PoolingType::Max => "GlobalMaxPool",
+            PoolingType::Average => "GlobalAveragePool",
+        }
+    }
+}
+
+//--------------------------------------------------------------Adaptive Pooling---------------------------------------------------------------------
+
+//Pools the trailing output_size.len() axes down to exactly output_size, whatever the input size.
+//Output cell i along an axis of length L covers [floor(i * L / out), ceil((i + 1) * L / out)),
+//so windows may overlap or differ in size by one. AdaptiveAvgPool2d is
+//AdaptivePooling::new(PoolingType::Average, vec![h, w]).
+pub struct AdaptivePooling {
+    pooling_type: PoolingType,
+    output_size: Vec<usize>,
+    input_shape: Vec<usize>,
+    argmax: Vec<usize>,
+}
+
+impl AdaptivePooling {
+    pub fn new(pooling_type: PoolingType, output_size: Vec<usize>) -> Self {
+        assert!(
+            !output_size.is_empty() && output_size.iter().all(|&o| o > 0),
+            "Adaptive pooling needs a non-empty, positive output size, got {:?}",
+            output_size
+        );
+        AdaptivePooling {
+            pooling_type,
+            output_size,
+            input_shape: Vec::new(),
+            argmax: Vec::new(),
+        }
+    }
+
+    //Input range [start, end) of every output cell along each pooled axis
+    fn windows(&self, in_spatial: &[usize]) -> Vec<Vec<(usize, usize)>> {
+        in_spatial
+            .iter()
+            .zip(self.output_size.iter())
+            .map(|(&size, &out)| {
+                (0..out)
+                    .map(|i| (i * size / out, ((i + 1) * size).div_ceil(out)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    //Flat offsets inside one input lane covered by an output cell
+    fn window_positions(
+        windows: &[Vec<(usize, usize)>],
+        out_index: &[usize],
+        in_spatial: &[usize],
+    ) -> Vec<usize> {
+        let mut positions = vec![0];
+        for d in 0..in_spatial.len() {
+            let (start, end) = windows[d][out_index[d]];
+            positions = positions
+                .iter()
+                .flat_map(|&p| (start..end).map(move |i| p * in_spatial[d] + i))
+                .collect();
+        }
+        positions
+    }
+
+    fn split_shape(&self, shape: &[usize]) -> (Vec<usize>, Vec<usize>) {
+        let dims = self.output_size.len();
+        assert!(
+            shape.len() >= dims
+                && shape[shape.len() - dims..]
+                    .iter()
+                    .zip(self.output_size.iter())
+                    .all(|(&i, &o)| i >= o),
+            "Cannot adaptively pool {:?} down to {:?}",
+            shape,
+            self.output_size
+        );
+        let lead = shape.len() - dims;
+        (shape[..lead].to_vec(), shape[lead..].to_vec())
+    }
+}
+
+impl Layer for AdaptivePooling {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let (lead, in_spatial) = self.split_shape(&input.shape);
+        let windows = self.windows(&in_spatial);
+        let in_size: usize = in_spatial.iter().product();
+        let out_size: usize = self.output_size.iter().product();
+        let lanes = input.data.len() / in_size;
+
+        let mut res = Tensor::new([lead, self.output_size.clone()].concat());
+        self.argmax = vec![0; res.data.len()];
+        self.input_shape = input.shape.clone();
+
+        for o in 0..out_size {
+            let out_index = unravel(o, &self.output_size);
+            let positions = AdaptivePooling::window_positions(&windows, &out_index, &in_spatial);
+            for lane in 0..lanes {
+                let values = &input.data[lane * in_size..(lane + 1) * in_size];
+                res.data[lane * out_size + o] = match self.pooling_type {
+                    PoolingType::Max => {
+                        let mut best = positions[0];
+                        for &p in &positions {
+                            if values[p] > values[best] {
+                                best = p;
+                            }
+                        }
+                        self.argmax[lane * out_size + o] = lane * in_size + best;
+                        values[best]
+                    }
+                    PoolingType::Average => {
+                        positions.iter().map(|&p| values[p]).sum::<f64>() / positions.len() as f64
+                    }
+                };
+            }
+        }
+
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let mut grad_input = Tensor::new(self.input_shape.clone());
+
+        match self.pooling_type {
+            PoolingType::Max => {
+                for (o, &g) in grad_output.data.iter().enumerate() {
+                    grad_input.data[self.argmax[o]] += g;
+                }
+            }
+            PoolingType::Average => {
+                let (_, in_spatial) = self.split_shape(&self.input_shape);
+                let windows = self.windows(&in_spatial);
+                let in_size: usize = in_spatial.iter().product();
+                let out_size: usize = self.output_size.iter().product();
+                let lanes = grad_output.data.len() / out_size;
+
+                for o in 0..out_size {
+                    let out_index = unravel(o, &self.output_size);
+                    let positions =
+                        AdaptivePooling::window_positions(&windows, &out_index, &in_spatial);
+                    let share = 1.0 / positions.len() as f64;
+                    for lane in 0..lanes {
+                        let g = grad_output.data[lane * out_size + o] * share;
+                        for &p in &positions {
+                            grad_input.data[lane * in_size + p] += g;
+                        }
+                    }
+                }
+            }
+        }
+
+        grad_input
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let dims = self.output_size.len();
+        let fits = input_shape.len() > dims
+            && input_shape[input_shape.len() - dims..]
+                .iter()
+                .zip(&self.output_size)
+                .all(|(&size, &out)| size >= out);
+        if !fits {
+            let spatial: Vec<String> = self
+                .output_size
+                .iter()
+                .map(|out| format!(">= {}", out))
+                .collect();
+            let expected = format!("[C, {}]", spatial.join(", "));
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        let (lead, _) = self.split_shape(input_shape);
+        Ok([lead, self.output_size.clone()].concat())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn name(&self) -> &str {
+        match (&self.pooling_type, self.output_size.len()) {
+            (PoolingType::Average, 2) => "AdaptiveAvgPool2d",
+            (PoolingType::Max, 2) => "AdaptiveMaxPool2d",
+            (PoolingType::Average, _) => "AdaptiveAvgPool",
+            (PoolingType::Max, _) => "AdaptiveMaxPool",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::gradient_check;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(layer: &mut dyn Layer, input_shape: Vec<usize>) {
+        let input = Tensor::random(input_shape);
+        let error = gradient_check(layer, &input, 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            layer.name(),
+            error
+        );
+    }
+
+    #[test]
+    fn max_pooling_gradients() {
+        assert_gradients(
+            &mut Pooling::new(PoolingType::Max, vec![2, 2], 2),
+            vec![2, 3, 4, 4],
+        );
+        assert_gradients(
+            &mut Pooling::new(PoolingType::Max, vec![3, 3], 2).with_padding(1),
+            vec![2, 2, 5, 6],
+        );
+        assert_gradients(
+            &mut Pooling::new(PoolingType::Max, vec![3], 1).with_padding(1),
+            vec![2, 3, 7],
+        );
+    }
+
+    #[test]
+    fn average_pooling_gradients() {
+        assert_gradients(
+            &mut Pooling::new(PoolingType::Average, vec![2, 2], 2),
+            vec![2, 3, 4, 4],
+        );
+        assert_gradients(
+            &mut Pooling::new(PoolingType::Average, vec![3, 3], 2).with_padding(1),
+            vec![2, 2, 5, 6],
+        );
+    }
+
+    #[test]
+    fn padding_never_wins_a_max() {
+        let mut pooling = Pooling::new(PoolingType::Max, vec![2], 2).with_padding(1);
+        let input = Tensor::from(vec![1, 1, 4], vec![-1.0, -2.0, -3.0, -4.0]);
+        let output = pooling.forward(&input);
+        assert_eq!(output.data, vec![-1.0, -2.0, -4.0]);
+    }
+
+    #[test]
+    fn nan_windows_propagate_and_keep_their_gradient() {
+        let mut pooling = Pooling::new(PoolingType::Max, vec![2], 2);
+        let input = Tensor::from(vec![1, 1, 4], vec![1.0, 2.0, f64::NAN, f64::NAN]);
+        let output = pooling.forward(&input);
+        assert_eq!(output.data[0], 2.0);
+        assert!(output.data[1].is_nan());
+
+        let grad = pooling.backward(&Tensor::from(vec![1, 1, 2], vec![10.0, 1.0]));
+        assert_eq!(grad.data, vec![0.0, 10.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn global_pooling_gradients() {
+        assert_gradients(&mut GlobalPooling::new(PoolingType::Max), vec![2, 3, 4, 5]);
+        assert_gradients(
+            &mut GlobalPooling::new(PoolingType::Average),
+            vec![2, 3, 4, 5],
+        );
+        assert_gradients(&mut GlobalPooling::new(PoolingType::Average), vec![2, 3, 6]);
+    }
+
+    #[test]
+    fn adaptive_pooling_gradients() {
+        for pooling_type in [PoolingType::Max, PoolingType::Average] {
+            assert_gradients(
+                &mut AdaptivePooling::new(pooling_type.clone(), vec![3, 2]),
+                vec![2, 2, 5, 7],
+            );
+            assert_gradients(&mut AdaptivePooling::new(pooling_type, vec![3]), vec![2, 5]);
+        }
+    }
+
+    //PyTorch bins: cell i covers [floor(i * in / out), ceil((i + 1) * in / out))
+    fn torch_bins(size: usize, out: usize) -> Vec<(usize, usize)> {
+        (0..out)
+            .map(|i| {
+                let start = (i * size) as f64 / out as f64;
+                let end = ((i + 1) * size) as f64 / out as f64;
+                (start.floor() as usize, end.ceil() as usize)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adaptive_bins_match_pytorch_for_non_divisible_sizes() {
+        assert_eq!(torch_bins(5, 3), vec![(0, 2), (1, 4), (3, 5)]);
+
+        let input = Tensor::from(vec![1, 5], vec![0.0, 1.0, 2.0, 3.0, 4.0]);
+        let mut average = AdaptivePooling::new(PoolingType::Average, vec![3]);
+        assert_eq!(average.forward(&input).data, vec![0.5, 2.0, 3.5]);
+        let mut max = AdaptivePooling::new(PoolingType::Max, vec![3]);
+        assert_eq!(max.forward(&input).data, vec![1.0, 3.0, 4.0]);
+
+        let (h, w, oh, ow) = (5, 7, 3, 4);
+        let input = Tensor::random(vec![1, 1, h, w]);
+        let output = AdaptivePooling::new(PoolingType::Average, vec![oh, ow]).forward(&input);
+        for (i, &(y0, y1)) in torch_bins(h, oh).iter().enumerate() {
+            for (j, &(x0, x1)) in torch_bins(w, ow).iter().enumerate() {
+                let mut sum = 0.0;
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        sum += input.data[y * w + x];
+                    }
+                }
+                let expected = sum / ((y1 - y0) * (x1 - x0)) as f64;
+                assert!((output.data[i * ow + j] - expected).abs() < 1e-12);
+            }
+        }
+    }
 }
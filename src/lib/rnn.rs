@@ -0,0 +1,930 @@
+use super::{
+    activations::sigmoid,
+    layer::{Layer, ShapeError},
+    tensor::{accumulate_a_b, accumulate_a_bt, accumulate_at_b, broadcast_rows, Tensor},
+};
+use std::marker::PhantomData;
+
+//Recurrent layers over time-major [T, N, F] sequences. Every stacked layer and direction
+//has its own weights; a bidirectional layer concatenates both directions on the feature
+//axis, so stacked layers above the first see 2 * hidden_size features.
+//The output is [T, N, hidden_size * directions], or only the last step [N, hidden_size * directions]
+//when return_sequences is turned off (the backward direction's last step is t = 0).
+pub struct Recurrent<C: Cell> {
+    pub weights: Vec<CellWeights>,
+    input_size: usize,
+    hidden_size: usize,
+    num_layers: usize,
+    bidirectional: bool,
+    return_sequences: bool,
+    truncation: Option<usize>,
+    initial_state: Option<Tensor>,
+    initial_cell_state: Option<Tensor>,
+    final_state: Tensor,
+    final_cell_state: Tensor,
+    gradients: Vec<CellWeights>,
+    //Inputs of every stacked layer, [T, N, features]
+    layer_inputs: Vec<Vec<f64>>,
+    runs: Vec<DirectionRun>,
+    input_shape: Vec<usize>,
+    cell: PhantomData<C>,
+}
+
+//Elman network, h = tanh(W_ih x + b_ih + W_hh h + b_hh)
+#[allow(clippy::upper_case_acronyms)]
+pub type RNN = Recurrent<RnnCell>;
+//Long short-term memory with input, forget, cell and output gates
+#[allow(clippy::upper_case_acronyms)]
+pub type LSTM = Recurrent<LstmCell>;
+//Gated recurrent unit with reset, update and new gates
+#[allow(clippy::upper_case_acronyms)]
+pub type GRU = Recurrent<GruCell>;
+
+//Weights of one layer in one direction, the gates are stacked along the rows
+pub struct CellWeights {
+    //[gates * hidden_size, input features]
+    pub w_ih: Tensor,
+    //[gates * hidden_size, hidden_size]
+    pub w_hh: Tensor,
+    //[gates * hidden_size]
+    pub b_ih: Tensor,
+    //[gates * hidden_size]
+    pub b_hh: Tensor,
+}
+
+//Everything one direction of one layer remembers from forward, in processing order
+struct DirectionRun {
+    //T + 1 hidden states, the first one is the initial state
+    hs: Vec<Vec<f64>>,
+    //T + 1 cell states, only filled for cells with a cell state
+    cs: Vec<Vec<f64>>,
+    //Per step values the cell needs for its backward pass
+    caches: Vec<Vec<f64>>,
+}
+
+impl<C: Cell> Recurrent<C> {
+    //One unidirectional layer returning the whole sequence
+    pub fn new(input_size: usize, hidden_size: usize) -> Recurrent<C> {
+        let mut layer = Recurrent {
+            weights: Vec::new(),
+            input_size,
+            hidden_size,
+            num_layers: 1,
+            bidirectional: false,
+            return_sequences: true,
+            truncation: None,
+            initial_state: None,
+            initial_cell_state: None,
+            final_state: Tensor::new(Vec::new()),
+            final_cell_state: Tensor::new(Vec::new()),
+            gradients: Vec::new(),
+            layer_inputs: Vec::new(),
+            runs: Vec::new(),
+            input_shape: Vec::new(),
+            cell: PhantomData,
+        };
+        layer.init_weights();
+        layer
+    }
+
+    pub fn with_num_layers(mut self, num_layers: usize) -> Recurrent<C> {
+        assert!(num_layers > 0, "A recurrent layer needs at least one layer");
+        self.num_layers = num_layers;
+        self.init_weights();
+        self
+    }
+
+    pub fn bidirectional(mut self) -> Recurrent<C> {
+        self.bidirectional = true;
+        self.init_weights();
+        self
+    }
+
+    //Only output the last step, for sequence classifiers
+    pub fn without_sequences(mut self) -> Recurrent<C> {
+        self.return_sequences = false;
+        self
+    }
+
+    //Truncated backpropagation through time: the gradient carried between steps is cut
+    //every `steps` steps, so no error flows further back than one segment
+    pub fn with_truncation(mut self, steps: usize) -> Recurrent<C> {
+        assert!(steps > 0, "Truncation length must be positive");
+        self.truncation = Some(steps);
+        self
+    }
+
+    //Hidden state the next forward calls start from, [num_layers * directions, N, hidden_size].
+    //Without one every sequence starts from zeros.
+    pub fn set_initial_state(&mut self, h0: Option<Tensor>) {
+        self.initial_state = h0;
+    }
+
+    //Cell state the next forward calls start from, [num_layers * directions, N, hidden_size]
+    pub fn set_initial_cell_state(&mut self, c0: Option<Tensor>) {
+        assert!(C::HAS_CELL_STATE, "{} has no cell state", C::NAME);
+        self.initial_cell_state = c0;
+    }
+
+    //Hidden state after the last forward call, [num_layers * directions, N, hidden_size]
+    pub fn final_state(&self) -> &Tensor {
+        &self.final_state
+    }
+
+    //Cell state after the last forward call, [num_layers * directions, N, hidden_size]
+    pub fn final_cell_state(&self) -> &Tensor {
+        assert!(C::HAS_CELL_STATE, "{} has no cell state", C::NAME);
+        &self.final_cell_state
+    }
+
+    fn directions(&self) -> usize {
+        if self.bidirectional {
+            2
+        } else {
+            1
+        }
+    }
+
+    //Uniform in [-1 / sqrt(hidden_size), 1 / sqrt(hidden_size)]
+    fn init_weights(&mut self) {
+        let rows = C::GATES * self.hidden_size;
+        let bound = 1.0 / (self.hidden_size as f64).sqrt();
+        let random = |shape: Vec<usize>| Tensor::random(shape).multiply_scalar(bound);
+
+        self.weights.clear();
+        self.gradients.clear();
+        for layer in 0..self.num_layers {
+            let features = if layer == 0 {
+                self.input_size
+            } else {
+                self.hidden_size * self.directions()
+            };
+            for _ in 0..self.directions() {
+                self.weights.push(CellWeights {
+                    w_ih: random(vec![rows, features]),
+                    w_hh: random(vec![rows, self.hidden_size]),
+                    b_ih: random(vec![rows]),
+                    b_hh: random(vec![rows]),
+                });
+                self.gradients.push(CellWeights {
+                    w_ih: Tensor::new(vec![rows, features]),
+                    w_hh: Tensor::new(vec![rows, self.hidden_size]),
+                    b_ih: Tensor::new(vec![rows]),
+                    b_hh: Tensor::new(vec![rows]),
+                });
+            }
+        }
+    }
+
+    //Slice of an initial state tensor belonging to one layer and direction, zeros without one
+    fn initial(&self, state: &Option<Tensor>, index: usize, batch: usize) -> Vec<f64> {
+        let size = batch * self.hidden_size;
+        match state {
+            Some(state) => {
+                assert!(
+                    state.shape
+                        == vec![self.num_layers * self.directions(), batch, self.hidden_size],
+                    "Initial state must be [{}, {}, {}], got {:?}",
+                    self.num_layers * self.directions(),
+                    batch,
+                    self.hidden_size,
+                    state.shape
+                );
+                state.data[index * size..(index + 1) * size].to_vec()
+            }
+            None => vec![0.0; size],
+        }
+    }
+
+    //Runs one direction of one layer and writes its hidden states into its half of output
+    fn run_direction(
+        &self,
+        index: usize,
+        direction: usize,
+        input: &[f64],
+        features: usize,
+        output: &mut [f64],
+    ) -> DirectionRun {
+        let (steps, batch) = (self.input_shape[0], self.input_shape[1]);
+        let hidden = self.hidden_size;
+        let rows = C::GATES * hidden;
+        let out_features = hidden * self.directions();
+        let weights = &self.weights[index];
+
+        let mut run = DirectionRun {
+            hs: vec![self.initial(&self.initial_state, index, batch)],
+            cs: Vec::new(),
+            caches: Vec::new(),
+        };
+        if C::HAS_CELL_STATE {
+            run.cs
+                .push(self.initial(&self.initial_cell_state, index, batch));
+        }
+
+        for s in 0..steps {
+            let t = time_step(s, steps, direction);
+            let x = &input[t * batch * features..(t + 1) * batch * features];
+
+            let mut xw = broadcast_rows(&weights.b_ih.data, batch);
+            accumulate_a_bt(&mut xw, x, &weights.w_ih.data, batch, features, rows);
+            let mut hw = broadcast_rows(&weights.b_hh.data, batch);
+            accumulate_a_bt(&mut hw, &run.hs[s], &weights.w_hh.data, batch, hidden, rows);
+
+            let mut h = vec![0.0; batch * hidden];
+            let mut c = vec![0.0; if C::HAS_CELL_STATE { batch * hidden } else { 0 }];
+            let mut cache = vec![0.0; C::CACHE * batch * hidden];
+            let c_prev = if C::HAS_CELL_STATE {
+                &run.cs[s][..]
+            } else {
+                &[]
+            };
+            C::forward(
+                &xw, &hw, &run.hs[s], c_prev, hidden, &mut h, &mut c, &mut cache,
+            );
+
+            for n in 0..batch {
+                let start = (t * batch + n) * out_features + direction * hidden;
+                output[start..start + hidden].copy_from_slice(&h[n * hidden..(n + 1) * hidden]);
+            }
+
+            run.hs.push(h);
+            if C::HAS_CELL_STATE {
+                run.cs.push(c);
+            }
+            run.caches.push(cache);
+        }
+        run
+    }
+}
+
+//Position in the sequence of the s-th step processed by a direction
+fn time_step(s: usize, steps: usize, direction: usize) -> usize {
+    if direction == 0 {
+        s
+    } else {
+        steps - 1 - s
+    }
+}
+
+impl<C: Cell> Layer for Recurrent<C> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        assert!(
+            input.shape.len() == 3 && input.shape[2] == self.input_size,
+            "{} expects [T, N, {}], got {:?}",
+            C::NAME,
+            self.input_size,
+            input.shape
+        );
+        let (steps, batch) = (input.shape[0], input.shape[1]);
+        let hidden = self.hidden_size;
+        let out_features = hidden * self.directions();
+        self.input_shape = input.shape.clone();
+
+        let mut layer_input = input.data.clone();
+        let mut features = self.input_size;
+        let mut runs = Vec::new();
+        let mut layer_inputs = Vec::new();
+
+        for layer in 0..self.num_layers {
+            let mut output = vec![0.0; steps * batch * out_features];
+            for direction in 0..self.directions() {
+                let index = layer * self.directions() + direction;
+                runs.push(self.run_direction(
+                    index,
+                    direction,
+                    &layer_input,
+                    features,
+                    &mut output,
+                ));
+            }
+            layer_inputs.push(layer_input);
+            layer_input = output;
+            features = out_features;
+        }
+
+        let states = self.num_layers * self.directions();
+        self.final_state = Tensor::new(vec![states, batch, hidden]);
+        self.final_cell_state = Tensor::new(vec![states, batch, hidden]);
+        for (index, run) in runs.iter().enumerate() {
+            let size = batch * hidden;
+            self.final_state.data[index * size..(index + 1) * size].copy_from_slice(&run.hs[steps]);
+            if C::HAS_CELL_STATE {
+                self.final_cell_state.data[index * size..(index + 1) * size]
+                    .copy_from_slice(&run.cs[steps]);
+            }
+        }
+        self.runs = runs;
+        self.layer_inputs = layer_inputs;
+
+        if self.return_sequences {
+            return Tensor::from(vec![steps, batch, out_features], layer_input);
+        }
+
+        //The last step of each direction is its final hidden state in the top layer
+        let mut res = Tensor::new(vec![batch, out_features]);
+        let top = (self.num_layers - 1) * self.directions();
+        for direction in 0..self.directions() {
+            let h = &self.runs[top + direction].hs[steps];
+            for n in 0..batch {
+                let start = n * out_features + direction * hidden;
+                res.data[start..start + hidden].copy_from_slice(&h[n * hidden..(n + 1) * hidden]);
+            }
+        }
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let (steps, batch) = (self.input_shape[0], self.input_shape[1]);
+        let hidden = self.hidden_size;
+        let rows = C::GATES * hidden;
+        let out_features = hidden * self.directions();
+
+        //Gradient w.r.t. the output sequence of the current layer
+        let mut grad_sequence = if self.return_sequences {
+            grad_output.data.clone()
+        } else {
+            let mut grad = vec![0.0; steps * batch * out_features];
+            for direction in 0..self.directions() {
+                let t = time_step(steps - 1, steps, direction);
+                for n in 0..batch {
+                    let start = n * out_features + direction * hidden;
+                    let target = (t * batch + n) * out_features + direction * hidden;
+                    grad[target..target + hidden]
+                        .copy_from_slice(&grad_output.data[start..start + hidden]);
+                }
+            }
+            grad
+        };
+
+        for layer in (0..self.num_layers).rev() {
+            let input = &self.layer_inputs[layer];
+            let features = input.len() / (steps * batch);
+            let mut grad_input = vec![0.0; input.len()];
+
+            for direction in 0..self.directions() {
+                let index = layer * self.directions() + direction;
+                let (weights, run) = (&self.weights[index], &self.runs[index]);
+                let gradients = &mut self.gradients[index];
+                for g in [
+                    &mut gradients.w_ih,
+                    &mut gradients.w_hh,
+                    &mut gradients.b_ih,
+                    &mut gradients.b_hh,
+                ] {
+                    g.data.fill(0.0);
+                }
+
+                let mut dh_carry = vec![0.0; batch * hidden];
+                let mut dc_carry = vec![0.0; batch * hidden];
+
+                for s in (0..steps).rev() {
+                    let t = time_step(s, steps, direction);
+                    let x = &input[t * batch * features..(t + 1) * batch * features];
+
+                    let mut dh = dh_carry;
+                    for n in 0..batch {
+                        let start = (t * batch + n) * out_features + direction * hidden;
+                        for (d, g) in dh[n * hidden..(n + 1) * hidden]
+                            .iter_mut()
+                            .zip(&grad_sequence[start..start + hidden])
+                        {
+                            *d += g;
+                        }
+                    }
+
+                    let mut d_xw = vec![0.0; batch * rows];
+                    let mut d_hw = vec![0.0; batch * rows];
+                    let mut dh_prev = vec![0.0; batch * hidden];
+                    let mut dc_prev = vec![0.0; batch * hidden];
+                    let (c, c_prev): (&[f64], &[f64]) = if C::HAS_CELL_STATE {
+                        (&run.cs[s + 1], &run.cs[s])
+                    } else {
+                        (&[], &[])
+                    };
+                    C::backward(
+                        &dh,
+                        &dc_carry,
+                        &run.hs[s + 1],
+                        c,
+                        &run.hs[s],
+                        c_prev,
+                        &run.caches[s],
+                        hidden,
+                        &mut d_xw,
+                        &mut d_hw,
+                        &mut dh_prev,
+                        &mut dc_prev,
+                    );
+
+                    accumulate_at_b(&mut gradients.w_ih.data, &d_xw, x, batch, rows, features);
+                    accumulate_at_b(
+                        &mut gradients.w_hh.data,
+                        &d_hw,
+                        &run.hs[s],
+                        batch,
+                        rows,
+                        hidden,
+                    );
+                    for n in 0..batch {
+                        for (b, g) in gradients
+                            .b_ih
+                            .data
+                            .iter_mut()
+                            .zip(&d_xw[n * rows..(n + 1) * rows])
+                        {
+                            *b += g;
+                        }
+                        for (b, g) in gradients
+                            .b_hh
+                            .data
+                            .iter_mut()
+                            .zip(&d_hw[n * rows..(n + 1) * rows])
+                        {
+                            *b += g;
+                        }
+                    }
+
+                    accumulate_a_b(
+                        &mut grad_input[t * batch * features..(t + 1) * batch * features],
+                        &d_xw,
+                        &weights.w_ih.data,
+                        batch,
+                        rows,
+                        features,
+                    );
+                    accumulate_a_b(&mut dh_prev, &d_hw, &weights.w_hh.data, batch, rows, hidden);
+
+                    dh_carry = dh_prev;
+                    dc_carry = dc_prev;
+                    if self.truncation.is_some_and(|k| s % k == 0) {
+                        dh_carry.fill(0.0);
+                        dc_carry.fill(0.0);
+                    }
+                }
+            }
+
+            grad_sequence = grad_input;
+        }
+
+        Tensor::from(self.input_shape.clone(), grad_sequence)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        self.weights
+            .iter_mut()
+            .flat_map(|w| [&mut w.w_ih, &mut w.w_hh, &mut w.b_ih, &mut w.b_hh])
+            .collect()
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.gradients
+            .iter()
+            .flat_map(|g| [&g.w_ih, &g.w_hh, &g.b_ih, &g.b_hh])
+            .collect()
+    }
+
+    //Samples are [T, F]
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.len() != 2 || input_shape[1] != self.input_size {
+            let expected = format!("[*, {}]", self.input_size);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        let out_features = self.hidden_size * self.directions();
+        if self.return_sequences {
+            Ok(vec![input_shape[0], out_features])
+        } else {
+            Ok(vec![out_features])
+        }
+    }
+
+    //Sequences stay time-major [T, N, F], the final states are batch-first [N, F]
+    fn batch_axis(&self, input_batch_axis: usize) -> usize {
+        if self.return_sequences {
+            input_batch_axis
+        } else {
+            0
+        }
+    }
+
+    //The two gate products of every step, layer and direction
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let rows = C::GATES * self.hidden_size;
+        let per_step: usize = self
+            .weights
+            .iter()
+            .map(|w| 2 * rows * (w.w_ih.shape[1] + self.hidden_size))
+            .sum();
+        input_shape[0] * per_step
+    }
+
+    fn name(&self) -> &str {
+        C::NAME
+    }
+}
+
+//--------------------------------------------------------------Cells---------------------------------------------------------------------
+
+//The per step math of a recurrent layer. Pre-activations arrive split into the input part
+//xw = W_ih x + b_ih and the recurrent part hw = W_hh h + b_hh, both [N, GATES * hidden],
+//and states are [N, hidden].
+pub trait Cell {
+    const NAME: &'static str;
+    const GATES: usize;
+    const HAS_CELL_STATE: bool;
+    //Values per hidden unit the cell caches for backward
+    const CACHE: usize;
+
+    #[allow(clippy::too_many_arguments)]
+    fn forward(
+        xw: &[f64],
+        hw: &[f64],
+        h_prev: &[f64],
+        c_prev: &[f64],
+        hidden: usize,
+        h: &mut [f64],
+        c: &mut [f64],
+        cache: &mut [f64],
+    );
+
+    //Turns dh (and dc) into the gradients of both pre-activation parts and of the previous
+    //states. dh_prev only receives the part that does not flow through hw.
+    #[allow(clippy::too_many_arguments)]
+    fn backward(
+        dh: &[f64],
+        dc: &[f64],
+        h: &[f64],
+        c: &[f64],
+        h_prev: &[f64],
+        c_prev: &[f64],
+        cache: &[f64],
+        hidden: usize,
+        d_xw: &mut [f64],
+        d_hw: &mut [f64],
+        dh_prev: &mut [f64],
+        dc_prev: &mut [f64],
+    );
+}
+
+pub struct RnnCell;
+
+impl Cell for RnnCell {
+    const NAME: &'static str = "RNN";
+    const GATES: usize = 1;
+    const HAS_CELL_STATE: bool = false;
+    const CACHE: usize = 0;
+
+    fn forward(
+        xw: &[f64],
+        hw: &[f64],
+        _h_prev: &[f64],
+        _c_prev: &[f64],
+        _hidden: usize,
+        h: &mut [f64],
+        _c: &mut [f64],
+        _cache: &mut [f64],
+    ) {
+        for ((h, x), r) in h.iter_mut().zip(xw).zip(hw) {
+            *h = (x + r).tanh();
+        }
+    }
+
+    fn backward(
+        dh: &[f64],
+        _dc: &[f64],
+        h: &[f64],
+        _c: &[f64],
+        _h_prev: &[f64],
+        _c_prev: &[f64],
+        _cache: &[f64],
+        _hidden: usize,
+        d_xw: &mut [f64],
+        d_hw: &mut [f64],
+        _dh_prev: &mut [f64],
+        _dc_prev: &mut [f64],
+    ) {
+        for i in 0..dh.len() {
+            d_xw[i] = dh[i] * (1.0 - h[i] * h[i]);
+            d_hw[i] = d_xw[i];
+        }
+    }
+}
+
+//Gates in the order input, forget, cell, output
+pub struct LstmCell;
+
+impl Cell for LstmCell {
+    const NAME: &'static str = "LSTM";
+    const GATES: usize = 4;
+    const HAS_CELL_STATE: bool = true;
+    //The activated gates
+    const CACHE: usize = 4;
+
+    fn forward(
+        xw: &[f64],
+        hw: &[f64],
+        _h_prev: &[f64],
+        c_prev: &[f64],
+        hidden: usize,
+        h: &mut [f64],
+        c: &mut [f64],
+        cache: &mut [f64],
+    ) {
+        for (n, (h, c)) in h.chunks_mut(hidden).zip(c.chunks_mut(hidden)).enumerate() {
+            let pre = |gate: usize, j: usize| {
+                let k = (n * 4 + gate) * hidden + j;
+                xw[k] + hw[k]
+            };
+            for j in 0..hidden {
+                let i = sigmoid(pre(0, j));
+                let f = sigmoid(pre(1, j));
+                let g = pre(2, j).tanh();
+                let o = sigmoid(pre(3, j));
+                c[j] = f * c_prev[n * hidden + j] + i * g;
+                h[j] = o * c[j].tanh();
+                for (gate, value) in [i, f, g, o].into_iter().enumerate() {
+                    cache[(n * 4 + gate) * hidden + j] = value;
+                }
+            }
+        }
+    }
+
+    fn backward(
+        dh: &[f64],
+        dc: &[f64],
+        _h: &[f64],
+        c: &[f64],
+        _h_prev: &[f64],
+        c_prev: &[f64],
+        cache: &[f64],
+        hidden: usize,
+        d_xw: &mut [f64],
+        d_hw: &mut [f64],
+        _dh_prev: &mut [f64],
+        dc_prev: &mut [f64],
+    ) {
+        for k in 0..dh.len() {
+            let (n, j) = (k / hidden, k % hidden);
+            let gate = |g: usize| (n * 4 + g) * hidden + j;
+            let (i, f, g, o) = (
+                cache[gate(0)],
+                cache[gate(1)],
+                cache[gate(2)],
+                cache[gate(3)],
+            );
+
+            let tanh_c = c[k].tanh();
+            let dc_total = dc[k] + dh[k] * o * (1.0 - tanh_c * tanh_c);
+            dc_prev[k] = dc_total * f;
+
+            let pre_gradients = [
+                dc_total * g * i * (1.0 - i),
+                dc_total * c_prev[k] * f * (1.0 - f),
+                dc_total * i * (1.0 - g * g),
+                dh[k] * tanh_c * o * (1.0 - o),
+            ];
+            for (index, d) in pre_gradients.into_iter().enumerate() {
+                d_xw[gate(index)] = d;
+                d_hw[gate(index)] = d;
+            }
+        }
+    }
+}
+
+//Gates in the order reset, update, new. The reset gate scales the recurrent part of the
+//new gate after its bias, n = tanh(xw_n + r * hw_n), and h = (1 - z) * n + z * h_prev.
+pub struct GruCell;
+
+impl Cell for GruCell {
+    const NAME: &'static str = "GRU";
+    const GATES: usize = 3;
+    const HAS_CELL_STATE: bool = false;
+    //r, z, n and hw_n
+    const CACHE: usize = 4;
+
+    fn forward(
+        xw: &[f64],
+        hw: &[f64],
+        h_prev: &[f64],
+        _c_prev: &[f64],
+        hidden: usize,
+        h: &mut [f64],
+        _c: &mut [f64],
+        cache: &mut [f64],
+    ) {
+        for k in 0..h.len() {
+            let (n, j) = (k / hidden, k % hidden);
+            let gate = |g: usize| (n * 3 + g) * hidden + j;
+            let r = sigmoid(xw[gate(0)] + hw[gate(0)]);
+            let z = sigmoid(xw[gate(1)] + hw[gate(1)]);
+            let hw_n = hw[gate(2)];
+            let new = (xw[gate(2)] + r * hw_n).tanh();
+            h[k] = (1.0 - z) * new + z * h_prev[k];
+            for (index, value) in [r, z, new, hw_n].into_iter().enumerate() {
+                cache[(n * 4 + index) * hidden + j] = value;
+            }
+        }
+    }
+
+    fn backward(
+        dh: &[f64],
+        _dc: &[f64],
+        _h: &[f64],
+        _c: &[f64],
+        h_prev: &[f64],
+        _c_prev: &[f64],
+        cache: &[f64],
+        hidden: usize,
+        d_xw: &mut [f64],
+        d_hw: &mut [f64],
+        dh_prev: &mut [f64],
+        _dc_prev: &mut [f64],
+    ) {
+        for k in 0..dh.len() {
+            let (n, j) = (k / hidden, k % hidden);
+            let cached = |index: usize| cache[(n * 4 + index) * hidden + j];
+            let (r, z, new, hw_n) = (cached(0), cached(1), cached(2), cached(3));
+            let gate = |g: usize| (n * 3 + g) * hidden + j;
+
+            dh_prev[k] = dh[k] * z;
+            let d_new = dh[k] * (1.0 - z) * (1.0 - new * new);
+            let d_r = d_new * hw_n * r * (1.0 - r);
+            let d_z = dh[k] * (h_prev[k] - new) * z * (1.0 - z);
+
+            d_xw[gate(0)] = d_r;
+            d_hw[gate(0)] = d_r;
+            d_xw[gate(1)] = d_z;
+            d_hw[gate(1)] = d_z;
+            d_xw[gate(2)] = d_new;
+            d_hw[gate(2)] = d_new * r;
+        }
+    }
+}
+
+//--------------------------------------------------------------Time Distributed---------------------------------------------------------------------
+
+//Applies a layer built for [features, batch] columns, like TensorLayer, to every step of a
+//[T, N, F] sequence by treating all T * N steps as one batch. Used for sequence taggers.
+pub struct TimeDistributed<L: Layer> {
+    pub layer: L,
+    input_shape: Vec<usize>,
+}
+
+impl<L: Layer> TimeDistributed<L> {
+    pub fn new(layer: L) -> TimeDistributed<L> {
+        TimeDistributed {
+            layer,
+            input_shape: Vec::new(),
+        }
+    }
+}
+
+impl<L: Layer> Layer for TimeDistributed<L> {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let (steps, batch) = (input.shape[0], input.shape[1]);
+        let features = input.data.len() / (steps * batch);
+        self.input_shape = input.shape.clone();
+
+        let columns = Tensor::from(vec![steps * batch, features], input.data.clone()).transpose();
+        let output = self.layer.forward(&columns).transpose();
+        let out_features = output.shape[1];
+        output.reshape(vec![steps, batch, out_features])
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let (steps, batch) = (self.input_shape[0], self.input_shape[1]);
+        let out_features = grad_output.data.len() / (steps * batch);
+
+        let columns = grad_output
+            .reshape(vec![steps * batch, out_features])
+            .transpose();
+        self.layer
+            .backward(&columns)
+            .transpose()
+            .reshape(self.input_shape.clone())
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        self.layer.parameters()
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.layer.gradients()
+    }
+
+    fn update(&mut self, learning_rate: f64) {
+        self.layer.update(learning_rate);
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.layer.set_training(training);
+    }
+
+    //Samples are [T, F]
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.is_empty() {
+            return Err(ShapeError::new(self.name(), "[T, *]", input_shape));
+        }
+        let mut shape = vec![input_shape[0]];
+        shape.extend(self.layer.output_shape(&input_shape[1..])?);
+        Ok(shape)
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape[0] * self.layer.flops(&input_shape[1..])
+    }
+
+    fn non_trainable_parameters(&self) -> usize {
+        self.layer.non_trainable_parameters()
+    }
+
+    fn name(&self) -> &str {
+        "TimeDistributed"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::{activations::TANH, dense::TensorLayer, gradient_check::gradient_check};
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(layer: &mut dyn Layer, input_shape: Vec<usize>) {
+        let error = gradient_check(layer, &Tensor::random(input_shape), 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            layer.name(),
+            error
+        );
+    }
+
+    #[test]
+    fn rnn_gradients() {
+        assert_gradients(&mut RNN::new(3, 4), vec![5, 2, 3]);
+    }
+
+    #[test]
+    fn lstm_gradients() {
+        assert_gradients(&mut LSTM::new(3, 4), vec![5, 2, 3]);
+        assert_gradients(&mut LSTM::new(3, 4).without_sequences(), vec![5, 2, 3]);
+    }
+
+    #[test]
+    fn gru_gradients() {
+        assert_gradients(&mut GRU::new(3, 4), vec![5, 2, 3]);
+        assert_gradients(&mut GRU::new(3, 4).without_sequences(), vec![5, 2, 3]);
+    }
+
+    #[test]
+    fn stacked_bidirectional_gradients() {
+        assert_gradients(
+            &mut LSTM::new(3, 2).with_num_layers(2).bidirectional(),
+            vec![4, 2, 3],
+        );
+        assert_gradients(
+            &mut GRU::new(3, 2)
+                .with_num_layers(2)
+                .bidirectional()
+                .without_sequences(),
+            vec![4, 2, 3],
+        );
+    }
+
+    #[test]
+    fn time_distributed_gradients() {
+        assert_gradients(
+            &mut TimeDistributed::new(TensorLayer::new(3, 4, TANH)),
+            vec![5, 2, 3],
+        );
+    }
+
+    #[test]
+    fn truncation_stops_the_gradient_before_the_window() {
+        let input = Tensor::random(vec![6, 2, 3]);
+        //Only the last step receives an upstream gradient
+        let mut upstream = Tensor::new(vec![6, 2, 4]);
+        upstream.data[5 * 8..].fill(1.0);
+
+        let mut truncated = LSTM::new(3, 4).with_truncation(2);
+        truncated.forward(&input);
+        let grad = truncated.backward(&upstream);
+        let step = |t: usize| &grad.data[t * 6..(t + 1) * 6];
+        for t in 0..4 {
+            assert!(
+                step(t).iter().all(|&g| g == 0.0),
+                "step {} got {:?}",
+                t,
+                step(t)
+            );
+        }
+        assert!(step(4).iter().any(|&g| g != 0.0));
+        assert!(step(5).iter().any(|&g| g != 0.0));
+
+        //Without truncation the error reaches the first step
+        let mut full = LSTM::new(3, 4);
+        full.forward(&input);
+        let grad = full.backward(&upstream);
+        assert!(grad.data[..6].iter().any(|&g| g != 0.0));
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+//Structured overview of a network for a given batch size, printed as a table by Display.
+//Shapes are those of a single sample; FLOPs and activation memory cover the whole batch.
+pub struct Summary {
+    pub batch_size: usize,
+    pub layers: Vec<LayerSummary>,
+}
+
+pub struct LayerSummary {
+    pub name: String,
+    pub output_shape: Vec<usize>,
+    pub trainable_parameters: usize,
+    pub non_trainable_parameters: usize,
+    //Estimated floating point operations of a forward pass over the batch
+    pub flops: usize,
+    //Bytes taken by the parameters and by the layer's output for the batch
+    pub memory: usize,
+}
+
+impl Summary {
+    pub fn trainable_parameters(&self) -> usize {
+        self.layers.iter().map(|l| l.trainable_parameters).sum()
+    }
+
+    pub fn non_trainable_parameters(&self) -> usize {
+        self.layers.iter().map(|l| l.non_trainable_parameters).sum()
+    }
+
+    pub fn total_parameters(&self) -> usize {
+        self.trainable_parameters() + self.non_trainable_parameters()
+    }
+
+    pub fn flops(&self) -> usize {
+        self.layers.iter().map(|l| l.flops).sum()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.layers.iter().map(|l| l.memory).sum()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows: Vec<[String; 6]> = self
+            .layers
+            .iter()
+            .map(|layer| {
+                [
+                    layer.name.clone(),
+                    format!("{:?}", layer.output_shape),
+                    layer.trainable_parameters.to_string(),
+                    layer.non_trainable_parameters.to_string(),
+                    format_count(layer.flops),
+                    format_bytes(layer.memory),
+                ]
+            })
+            .collect();
+        let header = [
+            "Layer",
+            "Output Shape",
+            "Trainable",
+            "Non-trainable",
+            "FLOPs",
+            "Memory",
+        ];
+
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let total_width = widths.iter().sum::<usize>() + 3 * (widths.len() - 1);
+
+        let write_row = |f: &mut fmt::Formatter, cells: &[&str]| -> fmt::Result {
+            let padded: Vec<String> = cells
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", padded.join("   ").trim_end())
+        };
+
+        writeln!(f, "Batch size: {}", self.batch_size)?;
+        writeln!(f, "{}", "=".repeat(total_width))?;
+        write_row(f, &header)?;
+        writeln!(f, "{}", "=".repeat(total_width))?;
+        for row in &rows {
+            write_row(f, &row.each_ref().map(String::as_str))?;
+        }
+        writeln!(f, "{}", "=".repeat(total_width))?;
+        writeln!(f, "Total params: {}", self.total_parameters())?;
+        writeln!(f, "Trainable params: {}", self.trainable_parameters())?;
+        writeln!(
+            f,
+            "Non-trainable params: {}",
+            self.non_trainable_parameters()
+        )?;
+        writeln!(f, "Total FLOPs: {}", format_count(self.flops()))?;
+        write!(f, "Total memory: {}", format_bytes(self.memory()))
+    }
+}
+
+//1234567 -> "1.23M"
+fn format_count(count: usize) -> String {
+    let units = [(1e12, "T"), (1e9, "G"), (1e6, "M"), (1e3, "K")];
+    for (size, unit) in units {
+        if count as f64 >= size {
+            return format!("{:.2}{}", count as f64 / size, unit);
+        }
+    }
+    count.to_string()
+}
+
+fn format_bytes(bytes: usize) -> String {
+    let units = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+    for (size, unit) in units {
+        if bytes >= size {
+            return format!("{:.2} {}", bytes as f64 / size as f64, unit);
+        }
+    }
+    format!("{} B", bytes)
+}
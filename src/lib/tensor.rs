@@ -1,12 +1,10 @@
-use std::thread::panicking;
-
 use rand::{thread_rng, Rng};
 //use std::default::Default;
 
 #[derive(Clone)]
-pub struct Tensor {
+pub struct Tensor<T = f64> {
     pub shape: Vec<usize>,
-    pub data: Vec<f64>,
+    pub data: Vec<T>,
 }
 
 impl Tensor {
@@ -15,16 +13,12 @@ impl Tensor {
     //[0.0, 1.0]
     //[2.0, 3.0]
     pub fn new(shape: Vec<usize>) -> Tensor {
-        let mut data = Vec::new();
         let mut count = 1;
-        let mut rng = thread_rng();
 
         for i in &shape {
             count *= i;
         }
-        for _ in 0..count {
-            data.push(0.0);
-        }
+        let data = vec![0.0; count];
 
         Tensor { shape, data }
     }
@@ -152,16 +146,14 @@ impl Tensor {
             "self shape: {:?} \n other shape: {:?}",
             self.shape, other.shape
         );*/
-        if (other_shape_len == 1 && self_shape_len > other_shape_len) {
-            let mut res = Tensor::new(vec![self.shape[0], other.shape[1]]);
+        if other_shape_len == 1 && self_shape_len > other_shape_len {
             panic!(
                 "TYPE 1, self shape: {:?} \n other shape: {:?}",
                 self.shape, other.shape
             );
-            return res;
         }
 
-        if (self_shape_len == 1 && self_shape_len < other_shape_len) {
+        if self_shape_len == 1 && self_shape_len < other_shape_len {
             panic!(
                 "TYPE 2, self shape: {:?} \n other shape: {:?}",
                 self.shape, other.shape
@@ -179,9 +171,7 @@ impl Tensor {
         // Check if both tensors are 2D
         if self_shape_len == 2
             && other_shape_len == 2
-            && (self.shape[1] == other.shape[0]
-                || self.shape[0] == self.shape[1]
-                || self.shape[0] == self.shape[0])
+            && (self.shape[1] == other.shape[0] || self.shape[0] == self.shape[1])
         {
             let res = self.matrix_multiply(other);
             //println!("shape: {:?} data: {:?}", res.shape, res.data);
@@ -197,21 +187,24 @@ impl Tensor {
     fn matrix_multiply(&self, other: &Tensor) -> Tensor {
         assert!(self.shape.len() == 2 && other.shape.len() == 2);
         assert!(
-            self.shape[1] == other.shape[0] || self.shape == other.shape, // || self.shape[0] == other.shape[0]
+            self.shape[1] == other.shape[0],
             "Incompatible shapes ({:?} and {:?})for matrix multiplication",
             self.shape,
             other.shape
         );
 
-        let mut res = Tensor::new(vec![self.shape[0], other.shape[1]]);
+        let (rows, inner, cols) = (self.shape[0], self.shape[1], other.shape[1]);
+        let mut res = Tensor::new(vec![rows, cols]);
 
-        for i in 0..self.shape[0] {
-            for j in 0..other.shape[1] {
-                let mut sum = 0.0;
-                for k in 0..self.shape[1] {
-                    sum += self.get(vec![i, k]) * other.get(vec![k, j]);
+        //i-k-j order walks both operands row by row, which keeps large GEMMs (im2col) cache friendly
+        for i in 0..rows {
+            let out_row = &mut res.data[i * cols..(i + 1) * cols];
+            for k in 0..inner {
+                let a = self.data[i * inner + k];
+                let other_row = &other.data[k * cols..(k + 1) * cols];
+                for (out, &b) in out_row.iter_mut().zip(other_row.iter()) {
+                    *out += a * b;
                 }
-                res.set(vec![i, j], sum);
             }
         }
 
@@ -245,10 +238,10 @@ impl Tensor {
             res.set(vec![1, 0], p3 + p4);
             res.set(vec![1, 1], p1 + p5 - p3 - p7);
 
-            return res;
+            res
         } else {
             // If not 2x2, use regular multiplication
-            return self.matrix_multiply(other); //
+            self.matrix_multiply(other)
         }
     }
 
@@ -266,7 +259,7 @@ impl Tensor {
         let mut res = Tensor::new(ten);
 
         for i in 0..self.data.len() {
-            res.data[i] = self.data[i] * self.data[i]
+            res.data[i] = self.data[i] * other.data[i]
         }
 
         res
@@ -280,13 +273,13 @@ impl Tensor {
 
     pub fn add(&mut self, other: &Tensor) {
         assert!(
-            self.shape == other.shape || other.shape.len() == 1 && other.shape[0] == self.shape[0],
+            self.shape == other.shape || other.is_column_of(self),
             "Shape mismatch for addition: {:?} and {:?}",
             self.shape,
             other.shape
         );
 
-        if other.shape.len() == 1 && self.shape.len() == 2 {
+        if self.shape.len() == 2 && other.is_column_of(self) {
             // Broadcasting the biases across the second dimension
             for i in 0..self.shape[0] {
                 for j in 0..self.shape[1] {
@@ -302,7 +295,7 @@ impl Tensor {
 
     pub fn subtract(&mut self, other: &Tensor) {
         assert!(
-            self.shape == other.shape || other.shape.len() == 1 && other.shape[0] == self.shape[0],
+            self.shape == other.shape || other.is_column_of(self),
             "Shape mismatch for subtraction: {:?} and {:?} with data {:?} and {:?}",
             self.shape,
             other.shape,
@@ -310,7 +303,7 @@ impl Tensor {
             other.data
         );
 
-        if other.shape.len() == 1 && self.shape.len() == 2 {
+        if self.shape.len() == 2 && other.is_column_of(self) {
             // Broadcasting the biases across the second dimension
             for i in 0..self.shape[0] {
                 for j in 0..self.shape[1] {
@@ -324,9 +317,445 @@ impl Tensor {
         }
     }
 
+    //True if self is a [n] or [n, 1] column that can be broadcast across every column of other
+    fn is_column_of(&self, other: &Tensor) -> bool {
+        !self.shape.is_empty()
+            && !other.shape.is_empty()
+            && self.shape[0] == other.shape[0]
+            && (self.shape.len() == 1 || self.shape.len() == 2 && self.shape[1] == 1)
+    }
+
+    //--------------------------------------------------------------Reductions---------------------------------------------------------------------
+
+    //Sum over the given axes. Reduced axes are kept with size 1 so the result
+    //lines up with the original tensor.
+    pub fn sum_axes(&self, axes: &[usize]) -> Tensor {
+        assert!(
+            axes.iter().all(|&a| a < self.shape.len()),
+            "Axes {:?} out of range for shape {:?}",
+            axes,
+            self.shape
+        );
+
+        let mut shape = self.shape.clone();
+        for &a in axes {
+            shape[a] = 1;
+        }
+        let mut res = Tensor::new(shape.clone());
+
+        for (i, &value) in self.data.iter().enumerate() {
+            res.data[reduced_offset(&unravel(i, &self.shape), &shape)] += value;
+        }
+
+        res
+    }
+
+    pub fn mean_axes(&self, axes: &[usize]) -> Tensor {
+        let count: usize = axes.iter().map(|&a| self.shape[a]).product();
+        let mut res = self.sum_axes(axes);
+        res.multiply_scalar(1.0 / count as f64)
+    }
+
+    //Biased (population) variance over the given axes, reduced axes kept with size 1
+    pub fn variance_axes(&self, axes: &[usize]) -> Tensor {
+        let mean = self.mean_axes(axes);
+        let mut centered = self.clone();
+        for (i, value) in centered.data.iter_mut().enumerate() {
+            let flat = reduced_offset(&unravel(i, &self.shape), &mean.shape);
+            *value = (*value - mean.data[flat]).powi(2);
+        }
+        centered.mean_axes(axes)
+    }
+
+    pub fn reshape(&self, shape: Vec<usize>) -> Tensor {
+        assert!(
+            shape.iter().product::<usize>() == self.data.len(),
+            "Cannot reshape {:?} into {:?}",
+            self.shape,
+            shape
+        );
+        Tensor::from(shape, self.data.clone())
+    }
+
+    //Rows [start, end) of a 2D tensor
+    pub fn rows(&self, start: usize, end: usize) -> Tensor {
+        assert!(self.shape.len() == 2, "rows only supports 2D tensors");
+        let cols = self.shape[1];
+        Tensor::from(
+            vec![end - start, cols],
+            self.data[start * cols..end * cols].to_vec(),
+        )
+    }
+
     pub fn increase_dim(&mut self, amt: usize) {
         for _i in 0..amt {
             self.shape.push(1);
         }
     }
+
+    //--------------------------------------------------------------Convolution Helpers---------------------------------------------------------------------
+
+    //Unfolds every (dilated) kernel window of a [N, C, *spatial] tensor into a column.
+    //The result is [C * prod(kernel), N * prod(out_spatial)] so a convolution of any
+    //dimensionality becomes a single matrix multiply. kernel, stride, padding and
+    //dilation hold one entry per spatial axis. Positions in the zero padding stay 0.0.
+    pub fn im2col(
+        &self,
+        kernel: &[usize],
+        stride: &[usize],
+        padding: &[usize],
+        dilation: &[usize],
+    ) -> Tensor {
+        let plan = UnfoldPlan::new(&self.shape, kernel, stride, padding, dilation);
+        let mut res = Tensor::new(vec![plan.rows, plan.cols]);
+
+        plan.for_each(|row, col, input| res.data[row * plan.cols + col] = self.data[input]);
+
+        res
+    }
+
+    //Inverse of im2col: folds columns back into a [N, C, *spatial] tensor, summing overlapping windows.
+    //This is exactly the gradient of im2col, which is what convolution backprop needs.
+    pub fn col2im(
+        &self,
+        input_shape: &[usize],
+        kernel: &[usize],
+        stride: &[usize],
+        padding: &[usize],
+        dilation: &[usize],
+    ) -> Tensor {
+        let plan = UnfoldPlan::new(input_shape, kernel, stride, padding, dilation);
+        assert!(
+            self.shape == vec![plan.rows, plan.cols],
+            "col2im expected columns of shape {:?}, got {:?}",
+            vec![plan.rows, plan.cols],
+            self.shape
+        );
+
+        let mut res = Tensor::new(input_shape.to_vec());
+
+        plan.for_each(|row, col, input| res.data[input] += self.data[row * plan.cols + col]);
+
+        res
+    }
+}
+
+//Index bookkeeping shared by im2col and col2im
+struct UnfoldPlan {
+    batch: usize,
+    channels: usize,
+    in_spatial: Vec<usize>,
+    out_spatial: Vec<usize>,
+    kernel: Vec<usize>,
+    //coordinates[d][o * kernel[d] + k] is the input coordinate along axis d of kernel tap k
+    //at output position o, or None if it falls in the padding
+    coordinates: Vec<Vec<Option<usize>>>,
+    rows: usize,
+    cols: usize,
+}
+
+impl UnfoldPlan {
+    fn new(
+        shape: &[usize],
+        kernel: &[usize],
+        stride: &[usize],
+        padding: &[usize],
+        dilation: &[usize],
+    ) -> UnfoldPlan {
+        let dims = kernel.len();
+        assert!(
+            shape.len() == dims + 2
+                && stride.len() == dims
+                && padding.len() == dims
+                && dilation.len() == dims,
+            "Unfolding {:?} needs one kernel, stride, padding and dilation entry per spatial axis",
+            shape
+        );
+
+        let in_spatial = shape[2..].to_vec();
+        let mut out_spatial = Vec::with_capacity(dims);
+        let mut coordinates = Vec::with_capacity(dims);
+
+        for d in 0..dims {
+            let out =
+                conv_output_size(in_spatial[d], kernel[d], stride[d], padding[d], dilation[d]);
+            let mut axis = Vec::with_capacity(out * kernel[d]);
+            for o in 0..out {
+                for k in 0..kernel[d] {
+                    let i = (o * stride[d] + k * dilation[d]) as isize - padding[d] as isize;
+                    axis.push(if i < 0 || i >= in_spatial[d] as isize {
+                        None
+                    } else {
+                        Some(i as usize)
+                    });
+                }
+            }
+            out_spatial.push(out);
+            coordinates.push(axis);
+        }
+
+        let taps: usize = kernel.iter().product();
+        let positions: usize = out_spatial.iter().product();
+
+        UnfoldPlan {
+            batch: shape[0],
+            channels: shape[1],
+            rows: shape[1] * taps,
+            cols: shape[0] * positions,
+            in_spatial,
+            out_spatial,
+            kernel: kernel.to_vec(),
+            coordinates,
+        }
+    }
+
+    //Calls f(row, col, input_offset) for every column entry that maps to a real (non padding) input
+    fn for_each(&self, mut f: impl FnMut(usize, usize, usize)) {
+        let taps: usize = self.kernel.iter().product();
+        let positions: usize = self.out_spatial.iter().product();
+        let in_size: usize = self.in_spatial.iter().product();
+
+        for tap in 0..taps {
+            let k = unravel(tap, &self.kernel);
+            for position in 0..positions {
+                let o = unravel(position, &self.out_spatial);
+
+                let mut offset = Some(0);
+                for d in 0..k.len() {
+                    offset = match (offset, self.coordinates[d][o[d] * self.kernel[d] + k[d]]) {
+                        (Some(flat), Some(i)) => Some(flat * self.in_spatial[d] + i),
+                        _ => None,
+                    };
+                }
+
+                if let Some(offset) = offset {
+                    for c in 0..self.channels {
+                        for b in 0..self.batch {
+                            f(
+                                c * taps + tap,
+                                b * positions + position,
+                                (b * self.channels + c) * in_size + offset,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+//Flat offset into a tensor with some axes reduced to size 1 (see sum_axes)
+//for the multi-index of an element of the unreduced tensor
+fn reduced_offset(index: &[usize], reduced_shape: &[usize]) -> usize {
+    index
+        .iter()
+        .zip(reduced_shape.iter())
+        .fold(0, |flat, (&i, &size)| {
+            flat * size + if size == 1 { 0 } else { i }
+        })
+}
+
+//Row-major multi-index of a flat offset into `shape`
+pub fn unravel(mut flat: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for d in (0..shape.len()).rev() {
+        index[d] = flat % shape[d];
+        flat /= shape[d];
+    }
+    index
+}
+
+//Number of positions a (dilated) kernel can take along one axis
+pub fn conv_output_size(
+    size: usize,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+) -> usize {
+    let span = dilation * (kernel - 1) + 1;
+    assert!(
+        size + 2 * padding >= span,
+        "Kernel span {} is larger than the padded input size {}",
+        span,
+        size + 2 * padding
+    );
+    (size + 2 * padding - span) / stride + 1
+}
+
+//Box-Muller transform, one N(0, 1) sample from two uniform ones
+pub fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+//--------------------------------------------------------------Slice Products---------------------------------------------------------------------
+
+//Row-major matrix products on flat buffers, for layers that keep their state outside of tensors
+
+//[rows] bias repeated for every sample, [batch, rows]
+pub(crate) fn broadcast_rows(bias: &[f64], batch: usize) -> Vec<f64> {
+    bias.repeat(batch)
+}
+
+//out[n, m] += sum_k a[n, k] * b[m, k]
+pub(crate) fn accumulate_a_bt(out: &mut [f64], a: &[f64], b: &[f64], n: usize, k: usize, m: usize) {
+    for i in 0..n {
+        let a_row = &a[i * k..(i + 1) * k];
+        for (j, o) in out[i * m..(i + 1) * m].iter_mut().enumerate() {
+            *o += a_row
+                .iter()
+                .zip(&b[j * k..(j + 1) * k])
+                .map(|(x, y)| x * y)
+                .sum::<f64>();
+        }
+    }
+}
+
+//out[k, m] += sum_n a[n, k] * b[n, m]
+pub(crate) fn accumulate_at_b(out: &mut [f64], a: &[f64], b: &[f64], n: usize, k: usize, m: usize) {
+    for i in 0..n {
+        let b_row = &b[i * m..(i + 1) * m];
+        for (j, &scale) in a[i * k..(i + 1) * k].iter().enumerate() {
+            for (o, y) in out[j * m..(j + 1) * m].iter_mut().zip(b_row) {
+                *o += scale * y;
+            }
+        }
+    }
+}
+
+//out[n, m] += sum_k a[n, k] * b[k, m]
+pub(crate) fn accumulate_a_b(out: &mut [f64], a: &[f64], b: &[f64], n: usize, k: usize, m: usize) {
+    for i in 0..n {
+        let out_row = &mut out[i * m..(i + 1) * m];
+        for (j, &scale) in a[i * k..(i + 1) * k].iter().enumerate() {
+            for (o, y) in out_row.iter_mut().zip(&b[j * m..(j + 1) * m]) {
+                *o += scale * y;
+            }
+        }
+    }
+}
+
+//x W^T + b for every row of x
+pub(crate) fn project(x: &[f64], weights: &Tensor, bias: &Tensor) -> Vec<f64> {
+    let (out, features) = (weights.shape[0], weights.shape[1]);
+    let rows = x.len() / features;
+    let mut res = broadcast_rows(&bias.data, rows);
+    accumulate_a_bt(&mut res, x, &weights.data, rows, features, out);
+    res
+}
+
+//Adds to the [weight, bias] gradients of project() and returns the gradient w.r.t. x
+pub(crate) fn project_backward(
+    grad: &[f64],
+    x: &[f64],
+    weights: &Tensor,
+    gradients: &mut [Tensor],
+) -> Vec<f64> {
+    let (out, features) = (weights.shape[0], weights.shape[1]);
+    let rows = x.len() / features;
+    accumulate_at_b(&mut gradients[0].data, grad, x, rows, out, features);
+    for row in grad.chunks(out) {
+        for (b, g) in gradients[1].data.iter_mut().zip(row) {
+            *b += g;
+        }
+    }
+    let mut grad_input = vec![0.0; x.len()];
+    accumulate_a_b(&mut grad_input, grad, &weights.data, rows, out, features);
+    grad_input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Direct 2-D unfolding with explicit loops, the layout im2col promises
+    fn reference_im2col(
+        input: &Tensor,
+        kernel: [usize; 2],
+        stride: usize,
+        padding: usize,
+        dilation: usize,
+    ) -> Tensor {
+        let (n, c, h, w) = (
+            input.shape[0],
+            input.shape[1],
+            input.shape[2],
+            input.shape[3],
+        );
+        let out_h = conv_output_size(h, kernel[0], stride, padding, dilation);
+        let out_w = conv_output_size(w, kernel[1], stride, padding, dilation);
+        let (rows, cols) = (c * kernel[0] * kernel[1], n * out_h * out_w);
+        let mut res = Tensor::new(vec![rows, cols]);
+
+        for b in 0..n {
+            for ch in 0..c {
+                for ki in 0..kernel[0] {
+                    for kj in 0..kernel[1] {
+                        for oh in 0..out_h {
+                            for ow in 0..out_w {
+                                let i = (oh * stride + ki * dilation) as isize - padding as isize;
+                                let j = (ow * stride + kj * dilation) as isize - padding as isize;
+                                if i < 0 || j < 0 || i >= h as isize || j >= w as isize {
+                                    continue;
+                                }
+                                let row = (ch * kernel[0] + ki) * kernel[1] + kj;
+                                let col = (b * out_h + oh) * out_w + ow;
+                                res.data[row * cols + col] =
+                                    input.get(vec![b, ch, i as usize, j as usize]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn im2col_matches_direct_2d_unfolding() {
+        let input = Tensor::random(vec![2, 3, 6, 5]);
+        for (stride, padding, dilation) in [(1, 0, 1), (2, 1, 1), (1, 2, 2), (2, 1, 2)] {
+            let columns = input.im2col(&[3, 2], &[stride; 2], &[padding; 2], &[dilation; 2]);
+            let expected = reference_im2col(&input, [3, 2], stride, padding, dilation);
+            assert_eq!(columns.shape, expected.shape);
+            assert_eq!(columns.data, expected.data);
+        }
+    }
+
+    #[test]
+    fn im2col_1d_matches_2d_with_unit_height() {
+        let input = Tensor::random(vec![2, 3, 9]);
+        let columns = input.im2col(&[3], &[2], &[1], &[2]);
+        let as_image = input
+            .reshape(vec![2, 3, 1, 9])
+            .im2col(&[1, 3], &[1, 2], &[0, 1], &[1, 2]);
+        assert_eq!(columns.shape, as_image.shape);
+        assert_eq!(columns.data, as_image.data);
+    }
+
+    #[test]
+    fn col2im_is_the_adjoint_of_im2col() {
+        let shape = [1, 2, 4, 5, 4];
+        let (kernel, stride, padding, dilation) = ([2; 3], [2; 3], [1; 3], [2; 3]);
+        let input = Tensor::random(shape.to_vec());
+        let columns = input.im2col(&kernel, &stride, &padding, &dilation);
+        let other = Tensor::random(columns.shape.clone());
+        let folded = other.col2im(&shape, &kernel, &stride, &padding, &dilation);
+
+        let lhs: f64 = columns
+            .data
+            .iter()
+            .zip(&other.data)
+            .map(|(a, b)| a * b)
+            .sum();
+        let rhs: f64 = input
+            .data
+            .iter()
+            .zip(&folded.data)
+            .map(|(a, b)| a * b)
+            .sum();
+        assert!((lhs - rhs).abs() < 1e-10, "{} != {}", lhs, rhs);
+    }
 }
@@ -0,0 +1,842 @@
+use super::{
+    attention::MultiHeadAttention,
+    dropout::Dropout,
+    layer::{Layer, ShapeError},
+    normalization::LayerNorm,
+    tensor::{project, project_backward, Tensor},
+};
+
+//Transformer building blocks over batch-first [N, L, E] sequences, E being d_model.
+//Blocks are post-norm by default, x = norm(x + dropout(sublayer(x))), as in the original
+//paper. Pre-norm blocks, x = x + dropout(sublayer(norm(x))), train more stably when deep.
+
+//--------------------------------------------------------------Feed Forward---------------------------------------------------------------------
+
+//Position-wise two layer network, relu(x W1^T + b1) W2^T + b2, applied to every position alone
+pub struct FeedForward {
+    //[hidden, E] and [hidden]
+    pub w1: Tensor,
+    pub b1: Tensor,
+    //[E, hidden] and [E]
+    pub w2: Tensor,
+    pub b2: Tensor,
+    input: Vec<f64>,
+    hidden: Vec<f64>,
+    //Gradients in the same order as parameters()
+    gradients: Vec<Tensor>,
+}
+
+impl FeedForward {
+    pub fn new(d_model: usize, hidden: usize) -> FeedForward {
+        let w1 =
+            Tensor::random(vec![hidden, d_model]).multiply_scalar(1.0 / (d_model as f64).sqrt());
+        let w2 =
+            Tensor::random(vec![d_model, hidden]).multiply_scalar(1.0 / (hidden as f64).sqrt());
+        FeedForward {
+            gradients: vec![
+                Tensor::new(w1.shape.clone()),
+                Tensor::new(vec![hidden]),
+                Tensor::new(w2.shape.clone()),
+                Tensor::new(vec![d_model]),
+            ],
+            w1,
+            b1: Tensor::new(vec![hidden]),
+            w2,
+            b2: Tensor::new(vec![d_model]),
+            input: Vec::new(),
+            hidden: Vec::new(),
+        }
+    }
+}
+
+impl Layer for FeedForward {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let d_model = self.w1.shape[1];
+        assert!(
+            input.shape.last() == Some(&d_model),
+            "FeedForward expects [..., {}], got {:?}",
+            d_model,
+            input.shape
+        );
+        self.input = input.data.clone();
+        self.hidden = project(&input.data, &self.w1, &self.b1);
+
+        let activated: Vec<f64> = self.hidden.iter().map(|x| x.max(0.0)).collect();
+        Tensor::from(input.shape.clone(), project(&activated, &self.w2, &self.b2))
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        for g in self.gradients.iter_mut() {
+            g.data.fill(0.0);
+        }
+        let activated: Vec<f64> = self.hidden.iter().map(|x| x.max(0.0)).collect();
+        let mut grad_hidden = project_backward(
+            &grad_output.data,
+            &activated,
+            &self.w2,
+            &mut self.gradients[2..4],
+        );
+        for (g, x) in grad_hidden.iter_mut().zip(&self.hidden) {
+            if *x <= 0.0 {
+                *g = 0.0;
+            }
+        }
+        let grad_input = project_backward(
+            &grad_hidden,
+            &self.input,
+            &self.w1,
+            &mut self.gradients[0..2],
+        );
+        Tensor::from(grad_output.shape.clone(), grad_input)
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.w1, &mut self.b1, &mut self.w2, &mut self.b2]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        self.gradients.iter().collect()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let d_model = self.w1.shape[1];
+        if input_shape.last() != Some(&d_model) {
+            let expected = format!("[*, {}]", d_model);
+            return Err(ShapeError::new(self.name(), expected, input_shape));
+        }
+        Ok(input_shape.to_vec())
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let (hidden, d_model) = (self.w1.shape[0], self.w1.shape[1]);
+        let positions = input_shape.iter().product::<usize>() / d_model;
+        4 * positions * d_model * hidden
+    }
+
+    fn name(&self) -> &str {
+        "FeedForward"
+    }
+}
+
+//--------------------------------------------------------------Encoder Layer---------------------------------------------------------------------
+
+//Self-attention followed by a feed-forward sublayer, each with dropout, a residual and a LayerNorm
+pub struct TransformerEncoderLayer {
+    pub self_attention: MultiHeadAttention,
+    pub feed_forward: FeedForward,
+    pub norm1: LayerNorm,
+    pub norm2: LayerNorm,
+    dropout1: Dropout,
+    dropout2: Dropout,
+    pre_norm: bool,
+}
+
+impl TransformerEncoderLayer {
+    //Post-norm without dropout
+    pub fn new(d_model: usize, heads: usize, dim_feedforward: usize) -> TransformerEncoderLayer {
+        TransformerEncoderLayer {
+            self_attention: MultiHeadAttention::new(d_model, heads),
+            feed_forward: FeedForward::new(d_model, dim_feedforward),
+            norm1: LayerNorm::new(vec![d_model]),
+            norm2: LayerNorm::new(vec![d_model]),
+            dropout1: Dropout::new(0.0),
+            dropout2: Dropout::new(0.0),
+            pre_norm: false,
+        }
+    }
+
+    pub fn with_pre_norm(mut self) -> TransformerEncoderLayer {
+        self.pre_norm = true;
+        self
+    }
+
+    //Dropout on the attention weights and on both sublayer outputs
+    pub fn with_dropout(mut self, p: f64) -> TransformerEncoderLayer {
+        self.self_attention = self.self_attention.with_dropout(p);
+        self.dropout1 = Dropout::new(p);
+        self.dropout2 = Dropout::new(p);
+        self
+    }
+
+    //Seeds every stochastic sublayer, call after with_dropout
+    pub fn with_seed(mut self, seed: u64) -> TransformerEncoderLayer {
+        self.self_attention = self.self_attention.with_seed(seed);
+        self.dropout1 = self.dropout1.with_seed(seed.wrapping_add(1));
+        self.dropout2 = self.dropout2.with_seed(seed.wrapping_add(2));
+        self
+    }
+
+    //[N, L] mask of padded positions for the next forward calls, see MultiHeadAttention
+    pub fn set_padding_mask(&mut self, mask: Option<Tensor>) {
+        self.self_attention.set_padding_mask(mask);
+    }
+}
+
+impl Layer for TransformerEncoderLayer {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let attention = &mut self.self_attention;
+        let x = residual(
+            input,
+            &mut self.norm1,
+            &mut self.dropout1,
+            self.pre_norm,
+            |x| attention.forward(x),
+        );
+        let feed_forward = &mut self.feed_forward;
+        residual(
+            &x,
+            &mut self.norm2,
+            &mut self.dropout2,
+            self.pre_norm,
+            |x| feed_forward.forward(x),
+        )
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let feed_forward = &mut self.feed_forward;
+        let grad = residual_backward(
+            grad_output,
+            &mut self.norm2,
+            &mut self.dropout2,
+            self.pre_norm,
+            |g| feed_forward.backward(g),
+        );
+        let attention = &mut self.self_attention;
+        residual_backward(
+            &grad,
+            &mut self.norm1,
+            &mut self.dropout1,
+            self.pre_norm,
+            |g| attention.backward(g),
+        )
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        let mut parameters = self.self_attention.parameters();
+        parameters.extend(self.norm1.parameters());
+        parameters.extend(self.feed_forward.parameters());
+        parameters.extend(self.norm2.parameters());
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        let mut gradients = self.self_attention.gradients();
+        gradients.extend(self.norm1.gradients());
+        gradients.extend(self.feed_forward.gradients());
+        gradients.extend(self.norm2.gradients());
+        gradients
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.self_attention.set_training(training);
+        self.dropout1.set_training(training);
+        self.dropout2.set_training(training);
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        self.self_attention
+            .output_shape(input_shape)
+            .map_err(|error| ShapeError::new(self.name(), error.expected, input_shape))
+    }
+
+    //Sublayers plus the residual additions
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let elements: usize = input_shape.iter().product();
+        self.self_attention.flops(input_shape)
+            + self.feed_forward.flops(input_shape)
+            + self.norm1.flops(input_shape)
+            + self.norm2.flops(input_shape)
+            + 2 * elements
+    }
+
+    fn name(&self) -> &str {
+        "TransformerEncoderLayer"
+    }
+}
+
+//--------------------------------------------------------------Decoder Layer---------------------------------------------------------------------
+
+//Causal self-attention, attention over the encoder output (the memory) and a feed-forward
+//sublayer. The memory is set before forward with set_memory and its gradient is available
+//after backward from memory_gradient.
+pub struct TransformerDecoderLayer {
+    pub self_attention: MultiHeadAttention,
+    pub cross_attention: MultiHeadAttention,
+    pub feed_forward: FeedForward,
+    pub norm1: LayerNorm,
+    pub norm2: LayerNorm,
+    pub norm3: LayerNorm,
+    dropout1: Dropout,
+    dropout2: Dropout,
+    dropout3: Dropout,
+    pre_norm: bool,
+    memory: Option<Tensor>,
+    memory_gradient: Tensor,
+}
+
+impl TransformerDecoderLayer {
+    //Post-norm without dropout
+    pub fn new(d_model: usize, heads: usize, dim_feedforward: usize) -> TransformerDecoderLayer {
+        TransformerDecoderLayer {
+            self_attention: MultiHeadAttention::new(d_model, heads).with_causal_mask(),
+            cross_attention: MultiHeadAttention::new(d_model, heads),
+            feed_forward: FeedForward::new(d_model, dim_feedforward),
+            norm1: LayerNorm::new(vec![d_model]),
+            norm2: LayerNorm::new(vec![d_model]),
+            norm3: LayerNorm::new(vec![d_model]),
+            dropout1: Dropout::new(0.0),
+            dropout2: Dropout::new(0.0),
+            dropout3: Dropout::new(0.0),
+            pre_norm: false,
+            memory: None,
+            memory_gradient: Tensor::new(Vec::new()),
+        }
+    }
+
+    pub fn with_pre_norm(mut self) -> TransformerDecoderLayer {
+        self.pre_norm = true;
+        self
+    }
+
+    //Dropout on both attention weights and on every sublayer output
+    pub fn with_dropout(mut self, p: f64) -> TransformerDecoderLayer {
+        self.self_attention = self.self_attention.with_dropout(p);
+        self.cross_attention = self.cross_attention.with_dropout(p);
+        self.dropout1 = Dropout::new(p);
+        self.dropout2 = Dropout::new(p);
+        self.dropout3 = Dropout::new(p);
+        self
+    }
+
+    //Seeds every stochastic sublayer, call after with_dropout
+    pub fn with_seed(mut self, seed: u64) -> TransformerDecoderLayer {
+        self.self_attention = self.self_attention.with_seed(seed);
+        self.cross_attention = self.cross_attention.with_seed(seed.wrapping_add(1));
+        self.dropout1 = self.dropout1.with_seed(seed.wrapping_add(2));
+        self.dropout2 = self.dropout2.with_seed(seed.wrapping_add(3));
+        self.dropout3 = self.dropout3.with_seed(seed.wrapping_add(4));
+        self
+    }
+
+    //Encoder output [N, S, E] the next forward calls attend to
+    pub fn set_memory(&mut self, memory: Option<Tensor>) {
+        self.memory = memory;
+    }
+
+    //[N, S] mask of padded memory positions for the next forward calls
+    pub fn set_memory_padding_mask(&mut self, mask: Option<Tensor>) {
+        self.cross_attention.set_padding_mask(mask);
+    }
+
+    //Gradient w.r.t. the memory from the last backward call, [N, S, E]
+    pub fn memory_gradient(&self) -> &Tensor {
+        &self.memory_gradient
+    }
+}
+
+impl Layer for TransformerDecoderLayer {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let memory = self
+            .memory
+            .as_ref()
+            .expect("TransformerDecoderLayer needs a memory, call set_memory first");
+
+        let attention = &mut self.self_attention;
+        let x = residual(
+            input,
+            &mut self.norm1,
+            &mut self.dropout1,
+            self.pre_norm,
+            |x| attention.forward(x),
+        );
+        let cross_attention = &mut self.cross_attention;
+        let x = residual(
+            &x,
+            &mut self.norm2,
+            &mut self.dropout2,
+            self.pre_norm,
+            |x| cross_attention.attend(x, memory, memory),
+        );
+        let feed_forward = &mut self.feed_forward;
+        residual(
+            &x,
+            &mut self.norm3,
+            &mut self.dropout3,
+            self.pre_norm,
+            |x| feed_forward.forward(x),
+        )
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let feed_forward = &mut self.feed_forward;
+        let grad = residual_backward(
+            grad_output,
+            &mut self.norm3,
+            &mut self.dropout3,
+            self.pre_norm,
+            |g| feed_forward.backward(g),
+        );
+
+        let cross_attention = &mut self.cross_attention;
+        let mut memory_gradient = Tensor::new(Vec::new());
+        let grad = residual_backward(
+            &grad,
+            &mut self.norm2,
+            &mut self.dropout2,
+            self.pre_norm,
+            |g| {
+                let (grad_query, mut grad_key, grad_value) = cross_attention.attend_backward(g);
+                grad_key.add(&grad_value);
+                memory_gradient = grad_key;
+                grad_query
+            },
+        );
+        self.memory_gradient = memory_gradient;
+
+        let attention = &mut self.self_attention;
+        residual_backward(
+            &grad,
+            &mut self.norm1,
+            &mut self.dropout1,
+            self.pre_norm,
+            |g| attention.backward(g),
+        )
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        let mut parameters = self.self_attention.parameters();
+        parameters.extend(self.norm1.parameters());
+        parameters.extend(self.cross_attention.parameters());
+        parameters.extend(self.norm2.parameters());
+        parameters.extend(self.feed_forward.parameters());
+        parameters.extend(self.norm3.parameters());
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        let mut gradients = self.self_attention.gradients();
+        gradients.extend(self.norm1.gradients());
+        gradients.extend(self.cross_attention.gradients());
+        gradients.extend(self.norm2.gradients());
+        gradients.extend(self.feed_forward.gradients());
+        gradients.extend(self.norm3.gradients());
+        gradients
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.self_attention.set_training(training);
+        self.cross_attention.set_training(training);
+        self.dropout1.set_training(training);
+        self.dropout2.set_training(training);
+        self.dropout3.set_training(training);
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        self.self_attention
+            .output_shape(input_shape)
+            .map_err(|error| ShapeError::new(self.name(), error.expected, input_shape))
+    }
+
+    //Sublayers plus the residual additions, counting the memory as long as the input
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let elements: usize = input_shape.iter().product();
+        self.self_attention.flops(input_shape)
+            + self.cross_attention.flops(input_shape)
+            + self.feed_forward.flops(input_shape)
+            + self.norm1.flops(input_shape)
+            + self.norm2.flops(input_shape)
+            + self.norm3.flops(input_shape)
+            + 3 * elements
+    }
+
+    fn name(&self) -> &str {
+        "TransformerDecoderLayer"
+    }
+}
+
+//--------------------------------------------------------------Residual Sublayers---------------------------------------------------------------------
+
+//One residual sublayer, post-norm or pre-norm
+fn residual(
+    input: &Tensor,
+    norm: &mut LayerNorm,
+    dropout: &mut Dropout,
+    pre_norm: bool,
+    sublayer: impl FnOnce(&Tensor) -> Tensor,
+) -> Tensor {
+    if pre_norm {
+        let mut res = dropout.forward(&sublayer(&norm.forward(input)));
+        res.add(input);
+        res
+    } else {
+        let mut res = dropout.forward(&sublayer(input));
+        res.add(input);
+        norm.forward(&res)
+    }
+}
+
+fn residual_backward(
+    grad_output: &Tensor,
+    norm: &mut LayerNorm,
+    dropout: &mut Dropout,
+    pre_norm: bool,
+    sublayer_backward: impl FnOnce(&Tensor) -> Tensor,
+) -> Tensor {
+    if pre_norm {
+        let mut res = norm.backward(&sublayer_backward(&dropout.backward(grad_output)));
+        res.add(grad_output);
+        res
+    } else {
+        let grad = norm.backward(grad_output);
+        let mut res = sublayer_backward(&dropout.backward(&grad));
+        res.add(&grad);
+        res
+    }
+}
+
+//--------------------------------------------------------------Encoder Stack---------------------------------------------------------------------
+
+//Encoder layers applied in order, optionally followed by a final LayerNorm
+//(usual with pre-norm layers, whose output is otherwise never normalized)
+pub struct TransformerEncoder {
+    pub layers: Vec<TransformerEncoderLayer>,
+    pub norm: Option<LayerNorm>,
+}
+
+impl TransformerEncoder {
+    pub fn new(layers: Vec<TransformerEncoderLayer>) -> TransformerEncoder {
+        assert!(
+            !layers.is_empty(),
+            "TransformerEncoder needs at least one layer"
+        );
+        TransformerEncoder { layers, norm: None }
+    }
+
+    pub fn with_final_norm(mut self, d_model: usize) -> TransformerEncoder {
+        self.norm = Some(LayerNorm::new(vec![d_model]));
+        self
+    }
+
+    //[N, L] mask of padded positions for every layer
+    pub fn set_padding_mask(&mut self, mask: Option<Tensor>) {
+        for layer in self.layers.iter_mut() {
+            layer.set_padding_mask(mask.clone());
+        }
+    }
+}
+
+impl Layer for TransformerEncoder {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        let mut x = input.clone();
+        for layer in self.layers.iter_mut() {
+            x = layer.forward(&x);
+        }
+        match self.norm.as_mut() {
+            Some(norm) => norm.forward(&x),
+            None => x,
+        }
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let mut grad = match self.norm.as_mut() {
+            Some(norm) => norm.backward(grad_output),
+            None => grad_output.clone(),
+        };
+        for layer in self.layers.iter_mut().rev() {
+            grad = layer.backward(&grad);
+        }
+        grad
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        let mut parameters: Vec<&mut Tensor> = self
+            .layers
+            .iter_mut()
+            .flat_map(|layer| layer.parameters())
+            .collect();
+        if let Some(norm) = self.norm.as_mut() {
+            parameters.extend(norm.parameters());
+        }
+        parameters
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        let mut gradients: Vec<&Tensor> = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.gradients())
+            .collect();
+        if let Some(norm) = self.norm.as_ref() {
+            gradients.extend(norm.gradients());
+        }
+        gradients
+    }
+
+    fn set_training(&mut self, training: bool) {
+        for layer in self.layers.iter_mut() {
+            layer.set_training(training);
+        }
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        let shape = self
+            .layers
+            .iter()
+            .try_fold(input_shape.to_vec(), |shape, layer| {
+                layer.output_shape(&shape)
+            })?;
+        match self.norm.as_ref() {
+            Some(norm) => norm.output_shape(&shape),
+            None => Ok(shape),
+        }
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        let layers: usize = self
+            .layers
+            .iter()
+            .map(|layer| layer.flops(input_shape))
+            .sum();
+        layers + self.norm.as_ref().map_or(0, |norm| norm.flops(input_shape))
+    }
+
+    fn name(&self) -> &str {
+        "TransformerEncoder"
+    }
+}
+
+//--------------------------------------------------------------Positional Encodings---------------------------------------------------------------------
+
+//Adds the fixed encoding PE[l, 2i] = sin(l / 10000^(2i / E)), PE[l, 2i + 1] = cos(...)
+//to every position l of a [N, L, E] sequence, for sequences up to max_len long
+pub struct SinusoidalPositionalEncoding {
+    pub encoding: Tensor,
+}
+
+impl SinusoidalPositionalEncoding {
+    pub fn new(max_len: usize, d_model: usize) -> SinusoidalPositionalEncoding {
+        let mut encoding = Tensor::new(vec![max_len, d_model]);
+        for l in 0..max_len {
+            for i in 0..d_model {
+                let frequency = 10000f64.powf(-((i - i % 2) as f64) / d_model as f64);
+                let angle = l as f64 * frequency;
+                encoding.data[l * d_model + i] = if i % 2 == 0 { angle.sin() } else { angle.cos() };
+            }
+        }
+        SinusoidalPositionalEncoding { encoding }
+    }
+}
+
+impl Layer for SinusoidalPositionalEncoding {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        add_positions(input, &self.encoding)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        grad_output.clone()
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        positions_shape(self.name(), &self.encoding, input_shape)
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn name(&self) -> &str {
+        "SinusoidalPositionalEncoding"
+    }
+}
+
+//A trainable [max_len, E] table added to every position of a [N, L, E] sequence
+pub struct LearnedPositionalEncoding {
+    pub encoding: Tensor,
+    encoding_gradient: Tensor,
+}
+
+impl LearnedPositionalEncoding {
+    pub fn new(max_len: usize, d_model: usize) -> LearnedPositionalEncoding {
+        LearnedPositionalEncoding {
+            encoding: Tensor::random(vec![max_len, d_model]).multiply_scalar(0.02),
+            encoding_gradient: Tensor::new(vec![max_len, d_model]),
+        }
+    }
+}
+
+impl Layer for LearnedPositionalEncoding {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        add_positions(input, &self.encoding)
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        //Every sample adds to the rows of the positions it covers
+        let position_values = grad_output.shape[1] * grad_output.shape[2];
+        self.encoding_gradient.data.fill(0.0);
+        for sample in grad_output.data.chunks(position_values) {
+            for (g, x) in self.encoding_gradient.data.iter_mut().zip(sample) {
+                *g += x;
+            }
+        }
+        grad_output.clone()
+    }
+
+    fn parameters(&mut self) -> Vec<&mut Tensor> {
+        vec![&mut self.encoding]
+    }
+
+    fn gradients(&self) -> Vec<&Tensor> {
+        vec![&self.encoding_gradient]
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        positions_shape(self.name(), &self.encoding, input_shape)
+    }
+
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        input_shape.iter().product()
+    }
+
+    fn name(&self) -> &str {
+        "LearnedPositionalEncoding"
+    }
+}
+
+fn add_positions(input: &Tensor, encoding: &Tensor) -> Tensor {
+    let (max_len, d_model) = (encoding.shape[0], encoding.shape[1]);
+    assert!(
+        input.shape.len() == 3 && input.shape[2] == d_model && input.shape[1] <= max_len,
+        "Positional encoding for up to [N, {}, {}] got {:?}",
+        max_len,
+        d_model,
+        input.shape
+    );
+    let position_values = input.shape[1] * d_model;
+    let mut res = input.clone();
+    for sample in res.data.chunks_mut(position_values) {
+        for (x, p) in sample.iter_mut().zip(&encoding.data) {
+            *x += p;
+        }
+    }
+    res
+}
+
+//Samples are [L, E] with L no longer than the encoding table
+fn positions_shape(
+    name: &str,
+    encoding: &Tensor,
+    input_shape: &[usize],
+) -> Result<Vec<usize>, ShapeError> {
+    let (max_len, d_model) = (encoding.shape[0], encoding.shape[1]);
+    if input_shape.len() != 2 || input_shape[0] > max_len || input_shape[1] != d_model {
+        let expected = format!("[<= {}, {}]", max_len, d_model);
+        return Err(ShapeError::new(name, expected, input_shape));
+    }
+    Ok(input_shape.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::{gradient_check, relative_error};
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn assert_gradients(layer: &mut dyn Layer, input_shape: Vec<usize>) {
+        let error = gradient_check(layer, &Tensor::random(input_shape), 1e-6);
+        assert!(
+            error < TOLERANCE,
+            "{} relative error {}",
+            layer.name(),
+            error
+        );
+    }
+
+    #[test]
+    fn encoder_layer_gradients() {
+        assert_gradients(&mut TransformerEncoderLayer::new(4, 2, 6), vec![2, 3, 4]);
+        assert_gradients(
+            &mut TransformerEncoderLayer::new(4, 2, 6).with_pre_norm(),
+            vec![2, 3, 4],
+        );
+    }
+
+    #[test]
+    fn encoder_stack_gradients() {
+        let layers = || {
+            vec![
+                TransformerEncoderLayer::new(4, 2, 6).with_pre_norm(),
+                TransformerEncoderLayer::new(4, 2, 6).with_pre_norm(),
+            ]
+        };
+        assert_gradients(&mut TransformerEncoder::new(layers()), vec![2, 3, 4]);
+        assert_gradients(
+            &mut TransformerEncoder::new(layers()).with_final_norm(4),
+            vec![2, 3, 4],
+        );
+    }
+
+    #[test]
+    fn decoder_layer_gradients() {
+        let mut decoder = TransformerDecoderLayer::new(4, 2, 6);
+        decoder.set_memory(Some(Tensor::random(vec![2, 5, 4])));
+        assert_gradients(&mut decoder, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn decoder_memory_gradient_matches_finite_differences() {
+        let epsilon = 1e-6;
+        let mut decoder = TransformerDecoderLayer::new(4, 2, 6).with_pre_norm();
+        let input = Tensor::random(vec![2, 3, 4]);
+        let memory = Tensor::random(vec![2, 5, 4]);
+
+        decoder.set_memory(Some(memory.clone()));
+        let output = decoder.forward(&input);
+        let upstream = Tensor::random(output.shape.clone());
+        decoder.backward(&upstream);
+        let analytic = decoder.memory_gradient().clone();
+        assert_eq!(analytic.shape, memory.shape);
+
+        let mut loss = |memory: &Tensor| -> f64 {
+            decoder.set_memory(Some(memory.clone()));
+            let output = decoder.forward(&input);
+            output
+                .data
+                .iter()
+                .zip(&upstream.data)
+                .map(|(o, u)| o * u)
+                .sum()
+        };
+        let mut perturbed = memory.clone();
+        for i in 0..memory.data.len() {
+            perturbed.data[i] = memory.data[i] + epsilon;
+            let plus = loss(&perturbed);
+            perturbed.data[i] = memory.data[i] - epsilon;
+            let minus = loss(&perturbed);
+            perturbed.data[i] = memory.data[i];
+
+            let numeric = (plus - minus) / (2.0 * epsilon);
+            let error = relative_error(analytic.data[i], numeric);
+            assert!(
+                error < TOLERANCE,
+                "memory element {} relative error {}",
+                i,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn learned_positional_encoding_gradients() {
+        assert_gradients(&mut LearnedPositionalEncoding::new(5, 4), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn sinusoidal_first_position_alternates_zero_and_one() {
+        let encoding = SinusoidalPositionalEncoding::new(4, 6);
+        assert_eq!(encoding.encoding.data[..6], [0.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+        //Second position, lowest frequency pair is sin(1), cos(1)
+        assert!((encoding.encoding.data[6] - 1f64.sin()).abs() < 1e-12);
+        assert!((encoding.encoding.data[7] - 1f64.cos()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,194 @@
+use super::{
+    layer::{Layer, ShapeError},
+    tensor::Tensor,
+};
+
+#[derive(Clone, Debug)]
+pub enum UpsampleMode {
+    Nearest,
+    //Half-pixel centers (align_corners = false), edges are clamped
+    Bilinear,
+}
+
+//Scales the two trailing spatial axes of [N, C, H, W] by an integer factor
+pub struct Upsample {
+    scale_factor: usize,
+    mode: UpsampleMode,
+    input_shape: Vec<usize>,
+}
+
+impl Upsample {
+    pub fn new(scale_factor: usize, mode: UpsampleMode) -> Upsample {
+        assert!(scale_factor > 0, "Scale factor must be positive");
+        Upsample {
+            scale_factor,
+            mode,
+            input_shape: Vec::new(),
+        }
+    }
+
+    //For every output coordinate along an axis, the input coordinates it reads and their weights.
+    //Both modes are separable, so H and W are handled independently.
+    fn taps(&self, size: usize) -> Vec<Vec<(usize, f64)>> {
+        let scale = self.scale_factor;
+        (0..size * scale)
+            .map(|o| match self.mode {
+                UpsampleMode::Nearest => vec![(o / scale, 1.0)],
+                UpsampleMode::Bilinear => {
+                    let source = ((o as f64 + 0.5) / scale as f64 - 0.5).max(0.0);
+                    let low = (source.floor() as usize).min(size - 1);
+                    let high = (low + 1).min(size - 1);
+                    let fraction = source - low as f64;
+                    vec![(low, 1.0 - fraction), (high, fraction)]
+                }
+            })
+            .collect()
+    }
+
+    fn check_shape(&self, shape: &[usize]) {
+        assert!(
+            shape.len() == 4,
+            "Upsample expects [N, C, H, W], got {:?}",
+            shape
+        );
+    }
+}
+
+impl Layer for Upsample {
+    fn forward(&mut self, input: &Tensor) -> Tensor {
+        self.check_shape(&input.shape);
+        let (h, w) = (input.shape[2], input.shape[3]);
+        let (rows, cols) = (self.taps(h), self.taps(w));
+        let (out_h, out_w) = (rows.len(), cols.len());
+
+        self.input_shape = input.shape.clone();
+        let mut res = Tensor::new(vec![input.shape[0], input.shape[1], out_h, out_w]);
+
+        for (plane, out) in input
+            .data
+            .chunks(h * w)
+            .zip(res.data.chunks_mut(out_h * out_w))
+        {
+            for (i, row_taps) in rows.iter().enumerate() {
+                for (j, col_taps) in cols.iter().enumerate() {
+                    let mut value = 0.0;
+                    for &(a, wa) in row_taps {
+                        for &(b, wb) in col_taps {
+                            value += wa * wb * plane[a * w + b];
+                        }
+                    }
+                    out[i * out_w + j] = value;
+                }
+            }
+        }
+
+        res
+    }
+
+    fn backward(&mut self, grad_output: &Tensor) -> Tensor {
+        let (h, w) = (self.input_shape[2], self.input_shape[3]);
+        let (rows, cols) = (self.taps(h), self.taps(w));
+        let (out_h, out_w) = (rows.len(), cols.len());
+
+        let mut grad_input = Tensor::new(self.input_shape.clone());
+
+        for (plane, grad) in grad_input
+            .data
+            .chunks_mut(h * w)
+            .zip(grad_output.data.chunks(out_h * out_w))
+        {
+            for (i, row_taps) in rows.iter().enumerate() {
+                for (j, col_taps) in cols.iter().enumerate() {
+                    let g = grad[i * out_w + j];
+                    for &(a, wa) in row_taps {
+                        for &(b, wb) in col_taps {
+                            plane[a * w + b] += wa * wb * g;
+                        }
+                    }
+                }
+            }
+        }
+
+        grad_input
+    }
+
+    fn output_shape(&self, input_shape: &[usize]) -> Result<Vec<usize>, ShapeError> {
+        if input_shape.len() != 3 {
+            return Err(ShapeError::new(self.name(), "[C, H, W]", input_shape));
+        }
+        Ok(vec![
+            input_shape[0],
+            input_shape[1] * self.scale_factor,
+            input_shape[2] * self.scale_factor,
+        ])
+    }
+
+    //Bilinear blends four inputs per output, nearest only copies
+    fn flops(&self, input_shape: &[usize]) -> usize {
+        match self.mode {
+            UpsampleMode::Nearest => 0,
+            UpsampleMode::Bilinear => {
+                let outputs: usize =
+                    input_shape.iter().product::<usize>() * self.scale_factor.pow(2);
+                8 * outputs
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self.mode {
+            UpsampleMode::Nearest => "UpsampleNearest",
+            UpsampleMode::Bilinear => "UpsampleBilinear",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::gradient_check::gradient_check;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    #[test]
+    fn upsample_gradients() {
+        for mode in [UpsampleMode::Nearest, UpsampleMode::Bilinear] {
+            for scale in [2, 3] {
+                let mut upsample = Upsample::new(scale, mode.clone());
+                let input = Tensor::random(vec![2, 2, 3, 4]);
+                let error = gradient_check(&mut upsample, &input, 1e-6);
+                assert!(
+                    error < TOLERANCE,
+                    "{} relative error {}",
+                    upsample.name(),
+                    error
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_repeats_pixels() {
+        let input = Tensor::from(vec![1, 1, 1, 2], vec![1.0, 2.0]);
+        let output = Upsample::new(2, UpsampleMode::Nearest).forward(&input);
+        assert_eq!(output.shape, vec![1, 1, 2, 4]);
+        assert_eq!(output.data, vec![1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 2.0, 2.0]);
+    }
+
+    //Matches torch.nn.functional.interpolate(mode="bilinear", align_corners=False)
+    #[test]
+    fn bilinear_uses_half_pixel_centres() {
+        let input = Tensor::from(vec![1, 1, 2, 2], vec![0.0, 1.0, 2.0, 3.0]);
+        let output = Upsample::new(2, UpsampleMode::Bilinear).forward(&input);
+        let expected = vec![
+            0.0, 0.25, 0.75, 1.0, //
+            0.5, 0.75, 1.25, 1.5, //
+            1.5, 1.75, 2.25, 2.5, //
+            2.0, 2.25, 2.75, 3.0,
+        ];
+        assert_eq!(output.shape, vec![1, 1, 4, 4]);
+        for (o, e) in output.data.iter().zip(&expected) {
+            assert!((o - e).abs() < 1e-12, "{:?} != {:?}", output.data, expected);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+#![allow(special_module_name)]
 use lib::{activations::SIGMOID, tensor::Tensor};
 use std::vec;
 pub mod lib;
@@ -8,7 +9,7 @@ fn main() {
     let now = Instant::now();
 
     let mut network = CPUTensorNetwork::new(2);
-    network.add_tensor_layer(3, SIGMOID); //Backprop currently can NOT handle diff dimensions
+    network.add_tensor_layer(3, SIGMOID);
     network.add_tensor_layer(3, SIGMOID);
 
     network.add_tensor_layer(3, SIGMOID);
@@ -35,7 +36,7 @@ fn main() {
     network.print_network();
 
     // //------------------------Training------------------------
-    for n in 0..input_arr.len() {
+    for (input, target) in input_arr.iter().zip(target_arr.iter()) {
         //does not seem to work if i train with more than two data points (two input -> output pairs)
         //might be due to model complexity being limited by the current dimension support.
         /*
@@ -44,18 +45,15 @@ fn main() {
         - ...idk
 
         */
-        network.train(input_arr[n].clone(), target_arr[n].clone(), 1000, 0.05);
+        network.train(input.clone(), target.clone(), 1000, 0.05);
     }
     //------------------------Printing Results----------------
     println!("-----------------BEFORE-------------------");
     println!("Output Tensor: {:?}", output_tensor.data); // Print the output Tensor
                                                          //output_tensor = network.feed_forward(input_arr[0].clone());
     println!("-----------------AFTER-------------------");
-    for n in 0..input_arr.len() {
-        println!(
-            "Output 1: {:?}",
-            network.feed_forward(input_arr[n].clone()).data
-        );
+    for input in &input_arr {
+        println!("Output 1: {:?}", network.feed_forward(input.clone()).data);
     }
     //println!("Output Tensor: {:?}", output_tensor.data); // Print the output Tensor
     println!("Elapsed time: {:.2?}", now.elapsed());